@@ -1,2 +0,0 @@
-// rename to api_key.rs
-pub const API_KEY: &str = "your-api-key";
\ No newline at end of file
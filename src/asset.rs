@@ -0,0 +1,239 @@
+use crate::media::MediaKind;
+use crate::metadata;
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+use walkdir::WalkDir;
+
+/// Stills that can be the photo half of a Live Photo
+const LIVE_PHOTO_STILL_EXTENSIONS: [&str; 4] = ["heic", "heif", "jpg", "jpeg"];
+/// Videos that can be the motion half of a Live Photo
+const LIVE_PHOTO_MOTION_EXTENSIONS: [&str; 1] = ["mov"];
+
+fn extension(path: &Path) -> String {
+    path.extension()
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .unwrap_or_default()
+}
+
+/// Whether a file is a photo or video rather than a sidecar
+pub fn is_media(path: &Path) -> bool {
+    MediaKind::of(path).is_some_and(MediaKind::is_media)
+}
+
+/// A photo or video as the user sees it: the primary file, the motion video
+/// of a Live Photo and any XMP sidecars. Operations that move, delete or
+/// trash files treat an asset as a unit so Live Photos are never split.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Asset {
+    /// The photo or video itself, or a sidecar whose media wasn't found
+    pub primary: PathBuf,
+    /// The video half of a Live Photo
+    pub motion: Option<PathBuf>,
+    pub sidecars: Vec<PathBuf>,
+}
+
+impl Asset {
+    pub fn new(primary: PathBuf) -> Asset {
+        Asset {
+            primary,
+            motion: None,
+            sidecars: Vec::new(),
+        }
+    }
+
+    /// Every file belonging to the asset, primary first
+    pub fn files(&self) -> Vec<&Path> {
+        let mut files = vec![self.primary.as_path()];
+        files.extend(self.motion.as_deref());
+        files.extend(self.sidecars.iter().map(PathBuf::as_path));
+        files
+    }
+
+    /// The photo and video files, leaving out sidecars
+    pub fn media_files(&self) -> Vec<&Path> {
+        self.files().into_iter().filter(|file| is_media(file)).collect()
+    }
+
+    pub fn is_live_photo(&self) -> bool {
+        self.motion.is_some()
+    }
+
+    pub fn contains(&self, path: &Path) -> bool {
+        self.files().contains(&path)
+    }
+}
+
+impl fmt::Display for Asset {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.primary.display())?;
+        if let Some(motion) = &self.motion {
+            write!(
+                f,
+                " + {} (Live Photo)",
+                motion.file_name().unwrap_or_default().to_string_lossy()
+            )?;
+        }
+        match self.sidecars.len() {
+            0 => Ok(()),
+            1 => write!(f, " + 1 sidecar"),
+            n => write!(f, " + {} sidecars", n),
+        }
+    }
+}
+
+/// Every asset under a directory, sorted by primary file
+pub fn scan_assets(dir: &Path) -> Vec<Asset> {
+    let files = WalkDir::new(dir)
+        .follow_links(true)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file() && MediaKind::of(entry.path()).is_some())
+        .map(|entry| entry.path().to_path_buf());
+    group_assets(files)
+}
+
+/// Group files into assets. Within a directory, a still and a MOV with the
+/// same basename form a Live Photo, as do a still and a MOV that carry the
+/// same Apple content identifier. `IMG_1.HEIC.xmp` and `IMG_1.xmp` are
+/// sidecars of `IMG_1.HEIC`. Files of unknown type are left out.
+pub fn group_assets(files: impl IntoIterator<Item = PathBuf>) -> Vec<Asset> {
+    let mut photos = Vec::new();
+    let mut videos = Vec::new();
+    let mut sidecars = Vec::new();
+    for file in files {
+        match MediaKind::of(&file) {
+            Some(MediaKind::Photo) => photos.push(file),
+            Some(MediaKind::Video) => videos.push(file),
+            Some(MediaKind::Sidecar) => sidecars.push(file),
+            None => {}
+        }
+    }
+    photos.sort();
+    videos.sort();
+    sidecars.sort();
+
+    let mut assets: Vec<Asset> = photos.into_iter().map(Asset::new).collect();
+
+    // Pair motion videos with stills of the same basename, then by content identifier
+    let mut unpaired_videos = Vec::new();
+    {
+        let mut stills: HashMap<(PathBuf, String), usize> = HashMap::new();
+        for (index, asset) in assets.iter().enumerate() {
+            if LIVE_PHOTO_STILL_EXTENSIONS.contains(&extension(&asset.primary).as_str()) {
+                stills.entry(stem_key(&asset.primary)).or_insert(index);
+            }
+        }
+
+        for video in videos {
+            let paired = LIVE_PHOTO_MOTION_EXTENSIONS.contains(&extension(&video).as_str())
+                && match stills.get(&stem_key(&video)) {
+                    Some(&index) if assets[index].motion.is_none() => {
+                        assets[index].motion = Some(video.clone());
+                        true
+                    }
+                    _ => false,
+                };
+            if !paired {
+                unpaired_videos.push(video);
+            }
+        }
+    }
+    pair_by_content_identifier(&mut assets, &mut unpaired_videos);
+    assets.extend(unpaired_videos.into_iter().map(Asset::new));
+
+    // Attach sidecars by full file name first (IMG_1.HEIC.xmp), then by basename (IMG_1.xmp)
+    let mut by_name: HashMap<(PathBuf, String), usize> = HashMap::new();
+    let mut by_stem: HashMap<(PathBuf, String), usize> = HashMap::new();
+    for (index, asset) in assets.iter().enumerate() {
+        for file in asset.media_files() {
+            by_name.entry(name_key(file)).or_insert(index);
+            by_stem.entry(stem_key(file)).or_insert(index);
+        }
+    }
+    for sidecar in sidecars {
+        let key = stem_key(&sidecar);
+        let owner = by_name.get(&key).or_else(|| by_stem.get(&key)).copied();
+        match owner {
+            Some(index) => assets[index].sidecars.push(sidecar),
+            None => assets.push(Asset::new(sidecar)),
+        }
+    }
+
+    assets.sort_by(|a, b| a.primary.cmp(&b.primary));
+    assets
+}
+
+/// Pair leftover MOVs with leftover stills in the same directory whose Apple
+/// content identifiers match, for Live Photos whose halves were renamed
+fn pair_by_content_identifier(assets: &mut [Asset], videos: &mut Vec<PathBuf>) {
+    let candidates: Vec<usize> = videos
+        .iter()
+        .enumerate()
+        .filter(|(_, video)| LIVE_PHOTO_MOTION_EXTENSIONS.contains(&extension(video).as_str()))
+        .map(|(index, _)| index)
+        .collect();
+    if candidates.is_empty() {
+        return;
+    }
+
+    // Only read stills that share a directory with a candidate video
+    let video_dirs: Vec<&Path> = candidates
+        .iter()
+        .filter_map(|&index| videos[index].parent())
+        .collect();
+    let mut stills: HashMap<(PathBuf, String), usize> = HashMap::new();
+    for (index, asset) in assets.iter().enumerate() {
+        let still = &asset.primary;
+        if asset.motion.is_some()
+            || !LIVE_PHOTO_STILL_EXTENSIONS.contains(&extension(still).as_str())
+            || !still.parent().is_some_and(|dir| video_dirs.contains(&dir))
+        {
+            continue;
+        }
+        if let Some(id) = metadata::content_identifier(still) {
+            stills.insert((parent(still), id), index);
+        }
+    }
+    if stills.is_empty() {
+        return;
+    }
+
+    let mut paired = Vec::new();
+    for index in candidates {
+        let video = &videos[index];
+        let Some(id) = metadata::content_identifier(video) else {
+            continue;
+        };
+        if let Some(still) = stills.remove(&(parent(video), id)) {
+            assets[still].motion = Some(video.clone());
+            paired.push(index);
+        }
+    }
+
+    for index in paired.into_iter().rev() {
+        videos.remove(index);
+    }
+}
+
+fn parent(path: &Path) -> PathBuf {
+    path.parent().map(Path::to_path_buf).unwrap_or_default()
+}
+
+/// Directory and lowercase basename without extension
+fn stem_key(path: &Path) -> (PathBuf, String) {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    (parent(path), stem)
+}
+
+/// Directory and lowercase file name
+fn name_key(path: &Path) -> (PathBuf, String) {
+    let name = path
+        .file_name()
+        .map(|name| name.to_string_lossy().to_lowercase())
+        .unwrap_or_default();
+    (parent(path), name)
+}
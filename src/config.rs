@@ -0,0 +1,803 @@
+use crate::constants;
+use crate::layout::{self, Layout};
+use crate::media::{MediaKind, MediaTypes};
+use crate::perceptual;
+use crate::secrets::ApiKeySource;
+use crate::BackupError;
+use std::collections::BTreeMap;
+use std::env;
+use std::fmt;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Where a resolved configuration value came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Source {
+    Default,
+    File(PathBuf),
+    Profile(PathBuf, String),
+    Env(&'static str),
+    Cli(&'static str),
+}
+
+impl fmt::Display for Source {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Source::Default => write!(f, "default"),
+            Source::File(path) => write!(f, "file {}", path.display()),
+            Source::Profile(path, name) => {
+                write!(f, "file {} [profiles.{}]", path.display(), name)
+            }
+            Source::Env(var) => write!(f, "env {}", var),
+            Source::Cli(flag) => write!(f, "flag {}", flag),
+        }
+    }
+}
+
+impl Source {
+    /// Position of the layer in the precedence order, higher wins
+    fn rank(&self) -> u8 {
+        match self {
+            Source::Default => 0,
+            Source::File(_) => 1,
+            Source::Profile(..) => 2,
+            Source::Env(_) => 3,
+            Source::Cli(_) => 4,
+        }
+    }
+}
+
+/// A configuration key and the name it goes by in each layer
+struct Key {
+    name: &'static str,
+    env: &'static str,
+    flag: Option<&'static str>,
+    default: Option<&'static str>,
+    secret: bool,
+}
+
+const KEYS: &[Key] = &[
+    Key {
+        name: "export_dir",
+        env: "APPLE_PHOTOS_EXPORT_DIR",
+        flag: Some("--export-dir"),
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "backup_dir",
+        env: "RAW_PHOTOS_BACKUP_DIR",
+        flag: Some("--backup-dir"),
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "immich_lib",
+        env: "IMMICH_LIB",
+        flag: Some("--immich-lib"),
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "immich_server",
+        env: "IMMICH_SERVER",
+        flag: Some("--immich-server"),
+        default: Some(constants::DEFAULT_IMMICH_SERVER),
+        secret: false,
+    },
+    Key {
+        name: "immich_docker_compose",
+        env: "IMMICH_DOCKER_COMPOSE",
+        flag: Some("--immich-docker-compose"),
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "jobs",
+        env: "BACKUP_PHOTOS_JOBS",
+        flag: Some("--jobs"),
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "backup_layout",
+        env: "BACKUP_PHOTOS_LAYOUT",
+        flag: Some("--layout"),
+        default: Some(layout::DEFAULT_LAYOUT),
+        secret: false,
+    },
+    Key {
+        name: "min_similarity",
+        env: "BACKUP_PHOTOS_MIN_SIMILARITY",
+        flag: Some("--min-similarity"),
+        default: Some(perceptual::DEFAULT_MIN_SIMILARITY),
+        secret: false,
+    },
+    Key {
+        name: "extra_photo_extensions",
+        env: "BACKUP_PHOTOS_EXTRA_PHOTO_EXTENSIONS",
+        flag: None,
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "extra_video_extensions",
+        env: "BACKUP_PHOTOS_EXTRA_VIDEO_EXTENSIONS",
+        flag: None,
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "extra_sidecar_extensions",
+        env: "BACKUP_PHOTOS_EXTRA_SIDECAR_EXTENSIONS",
+        flag: None,
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "immich_api_key",
+        env: "IMMICH_API_KEY",
+        flag: None,
+        default: None,
+        secret: true,
+    },
+    Key {
+        name: "immich_api_key_file",
+        env: "IMMICH_API_KEY_FILE",
+        flag: Some("--immich-api-key-file"),
+        default: None,
+        secret: false,
+    },
+    Key {
+        name: "immich_api_key_command",
+        env: "IMMICH_API_KEY_COMMAND",
+        flag: Some("--immich-api-key-command"),
+        default: None,
+        secret: false,
+    },
+];
+
+/// Keys that each name a way of getting the API key; only one may be in effect
+const API_KEY_KEYS: [&str; 3] = [
+    "immich_api_key",
+    "immich_api_key_file",
+    "immich_api_key_command",
+];
+
+/// Values given on the command line, which take precedence over every other layer
+#[derive(Debug, Default, Clone)]
+pub struct Overrides {
+    pub config_file: Option<PathBuf>,
+    pub profile: Option<String>,
+    pub export_dir: Option<PathBuf>,
+    pub backup_dir: Option<PathBuf>,
+    pub immich_lib: Option<PathBuf>,
+    pub immich_server: Option<String>,
+    pub immich_docker_compose: Option<PathBuf>,
+    pub immich_api_key_file: Option<PathBuf>,
+    pub immich_api_key_command: Option<String>,
+    pub jobs: Option<usize>,
+    pub layout: Option<String>,
+    pub min_similarity: Option<u8>,
+}
+
+impl Overrides {
+    fn get(&self, key: &str) -> Option<String> {
+        let path = |p: &Option<PathBuf>| p.as_ref().map(|p| p.to_string_lossy().to_string());
+        match key {
+            "export_dir" => path(&self.export_dir),
+            "backup_dir" => path(&self.backup_dir),
+            "immich_lib" => path(&self.immich_lib),
+            "immich_server" => self.immich_server.clone(),
+            "immich_docker_compose" => path(&self.immich_docker_compose),
+            "immich_api_key_file" => path(&self.immich_api_key_file),
+            "immich_api_key_command" => self.immich_api_key_command.clone(),
+            "jobs" => self.jobs.map(|jobs| jobs.to_string()),
+            "backup_layout" => self.layout.clone(),
+            "min_similarity" => self.min_similarity.map(|percent| percent.to_string()),
+            _ => None,
+        }
+    }
+}
+
+/// A raw value before validation, tagged with the layer that supplied it
+#[derive(Debug, Clone)]
+pub struct Setting {
+    pub value: String,
+    pub source: Source,
+}
+
+/// The parsed config file: shared top-level keys plus any named profiles
+#[derive(Debug, Default)]
+pub struct ConfigFile {
+    pub path: PathBuf,
+    pub found: bool,
+    pub default_profile: Option<String>,
+    base: Vec<(&'static str, String)>,
+    profiles: BTreeMap<String, Vec<(&'static str, String)>>,
+}
+
+/// All configuration layers for one profile merged together but not yet validated
+#[derive(Debug)]
+pub struct ConfigLayers {
+    pub config_file: PathBuf,
+    pub config_file_found: bool,
+    pub profile: String,
+    pub profile_source: Source,
+    settings: BTreeMap<&'static str, Setting>,
+}
+
+/// Fully resolved and validated settings for one photo library
+#[derive(Debug, Clone)]
+pub struct Profile {
+    pub name: String,
+    pub export_dir: PathBuf,
+    pub backup_dir: PathBuf,
+    pub immich_lib: PathBuf,
+    pub immich_server: String,
+    pub immich_docker_compose: Option<PathBuf>,
+    pub immich_api_key: Option<ApiKeySource>,
+    /// Worker threads for hashing and copying, or None for one per CPU
+    pub jobs: Option<usize>,
+    /// Where files go inside the backup directory
+    pub layout: Layout,
+    /// Extensions recognised as photos, videos and sidecars
+    pub media_types: MediaTypes,
+    /// How alike, in percent, a photo must look to one in Immich to count
+    /// as a near match
+    pub min_similarity: u8,
+}
+
+impl Profile {
+    /// Load the selected profile from defaults, the config file, the environment and the CLI
+    pub fn load(overrides: &Overrides) -> Result<Profile, BackupError> {
+        ConfigLayers::load(overrides)?.resolve()
+    }
+}
+
+/// Default location of the config file: $XDG_CONFIG_HOME/backup-photos/config.toml
+pub fn default_config_file() -> Result<PathBuf, BackupError> {
+    let config_home = match env::var_os("XDG_CONFIG_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => dirs::home_dir()
+            .ok_or_else(|| {
+                BackupError::ConfigError("Could not determine home directory".to_string())
+            })?
+            .join(".config"),
+    };
+
+    Ok(config_home
+        .join(constants::APP_NAME)
+        .join(constants::CONFIG_FILE_NAME))
+}
+
+impl ConfigFile {
+    /// Locate and parse the config file, which is allowed to be missing unless set explicitly
+    pub fn load(overrides: &Overrides) -> Result<ConfigFile, BackupError> {
+        let path = match (&overrides.config_file, env::var_os(constants::CONFIG_FILE_ENV)) {
+            (Some(path), _) => path.clone(),
+            (None, Some(path)) if !path.is_empty() => PathBuf::from(path),
+            _ => default_config_file()?,
+        };
+
+        if path.is_file() {
+            return read_config_file(&path);
+        }
+
+        if overrides.config_file.is_some() {
+            return Err(BackupError::ConfigError(format!(
+                "Config file not found: {}",
+                path.display()
+            )));
+        }
+
+        Ok(ConfigFile {
+            path,
+            ..Default::default()
+        })
+    }
+
+    /// Names of the profiles defined in the file, or the implicit default profile if none are
+    pub fn profile_names(&self) -> Vec<String> {
+        if self.profiles.is_empty() {
+            vec![constants::DEFAULT_PROFILE.to_string()]
+        } else {
+            self.profiles.keys().cloned().collect()
+        }
+    }
+
+    /// The profile to use: --profile, then BACKUP_PHOTOS_PROFILE, then default_profile
+    pub fn select_profile(&self, overrides: &Overrides) -> (String, Source) {
+        if let Some(name) = &overrides.profile {
+            return (name.clone(), Source::Cli("--profile"));
+        }
+
+        if let Ok(name) = env::var(constants::PROFILE_ENV) {
+            if !name.is_empty() {
+                return (name, Source::Env(constants::PROFILE_ENV));
+            }
+        }
+
+        match &self.default_profile {
+            Some(name) => (name.clone(), Source::File(self.path.clone())),
+            None => (constants::DEFAULT_PROFILE.to_string(), Source::Default),
+        }
+    }
+}
+
+impl ConfigLayers {
+    /// Read every layer for the selected profile
+    pub fn load(overrides: &Overrides) -> Result<ConfigLayers, BackupError> {
+        let file = ConfigFile::load(overrides)?;
+        let (profile, source) = file.select_profile(overrides);
+        ConfigLayers::for_profile(&file, &profile, source, overrides)
+    }
+
+    /// Merge the layers for a named profile in order of increasing precedence:
+    /// defaults, top-level file keys, the profile's table, environment, CLI
+    pub fn for_profile(
+        file: &ConfigFile,
+        profile: &str,
+        profile_source: Source,
+        overrides: &Overrides,
+    ) -> Result<ConfigLayers, BackupError> {
+        let profile_values = match file.profiles.get(profile) {
+            Some(values) => values.as_slice(),
+            None if file.profiles.is_empty() && profile == constants::DEFAULT_PROFILE => &[],
+            None => {
+                return Err(BackupError::ConfigError(format!(
+                    "Unknown profile '{}'. Profiles defined in {}: {}",
+                    profile,
+                    file.path.display(),
+                    file.profile_names().join(", ")
+                )))
+            }
+        };
+
+        let mut settings = BTreeMap::new();
+
+        for key in KEYS {
+            if let Some(default) = key.default {
+                settings.insert(
+                    key.name,
+                    Setting {
+                        value: default.to_string(),
+                        source: Source::Default,
+                    },
+                );
+            }
+        }
+
+        for (name, value) in &file.base {
+            settings.insert(
+                *name,
+                Setting {
+                    value: value.clone(),
+                    source: Source::File(file.path.clone()),
+                },
+            );
+        }
+
+        for (name, value) in profile_values {
+            settings.insert(
+                *name,
+                Setting {
+                    value: value.clone(),
+                    source: Source::Profile(file.path.clone(), profile.to_string()),
+                },
+            );
+        }
+
+        for key in KEYS {
+            if let Ok(value) = env::var(key.env) {
+                if !value.is_empty() {
+                    settings.insert(
+                        key.name,
+                        Setting {
+                            value,
+                            source: Source::Env(key.env),
+                        },
+                    );
+                }
+            }
+        }
+
+        for key in KEYS {
+            if let (Some(flag), Some(value)) = (key.flag, overrides.get(key.name)) {
+                settings.insert(
+                    key.name,
+                    Setting {
+                        value,
+                        source: Source::Cli(flag),
+                    },
+                );
+            }
+        }
+
+        Ok(ConfigLayers {
+            config_file: file.path.clone(),
+            config_file_found: file.found,
+            profile: profile.to_string(),
+            profile_source,
+            settings,
+        })
+    }
+
+    /// The raw value of a key and where it came from, if any layer set it
+    pub fn get(&self, key: &str) -> Option<&Setting> {
+        self.settings.get(key)
+    }
+
+    /// Names of every supported configuration key, in display order
+    pub fn keys() -> impl Iterator<Item = &'static str> {
+        KEYS.iter().map(|key| key.name)
+    }
+
+    /// Whether a key holds a secret that must never be printed
+    pub fn is_secret(key: &str) -> bool {
+        KEYS.iter().any(|k| k.name == key && k.secret)
+    }
+
+    /// Check a single key, returning the error `resolve` would report for it
+    pub fn validate(&self, key: &str) -> Result<(), BackupError> {
+        match key {
+            "immich_server" => self.server(key).map(|_| ()),
+            "immich_docker_compose" | "immich_api_key_file" => {
+                self.optional_path(key).map(|_| ())
+            }
+            "immich_api_key" | "immich_api_key_command" => self.api_key().map(|_| ()),
+            "jobs" => self.jobs().map(|_| ()),
+            "backup_layout" => self.layout().map(|_| ()),
+            "min_similarity" => self.min_similarity().map(|_| ()),
+            "extra_photo_extensions" | "extra_video_extensions" | "extra_sidecar_extensions" => {
+                self.media_types().map(|_| ())
+            }
+            _ => self.path(key).map(|_| ()),
+        }
+    }
+
+    /// Validate every key and build the resolved profile
+    pub fn resolve(&self) -> Result<Profile, BackupError> {
+        Ok(Profile {
+            name: self.profile.clone(),
+            export_dir: self.path("export_dir")?,
+            backup_dir: self.path("backup_dir")?,
+            immich_lib: self.path("immich_lib")?,
+            immich_server: self.server("immich_server")?,
+            immich_docker_compose: self.optional_path("immich_docker_compose")?,
+            immich_api_key: self.api_key()?,
+            jobs: self.jobs()?,
+            layout: self.layout()?,
+            media_types: self.media_types()?,
+            min_similarity: self.min_similarity()?,
+        })
+    }
+
+    fn required(&self, name: &str) -> Result<&Setting, BackupError> {
+        self.settings.get(name).ok_or_else(|| {
+            let key = KEYS.iter().find(|k| k.name == name);
+            BackupError::ConfigError(match key {
+                Some(Key {
+                    env,
+                    flag: Some(flag),
+                    ..
+                }) => format!(
+                    "{} is not set. Set it in {}, with the {} environment variable or with {}",
+                    name,
+                    self.config_file.display(),
+                    env,
+                    flag
+                ),
+                Some(key) => format!(
+                    "{} is not set. Set it in {} or with the {} environment variable",
+                    name,
+                    self.config_file.display(),
+                    key.env
+                ),
+                None => format!("{} is not set", name),
+            })
+        })
+    }
+
+    fn path(&self, name: &str) -> Result<PathBuf, BackupError> {
+        let setting = self.required(name)?;
+        let path = expand_home(&setting.value);
+
+        if !path.is_absolute() {
+            return Err(BackupError::ConfigError(format!(
+                "{} must be an absolute path, got '{}' (from {})",
+                name, setting.value, setting.source
+            )));
+        }
+
+        Ok(path)
+    }
+
+    fn optional_path(&self, name: &str) -> Result<Option<PathBuf>, BackupError> {
+        match self.settings.get(name) {
+            Some(_) => self.path(name).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    /// Pick the API key source from the highest-precedence layer that sets one
+    fn api_key(&self) -> Result<Option<ApiKeySource>, BackupError> {
+        let mut candidates: Vec<(&str, &Setting)> = API_KEY_KEYS
+            .iter()
+            .filter_map(|name| self.settings.get(name).map(|setting| (*name, setting)))
+            .collect();
+        candidates.sort_by_key(|(_, setting)| std::cmp::Reverse(setting.source.rank()));
+
+        let Some((name, setting)) = candidates.first() else {
+            return Ok(None);
+        };
+
+        if let Some((other, _)) = candidates
+            .get(1)
+            .filter(|(_, other)| other.source.rank() == setting.source.rank())
+        {
+            return Err(BackupError::ConfigError(format!(
+                "Both {} and {} are set (from {}); set only one way of loading the API key",
+                name, other, setting.source
+            )));
+        }
+
+        match *name {
+            "immich_api_key" => {
+                let file = match &setting.source {
+                    Source::File(path) | Source::Profile(path, _) => Some(path.clone()),
+                    _ => None,
+                };
+                Ok(Some(ApiKeySource::Value(setting.value.clone(), file)))
+            }
+            "immich_api_key_file" => Ok(Some(ApiKeySource::File(self.path(name)?))),
+            _ if setting.value.trim().is_empty() => Err(BackupError::ConfigError(format!(
+                "{} must not be empty (from {})",
+                name, setting.source
+            ))),
+            _ => Ok(Some(ApiKeySource::Command(setting.value.clone()))),
+        }
+    }
+
+    fn jobs(&self) -> Result<Option<usize>, BackupError> {
+        let Some(setting) = self.settings.get("jobs") else {
+            return Ok(None);
+        };
+
+        match setting.value.trim().parse::<usize>() {
+            Ok(jobs) if jobs > 0 => Ok(Some(jobs)),
+            _ => Err(BackupError::ConfigError(format!(
+                "jobs must be a positive number, got '{}' (from {})",
+                setting.value, setting.source
+            ))),
+        }
+    }
+
+    fn layout(&self) -> Result<Layout, BackupError> {
+        let setting = self.required("backup_layout")?;
+        Layout::parse(&setting.value).map_err(|e| {
+            BackupError::ConfigError(format!("backup_layout {} (from {})", e, setting.source))
+        })
+    }
+
+    fn min_similarity(&self) -> Result<u8, BackupError> {
+        let setting = self.required("min_similarity")?;
+        match setting.value.trim().trim_end_matches('%').parse::<u8>() {
+            Ok(percent) if (1..=100).contains(&percent) => Ok(percent),
+            _ => Err(BackupError::ConfigError(format!(
+                "min_similarity must be a percentage from 1 to 100, got '{}' (from {})",
+                setting.value, setting.source
+            ))),
+        }
+    }
+
+    /// The built-in media types plus the extensions listed, separated by
+    /// commas or spaces, in the extra_*_extensions keys
+    fn media_types(&self) -> Result<MediaTypes, BackupError> {
+        let mut types = MediaTypes::default();
+        for kind in MediaKind::ALL {
+            let Some(setting) = self.settings.get(kind.config_key()) else {
+                continue;
+            };
+            for ext in setting
+                .value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|ext| !ext.is_empty())
+            {
+                types.add(kind, ext).map_err(|e| {
+                    BackupError::ConfigError(format!(
+                        "{}: {} (from {})",
+                        kind.config_key(),
+                        e,
+                        setting.source
+                    ))
+                })?;
+            }
+        }
+        Ok(types)
+    }
+
+    fn server(&self, name: &str) -> Result<String, BackupError> {
+        let setting = self.required(name)?;
+        let url = setting.value.trim().trim_end_matches('/');
+
+        let host = url
+            .strip_prefix("http://")
+            .or_else(|| url.strip_prefix("https://"));
+        match host {
+            Some(host) if !host.is_empty() => Ok(url.to_string()),
+            _ => Err(BackupError::ConfigError(format!(
+                "{} must be an http:// or https:// URL, got '{}' (from {})",
+                name, setting.value, setting.source
+            ))),
+        }
+    }
+}
+
+/// Read the config file, rejecting unknown keys and values that aren't strings,
+/// numbers or booleans
+fn read_config_file(path: &Path) -> Result<ConfigFile, BackupError> {
+    let contents = fs::read_to_string(path)?;
+    let table: toml::Table = contents.parse().map_err(|e| {
+        BackupError::ConfigError(format!("Failed to parse {}: {}", path.display(), e))
+    })?;
+
+    let mut file = ConfigFile {
+        path: path.to_path_buf(),
+        found: true,
+        ..Default::default()
+    };
+
+    for (name, value) in table {
+        match (name.as_str(), value) {
+            ("default_profile", toml::Value::String(profile)) => {
+                file.default_profile = Some(profile)
+            }
+            ("profiles", toml::Value::Table(profiles)) => {
+                for (profile, value) in profiles {
+                    let toml::Value::Table(table) = value else {
+                        return Err(BackupError::ConfigError(format!(
+                            "profiles.{} in {} must be a table",
+                            profile,
+                            path.display()
+                        )));
+                    };
+                    let values = read_keys(table, &format!("profiles.{}.", profile), path)?;
+                    file.profiles.insert(profile, values);
+                }
+            }
+            (name, value) => {
+                let mut table = toml::Table::new();
+                table.insert(name.to_string(), value);
+                file.base.extend(read_keys(table, "", path)?);
+            }
+        }
+    }
+
+    if let Some(profile) = &file.default_profile {
+        if !file.profiles.contains_key(profile) {
+            return Err(BackupError::ConfigError(format!(
+                "default_profile '{}' in {} has no [profiles.{}] table",
+                profile,
+                path.display(),
+                profile
+            )));
+        }
+    }
+
+    Ok(file)
+}
+
+/// Validate the keys of one table against the known configuration keys
+fn read_keys(
+    table: toml::Table,
+    prefix: &str,
+    path: &Path,
+) -> Result<Vec<(&'static str, String)>, BackupError> {
+    let mut values = Vec::new();
+    for (name, value) in table {
+        let key = KEYS.iter().find(|k| k.name == name).ok_or_else(|| {
+            BackupError::ConfigError(format!(
+                "Unknown key '{}{}' in {}",
+                prefix,
+                name,
+                path.display()
+            ))
+        })?;
+
+        // Settings are checked by type when the profile is resolved, the same
+        // way whether they come from the file, the environment or a flag
+        match value {
+            toml::Value::String(s) => values.push((key.name, s)),
+            toml::Value::Integer(n) => values.push((key.name, n.to_string())),
+            toml::Value::Boolean(b) => values.push((key.name, b.to_string())),
+            other => {
+                return Err(BackupError::ConfigError(format!(
+                    "{}{} in {} must be a string, number or boolean, got {}",
+                    prefix,
+                    name,
+                    path.display(),
+                    other.type_str()
+                )))
+            }
+        }
+    }
+
+    Ok(values)
+}
+
+/// Expand a leading `~` to the user's home directory
+fn expand_home(value: &str) -> PathBuf {
+    if let Some(rest) = value.strip_prefix("~/") {
+        if let Some(home) = dirs::home_dir() {
+            return home.join(rest);
+        }
+    } else if value == "~" {
+        if let Some(home) = dirs::home_dir() {
+            return home;
+        }
+    }
+
+    PathBuf::from(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(contents: &str) -> Result<ConfigFile, BackupError> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, contents).unwrap();
+        read_config_file(&path)
+    }
+
+    #[test]
+    fn numbers_are_read_as_settings() {
+        let file = read(
+            "jobs = 4\nmin_similarity = 90\n\n[profiles.travel]\njobs = 2\nbackup_layout = \"flat\"\n",
+        )
+        .unwrap();
+
+        assert_eq!(file.base, vec![("jobs", "4".to_string()), ("min_similarity", "90".to_string())]);
+        assert_eq!(
+            file.profiles["travel"],
+            vec![("backup_layout", "flat".to_string()), ("jobs", "2".to_string())]
+        );
+    }
+
+    #[test]
+    fn tables_and_arrays_are_rejected() {
+        let error = read("jobs = [4]\n").unwrap_err().to_string();
+        assert!(error.contains("jobs in"), "{}", error);
+        assert!(error.contains("must be a string, number or boolean, got array"), "{}", error);
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let error = read("[profiles.travel]\njbos = 4\n").unwrap_err().to_string();
+        assert!(error.contains("Unknown key 'profiles.travel.jbos'"), "{}", error);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn literal_key_needs_a_private_config_file() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("config.toml");
+        fs::write(&path, "[profiles.home]\nimmich_api_key = \"secret\"\n").unwrap();
+        let file = read_config_file(&path).unwrap();
+        let overrides = Overrides::default();
+        let layers = ConfigLayers::for_profile(&file, "home", Source::Default, &overrides).unwrap();
+        let source = layers.api_key().unwrap().unwrap();
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o644)).unwrap();
+        let error = source.load().unwrap_err().to_string();
+        assert!(error.contains("has permissions 644"), "{}", error);
+        assert!(error.contains("chmod 600"), "{}", error);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(source.load().unwrap().expose(), "secret");
+    }
+}
@@ -1,5 +1,30 @@
-pub const APPLE_PHOTOS_EXPORT_DIR: &str = "/Users/levirogalla/Pictures/export";
-pub const RAW_PHOTOS_BACKUP_DIR: &str = "/Users/levirogalla/Pictures/backup";
-pub const IMMICH_LIB: &str = "/Users/levirogalla/Pictures/immich/library";
-pub const IMMICH_SERVER: &str = "http://localhost:2283";
-pub const IMMICH_DOCKER_COMPOSE: &str = "/Users/levirogalla/Pictures/immich/docker-compose.yml";
\ No newline at end of file
+/// Name used for the config, cache and data directories
+pub const APP_NAME: &str = "backup-photos";
+
+/// File name of the TOML config file inside the config directory
+pub const CONFIG_FILE_NAME: &str = "config.toml";
+
+/// Environment variable that points at an alternative config file
+pub const CONFIG_FILE_ENV: &str = "BACKUP_PHOTOS_CONFIG";
+
+/// Immich server used when no other layer sets one
+pub const DEFAULT_IMMICH_SERVER: &str = "http://localhost:2283";
+
+/// Environment variable that selects the profile to use
+pub const PROFILE_ENV: &str = "BACKUP_PHOTOS_PROFILE";
+
+/// Profile used when none is selected and the config file doesn't name a default
+pub const DEFAULT_PROFILE: &str = "default";
+
+/// File name of the content hash cache inside the cache directory
+pub const HASH_CACHE_FILE_NAME: &str = "hashes.json";
+
+/// Directory inside the backup root that holds the manifest and other tool state
+pub const STATE_DIR_NAME: &str = ".backup-photos";
+
+/// File name of the backup manifest inside the state directory
+pub const MANIFEST_FILE_NAME: &str = "manifest.jsonl";
+
+/// Directory inside the state directory that holds the journals of changes
+/// made to the backup, such as dedupe runs
+pub const JOURNAL_DIR_NAME: &str = "journal";
@@ -0,0 +1,394 @@
+use crate::BackupError;
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, info, warn};
+use rayon::prelude::*;
+use std::collections::HashSet;
+use std::fs::{self, File, FileTimes};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use walkdir::WalkDir;
+
+/// A single file to copy from the export directory into the backup
+#[derive(Debug, Clone)]
+pub struct CopyJob {
+    pub source: PathBuf,
+    pub destination: PathBuf,
+    pub size: u64,
+    /// The different file already at the name the source would normally get,
+    /// when that forced it under a new name
+    pub collision: Option<PathBuf>,
+}
+
+/// What happened to every file in a copy run
+#[derive(Debug, Default)]
+pub struct CopySummary {
+    pub copied: Vec<PathBuf>,
+    pub skipped: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+    pub bytes_copied: u64,
+    /// Copied files that got a new name because a different file had theirs
+    pub collisions: Vec<CopyJob>,
+}
+
+/// Numbers the temporary files of this process
+static NEXT_TEMP: AtomicUsize = AtomicUsize::new(0);
+
+enum Outcome {
+    Copied(u64),
+    Skipped,
+    Failed(String),
+}
+
+/// Find every file under `source_root` accepted by `include` and map it to
+/// the same relative path under `destination_root`
+pub fn plan_copy(
+    source_root: &Path,
+    destination_root: &Path,
+    include: impl Fn(&Path) -> bool,
+) -> Result<Vec<CopyJob>, BackupError> {
+    let mut jobs = Vec::new();
+
+    for entry in WalkDir::new(source_root)
+        .follow_links(true)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_map(|e| e.ok())
+    {
+        if !entry.file_type().is_file() {
+            continue;
+        }
+
+        if !include(entry.path()) {
+            continue;
+        }
+
+        let relative = entry
+            .path()
+            .strip_prefix(source_root)
+            .unwrap_or(entry.path());
+
+        jobs.push(CopyJob {
+            source: entry.path().to_path_buf(),
+            destination: destination_root.join(relative),
+            size: entry.metadata().map(|m| m.len()).unwrap_or(0),
+            collision: None,
+        });
+    }
+
+    Ok(jobs)
+}
+
+/// Copy every job in parallel, skipping destinations that already exist or
+/// that an earlier job in the list copies to, which the plan only allows for
+/// identical files. The progress bar advances by bytes and its message
+/// tracks the file count.
+pub fn copy_files(jobs: &[CopyJob]) -> CopySummary {
+    let repeated = repeated_destinations(jobs);
+    let total_bytes = jobs.iter().map(|job| job.size).sum();
+    let progress = ProgressBar::new(total_bytes);
+    progress.set_style(
+        ProgressStyle::default_bar()
+            .template(
+                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {bytes}/{total_bytes} ({binary_bytes_per_sec}, {eta}) {msg}",
+            )
+            .unwrap_or_else(|_| ProgressStyle::default_bar())
+            .progress_chars("#>-"),
+    );
+
+    let files_done = AtomicUsize::new(0);
+    progress.set_message(format!("0/{} files", jobs.len()));
+
+    let outcomes: Vec<Outcome> = jobs
+        .par_iter()
+        .zip(&repeated)
+        .map(|(job, repeated)| {
+            let outcome = if *repeated || job.destination.exists() {
+                debug!("Skipping existing file {}", job.destination.display());
+                progress.inc(job.size);
+                Outcome::Skipped
+            } else {
+                match copy_file_atomic(&job.source, &job.destination, &progress) {
+                    Ok(bytes) => Outcome::Copied(bytes),
+                    Err(e) => {
+                        warn!("Failed to copy {}: {}", job.source.display(), e);
+                        Outcome::Failed(e.to_string())
+                    }
+                }
+            };
+
+            let done = files_done.fetch_add(1, Ordering::Relaxed) + 1;
+            progress.set_message(format!("{}/{} files", done, jobs.len()));
+            outcome
+        })
+        .collect();
+
+    progress.finish_with_message(format!("{} files processed", jobs.len()));
+
+    let mut summary = CopySummary::default();
+    for (job, outcome) in jobs.iter().zip(outcomes) {
+        match outcome {
+            Outcome::Copied(bytes) => {
+                summary.bytes_copied += bytes;
+                summary.copied.push(job.source.clone());
+                if job.collision.is_some() {
+                    summary.collisions.push(job.clone());
+                }
+            }
+            Outcome::Skipped => summary.skipped.push(job.source.clone()),
+            Outcome::Failed(e) => summary.failed.push((job.source.clone(), e)),
+        }
+    }
+
+    summary
+}
+
+/// Log what `copy_files` would do without touching anything. The summary
+/// lists the files that would be copied and the ones that would be skipped.
+pub fn print_plan(jobs: &[CopyJob]) -> CopySummary {
+    let mut summary = CopySummary::default();
+    for (job, repeated) in jobs.iter().zip(repeated_destinations(jobs)) {
+        if repeated || job.destination.exists() {
+            info!("[dry run] Would skip existing {}", job.destination.display());
+            summary.skipped.push(job.source.clone());
+        } else {
+            info!(
+                "[dry run] Would copy {} -> {}",
+                job.source.display(),
+                job.destination.display()
+            );
+            summary.bytes_copied += job.size;
+            summary.copied.push(job.source.clone());
+            if job.collision.is_some() {
+                summary.collisions.push(job.clone());
+            }
+        }
+    }
+    summary
+}
+
+/// For each job, whether an earlier job copies to the same destination
+fn repeated_destinations(jobs: &[CopyJob]) -> Vec<bool> {
+    let mut seen = HashSet::new();
+    jobs.iter()
+        .map(|job| !seen.insert(job.destination.as_path()))
+        .collect()
+}
+
+/// Copy a file into a temporary sibling of the destination, carry over its
+/// permissions and timestamps, then publish it under the final name so a
+/// partially written file never appears there. Publishing never replaces a
+/// file that appeared at the destination in the meantime: that fails with
+/// `AlreadyExists`.
+pub fn copy_file_atomic(
+    source: &Path,
+    destination: &Path,
+    progress: &ProgressBar,
+) -> io::Result<u64> {
+    copy_via_temp(source, destination, progress, false)
+}
+
+/// Like `copy_file_atomic`, but the copy deliberately takes the place of
+/// whatever is at the destination, in one rename
+pub fn replace_file_atomic(
+    source: &Path,
+    destination: &Path,
+    progress: &ProgressBar,
+) -> io::Result<u64> {
+    copy_via_temp(source, destination, progress, true)
+}
+
+fn copy_via_temp(
+    source: &Path,
+    destination: &Path,
+    progress: &ProgressBar,
+    replace: bool,
+) -> io::Result<u64> {
+    let parent = destination.parent().ok_or_else(|| {
+        io::Error::other(format!("{} has no parent directory", destination.display()))
+    })?;
+    fs::create_dir_all(parent)?;
+
+    let file_name = destination
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    // Unique per copy, as parallel jobs may share a destination directory and name
+    let temp = parent.join(format!(
+        ".{}.{}.{}.tmp",
+        file_name,
+        std::process::id(),
+        NEXT_TEMP.fetch_add(1, Ordering::Relaxed)
+    ));
+
+    let result = write_copy(source, &temp, progress).and_then(|bytes| {
+        if replace {
+            fs::rename(&temp, destination)?;
+        } else {
+            publish(&temp, destination)?;
+        }
+        Ok(bytes)
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+
+    result
+}
+
+/// Give the finished temporary file its final name without overwriting
+/// anything. A hard link fails if the name is taken; on filesystems without
+/// hard links, such as exFAT, the name is checked right before a rename.
+fn publish(temp: &Path, destination: &Path) -> io::Result<()> {
+    match fs::hard_link(temp, destination) {
+        Ok(()) => {
+            if let Err(e) = fs::remove_file(temp) {
+                warn!("Could not remove {}: {}", temp.display(), e);
+            }
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Err(already_exists(destination)),
+        Err(e) => {
+            debug!("Can't hard link {}, renaming instead: {}", temp.display(), e);
+            if fs::symlink_metadata(destination).is_ok() {
+                return Err(already_exists(destination));
+            }
+            fs::rename(temp, destination)
+        }
+    }
+}
+
+fn already_exists(destination: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::AlreadyExists,
+        format!("{} appeared while it was being copied", destination.display()),
+    )
+}
+
+fn write_copy(source: &Path, temp: &Path, progress: &ProgressBar) -> io::Result<u64> {
+    let metadata = fs::metadata(source)?;
+    let mut reader = File::open(source)?;
+    let mut writer = File::create(temp)?;
+    let mut buffer = vec![0; 1024 * 1024];
+    let mut bytes = 0;
+
+    loop {
+        let bytes_read = reader.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        writer.write_all(&buffer[..bytes_read])?;
+        bytes += bytes_read as u64;
+        progress.inc(bytes_read as u64);
+    }
+
+    writer.sync_all()?;
+
+    let mut times = FileTimes::new();
+    if let Ok(accessed) = metadata.accessed() {
+        times = times.set_accessed(accessed);
+    }
+    if let Ok(modified) = metadata.modified() {
+        times = times.set_modified(modified);
+    }
+    writer.set_times(times)?;
+    fs::set_permissions(temp, metadata.permissions())?;
+
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Names in a directory, hidden ones included
+    fn names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn copies_then_skips_what_is_there() {
+        let dir = tempfile::tempdir().unwrap();
+        let (export, backup) = (dir.path().join("export"), dir.path().join("backup"));
+        write(&export.join("IMG_1.jpg"), "one");
+        write(&export.join("2024/IMG_2.jpg"), "two");
+        let modified = fs::metadata(export.join("IMG_1.jpg")).unwrap().modified().unwrap();
+
+        let jobs = plan_copy(&export, &backup, |_| true).unwrap();
+        assert_eq!(jobs[0].destination, backup.join("2024/IMG_2.jpg"));
+        let summary = copy_files(&jobs);
+        assert_eq!(summary.copied.len(), 2);
+        assert_eq!(summary.bytes_copied, 6);
+        assert_eq!(fs::read_to_string(backup.join("2024/IMG_2.jpg")).unwrap(), "two");
+        assert_eq!(
+            fs::metadata(backup.join("IMG_1.jpg")).unwrap().modified().unwrap(),
+            modified
+        );
+        assert_eq!(names(&backup), vec!["2024", "IMG_1.jpg"]);
+
+        let summary = copy_files(&jobs);
+        assert!(summary.copied.is_empty());
+        assert_eq!(summary.skipped.len(), 2);
+    }
+
+    #[test]
+    fn repeated_destination_is_copied_once() {
+        let dir = tempfile::tempdir().unwrap();
+        let (a, b) = (dir.path().join("a/IMG_1.jpg"), dir.path().join("b/IMG_1.jpg"));
+        write(&a, "same");
+        write(&b, "same");
+        let destination = dir.path().join("backup/IMG_1.jpg");
+        let jobs: Vec<CopyJob> = [a.clone(), b.clone()]
+            .into_iter()
+            .map(|source| CopyJob {
+                source,
+                destination: destination.clone(),
+                size: 4,
+                collision: None,
+            })
+            .collect();
+
+        let summary = copy_files(&jobs);
+        assert_eq!(summary.copied, vec![a]);
+        assert_eq!(summary.skipped, vec![b]);
+        assert_eq!(names(&dir.path().join("backup")), vec!["IMG_1.jpg"]);
+    }
+
+    #[test]
+    fn existing_destination_is_never_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = dir.path().join("new.jpg");
+        let destination = dir.path().join("backup/IMG_1.jpg");
+        write(&source, "new");
+        write(&destination, "old");
+
+        let e = copy_file_atomic(&source, &destination, &ProgressBar::hidden()).unwrap_err();
+        assert_eq!(e.kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(fs::read_to_string(&destination).unwrap(), "old");
+        assert_eq!(names(&dir.path().join("backup")), vec!["IMG_1.jpg"]);
+    }
+
+    #[test]
+    fn failed_copy_leaves_no_temporary_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let destination = dir.path().join("backup/IMG_1.jpg");
+        // A directory opens, so the temporary file is created, but can't be read
+        let source = dir.path().join("IMG_1.jpg");
+        fs::create_dir(&source).unwrap();
+
+        let result = copy_file_atomic(&source, &destination, &ProgressBar::hidden());
+        assert!(result.is_err());
+        assert!(names(&dir.path().join("backup")).is_empty());
+    }
+}
@@ -0,0 +1,703 @@
+use crate::asset;
+use crate::copy;
+use crate::hash_cache::HashCache;
+use crate::journal::{self, Journal, JournalEntry};
+use crate::manifest::Manifest;
+use crate::BackupError;
+use chrono::{DateTime, Utc};
+use indicatif::ProgressBar;
+use log::{info, warn};
+use std::collections::{BTreeMap, HashSet};
+use std::fs::{self, File, Metadata};
+use std::path::{Path, PathBuf};
+
+/// What `dedupe` does with files whose content is already elsewhere in the backup
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum DedupeMode {
+    /// Only list the duplicates
+    #[default]
+    Report,
+    /// Replace every duplicate with a hard link to the oldest copy, keeping
+    /// all paths but storing the content once
+    Hardlink,
+    /// Delete every copy but the oldest
+    KeepOldest,
+}
+
+/// A file with the same content as the kept copy of its group
+#[derive(Debug, Clone)]
+pub struct Duplicate {
+    pub path: PathBuf,
+    /// Already a hard link to the kept copy, so it takes no extra space
+    pub linked: bool,
+}
+
+/// Files in the backup that share the same content. The oldest is kept: the
+/// one the manifest has known longest, then the one modified first.
+#[derive(Debug, Clone)]
+pub struct DuplicateGroup {
+    pub sha256: String,
+    pub size: u64,
+    pub keep: PathBuf,
+    pub duplicates: Vec<Duplicate>,
+}
+
+impl DuplicateGroup {
+    /// Bytes freed by linking or deleting the duplicates
+    pub fn reclaimable(&self) -> u64 {
+        self.size * self.duplicates.iter().filter(|d| !d.linked).count() as u64
+    }
+}
+
+/// What a dedupe run or undo changed
+#[derive(Debug, Default)]
+pub struct DedupeSummary {
+    pub changed: Vec<PathBuf>,
+    pub bytes_freed: u64,
+    pub failed: Vec<(PathBuf, String)>,
+    /// Session ID of the journal recording the changes, if anything changed
+    pub session: Option<String>,
+}
+
+/// Group files by content. Only files of the same size are hashed, and empty
+/// files are left alone.
+pub fn find_duplicates(
+    backup_dir: &Path,
+    files: &[PathBuf],
+    manifest: &Manifest,
+    cache: &mut HashCache,
+    progress: &ProgressBar,
+) -> Vec<DuplicateGroup> {
+    let mut by_size: BTreeMap<u64, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        match fs::metadata(file) {
+            Ok(metadata) if metadata.len() > 0 => by_size
+                .entry(metadata.len())
+                .or_default()
+                .push(file.clone()),
+            Ok(_) => {}
+            Err(e) => warn!("Skipping {}: {}", file.display(), e),
+        }
+    }
+
+    let candidates: Vec<PathBuf> = by_size
+        .into_values()
+        .filter(|files| files.len() > 1)
+        .flatten()
+        .collect();
+    progress.set_length(candidates.len() as u64);
+
+    let mut by_hash: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    for (file, hash) in candidates.iter().zip(cache.hash_all(&candidates, progress)) {
+        match hash {
+            Ok(hash) => by_hash.entry(hash).or_default().push(file.clone()),
+            Err(e) => warn!("Failed to hash {}: {}", file.display(), e),
+        }
+    }
+
+    let mut groups = Vec::new();
+    for (sha256, mut paths) in by_hash {
+        if paths.len() < 2 {
+            continue;
+        }
+        let age = |path: &Path| {
+            let relative = path.strip_prefix(backup_dir).unwrap_or(path);
+            manifest
+                .get(relative)
+                .map(|record| record.first_seen)
+                .or_else(|| modified(&fs::metadata(path).ok()?))
+        };
+        paths.sort_by_cached_key(|path| {
+            (age(path).unwrap_or(DateTime::<Utc>::MAX_UTC), path.clone())
+        });
+
+        let keep = paths.remove(0);
+        let keep_metadata = fs::metadata(&keep).ok();
+        let duplicates = paths
+            .into_iter()
+            .map(|path| {
+                let linked = match (&keep_metadata, fs::metadata(&path)) {
+                    (Some(keep), Ok(other)) => same_file(keep, &other),
+                    _ => false,
+                };
+                Duplicate { path, linked }
+            })
+            .collect();
+        groups.push(DuplicateGroup {
+            size: fs::metadata(&keep).map(|m| m.len()).unwrap_or(0),
+            sha256,
+            keep,
+            duplicates,
+        });
+    }
+    groups
+}
+
+/// Take out of the groups every duplicate whose asset also has a file that
+/// isn't a duplicate, so deleting never splits a Live Photo or leaves a
+/// sidecar without its photo. `files` are all the files the groups were
+/// found among. Returns the duplicates taken out, with the reason.
+pub fn keep_assets_whole(
+    groups: &mut Vec<DuplicateGroup>,
+    files: &[PathBuf],
+) -> Vec<(PathBuf, String)> {
+    let duplicates: HashSet<&Path> = groups
+        .iter()
+        .flat_map(|group| group.duplicates.iter().map(|d| d.path.as_path()))
+        .collect();
+
+    let mut kept = Vec::new();
+    for asset in asset::group_assets(files.iter().cloned()) {
+        let files = asset.files();
+        let Some(unique) = files.iter().find(|file| !duplicates.contains(*file)) else {
+            continue;
+        };
+        let name = unique.file_name().unwrap_or_default().to_string_lossy();
+        for file in files.iter().filter(|file| duplicates.contains(*file)) {
+            kept.push((
+                file.to_path_buf(),
+                format!("kept with {}, which belongs to the same asset", name),
+            ));
+        }
+    }
+
+    let keep: HashSet<&Path> = kept.iter().map(|(file, _)| file.as_path()).collect();
+    for group in groups.iter_mut() {
+        group
+            .duplicates
+            .retain(|duplicate| !keep.contains(duplicate.path.as_path()));
+    }
+    groups.retain(|group| !group.duplicates.is_empty());
+    kept
+}
+
+/// Log every group with what `mode` does to each file
+pub fn print_plan(backup_dir: &Path, groups: &[DuplicateGroup], mode: DedupeMode) {
+    let relative = |path: &Path| {
+        path.strip_prefix(backup_dir)
+            .unwrap_or(path)
+            .display()
+            .to_string()
+    };
+
+    for group in groups {
+        info!(
+            "{} ({} bytes):",
+            &group.sha256[..12.min(group.sha256.len())],
+            group.size
+        );
+        info!("  keep      {}", relative(&group.keep));
+        for duplicate in &group.duplicates {
+            let action = match (mode, duplicate.linked) {
+                (DedupeMode::Hardlink, true) => "linked   ",
+                (DedupeMode::Hardlink, false) => "link     ",
+                (DedupeMode::KeepOldest, _) => "delete   ",
+                (DedupeMode::Report, true) => "duplicate (already linked)",
+                (DedupeMode::Report, false) => "duplicate",
+            };
+            info!("  {} {}", action, relative(&duplicate.path));
+        }
+    }
+
+    let duplicates: usize = groups.iter().map(|group| group.duplicates.len()).sum();
+    let reclaimable: u64 = groups.iter().map(DuplicateGroup::reclaimable).sum();
+    info!(
+        "Found {} duplicates of {} files, {} bytes could be reclaimed",
+        duplicates,
+        groups.len(),
+        reclaimable
+    );
+}
+
+/// Link or delete the duplicates of every group, rehashing both files first
+/// and skipping any whose content changed since the plan was made. Every
+/// change is written to a new journal before it is made, so `undo` can
+/// reverse it even if the run stops halfway, and deleted files are dropped
+/// from the manifest.
+pub fn apply(
+    backup_dir: &Path,
+    groups: &[DuplicateGroup],
+    mode: DedupeMode,
+    manifest: &mut Manifest,
+    progress: &ProgressBar,
+) -> Result<DedupeSummary, BackupError> {
+    let mut summary = DedupeSummary::default();
+    if mode == DedupeMode::Report {
+        return Ok(summary);
+    }
+
+    let mut journal: Option<Journal> = None;
+    let result = apply_groups(
+        backup_dir,
+        groups,
+        mode,
+        manifest,
+        &mut journal,
+        &mut summary,
+        progress,
+    );
+
+    // Files deleted before a failure are gone, so their records go either way
+    manifest.save()?;
+    if let Some(journal) = journal {
+        info!(
+            "Recorded {} changes in {}",
+            summary.changed.len(),
+            journal.path().display()
+        );
+        summary.session = Some(journal.session().to_string());
+    }
+    result.map(|_| summary)
+}
+
+fn apply_groups(
+    backup_dir: &Path,
+    groups: &[DuplicateGroup],
+    mode: DedupeMode,
+    manifest: &mut Manifest,
+    journal: &mut Option<Journal>,
+    summary: &mut DedupeSummary,
+    progress: &ProgressBar,
+) -> Result<(), BackupError> {
+    let relative = |path: &Path| path.strip_prefix(backup_dir).unwrap_or(path).to_path_buf();
+
+    for group in groups {
+        for duplicate in &group.duplicates {
+            progress.inc(1);
+            if mode == DedupeMode::Hardlink && duplicate.linked {
+                continue;
+            }
+
+            let path = &duplicate.path;
+            if let Err(e) = check_unchanged(&group.keep, path, &group.sha256) {
+                warn!("Skipping {}: {}", path.display(), e);
+                summary.failed.push((path.clone(), e));
+                continue;
+            }
+            let metadata = fs::metadata(path)?;
+            let now = Utc::now();
+
+            let entry = match mode {
+                DedupeMode::Hardlink => JournalEntry::Hardlinked {
+                    path: relative(path),
+                    target: relative(&group.keep),
+                    sha256: group.sha256.clone(),
+                    modified: modified(&metadata),
+                    readonly: metadata.permissions().readonly(),
+                    at: now,
+                },
+                DedupeMode::KeepOldest => JournalEntry::Deleted {
+                    path: relative(path),
+                    kept: relative(&group.keep),
+                    sha256: group.sha256.clone(),
+                    size: group.size,
+                    modified: modified(&metadata),
+                    readonly: metadata.permissions().readonly(),
+                    manifest: manifest.get(&relative(path)).cloned(),
+                    at: now,
+                },
+                DedupeMode::Report => unreachable!("report mode changes nothing"),
+            };
+
+            // Undo passes over an entry whose change never happened
+            let journal = match journal {
+                Some(journal) => journal,
+                None => journal.insert(Journal::create(backup_dir, "dedupe")?),
+            };
+            journal.append(&entry)?;
+
+            let changed = match mode {
+                DedupeMode::Hardlink => link_over(&group.keep, path),
+                _ => fs::remove_file(path),
+            };
+            match changed {
+                Ok(()) => {
+                    if mode == DedupeMode::KeepOldest {
+                        manifest.remove(&relative(path));
+                    }
+                    summary.changed.push(path.clone());
+                    if !duplicate.linked {
+                        summary.bytes_freed += group.size;
+                    }
+                }
+                Err(e) => {
+                    warn!("Failed to dedupe {}: {}", path.display(), e);
+                    summary.failed.push((path.clone(), e.to_string()));
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Reverse every change of a dedupe session, newest first. Linked paths get
+/// their own copy back and deleted files are copied back from the file that
+/// was kept, with their old modification time, permissions and manifest
+/// record. Changes that were already reversed are skipped, and the journal
+/// is retired once nothing is left to undo.
+pub fn undo(
+    backup_dir: &Path,
+    session: &str,
+    manifest: &mut Manifest,
+    dry_run: bool,
+) -> Result<DedupeSummary, BackupError> {
+    let entries = journal::load(backup_dir, session)?;
+    let mut summary = DedupeSummary {
+        session: Some(session.to_string()),
+        ..DedupeSummary::default()
+    };
+
+    for entry in entries.iter().rev() {
+        let path = backup_dir.join(entry.path());
+        let result = match entry {
+            JournalEntry::Hardlinked {
+                target,
+                sha256,
+                modified,
+                readonly,
+                ..
+            } => {
+                let target = backup_dir.join(target);
+                match (fs::metadata(&path), fs::metadata(&target)) {
+                    (Ok(current), Ok(kept)) if same_file(&current, &kept) => {
+                        if dry_run {
+                            info!(
+                                "[dry run] Would unlink {} from {}",
+                                path.display(),
+                                target.display()
+                            );
+                            Ok(true)
+                        } else {
+                            restore_copy(&target, &path, sha256, *modified, *readonly, true)
+                                .map(|_| true)
+                        }
+                    }
+                    (Ok(_), Ok(_)) => Ok(false),
+                    (Err(e), _) => Err(format!("{} is gone: {}", path.display(), e)),
+                    (_, Err(e)) => Err(format!("{} is gone: {}", target.display(), e)),
+                }
+            }
+            JournalEntry::Deleted {
+                kept,
+                sha256,
+                modified,
+                readonly,
+                manifest: record,
+                ..
+            } => {
+                let kept = backup_dir.join(kept);
+                if path.exists() {
+                    match crate::calculate_file_hash(&path) {
+                        Ok(hash) if hash == *sha256 => Ok(false),
+                        Ok(_) => Err(format!("a different file is now at {}", path.display())),
+                        Err(e) => Err(e.to_string()),
+                    }
+                } else if dry_run {
+                    info!(
+                        "[dry run] Would restore {} from {}",
+                        path.display(),
+                        kept.display()
+                    );
+                    Ok(true)
+                } else {
+                    restore_copy(&kept, &path, sha256, *modified, *readonly, false).map(|_| {
+                        if let Some(record) = record {
+                            manifest.restore(record.clone());
+                        }
+                        true
+                    })
+                }
+            }
+            JournalEntry::Trashed { .. } => {
+                Err("moved to the trash by sync, undo it with sync undo".to_string())
+            }
+        };
+
+        match result {
+            Ok(true) => summary.changed.push(path),
+            Ok(false) => info!("{} was already restored", path.display()),
+            Err(e) => {
+                warn!("Failed to undo the change to {}: {}", path.display(), e);
+                summary.failed.push((path, e));
+            }
+        }
+    }
+
+    if !dry_run {
+        manifest.save()?;
+        if summary.failed.is_empty() {
+            let retired = journal::mark_undone(backup_dir, session)?;
+            info!(
+                "Session {} is undone, its journal is kept at {}",
+                session,
+                retired.display()
+            );
+        }
+    }
+    Ok(summary)
+}
+
+/// Make sure the kept file and the duplicate both still have the planned content
+fn check_unchanged(keep: &Path, duplicate: &Path, sha256: &str) -> Result<(), String> {
+    for path in [keep, duplicate] {
+        let hash = crate::calculate_file_hash(path).map_err(|e| e.to_string())?;
+        if hash != sha256 {
+            return Err(format!("{} changed since it was hashed", path.display()));
+        }
+    }
+    Ok(())
+}
+
+/// Replace `path` with a hard link to `target` through a temporary link, so
+/// `path` always refers to a complete file
+fn link_over(target: &Path, path: &Path) -> std::io::Result<()> {
+    let parent = path.parent().unwrap_or(Path::new("."));
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = parent.join(format!(".{}.{}.link", name, std::process::id()));
+
+    let result = fs::hard_link(target, &temp).and_then(|_| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+/// Copy the kept file back to `path` after checking it still has the
+/// journaled content, then give it back its own modification time and
+/// permissions. With `replace`, the copy takes the place of the hard link at
+/// `path`; otherwise nothing may be there.
+fn restore_copy(
+    source: &Path,
+    path: &Path,
+    sha256: &str,
+    modified: Option<DateTime<Utc>>,
+    readonly: bool,
+    replace: bool,
+) -> Result<(), String> {
+    let hash = crate::calculate_file_hash(source).map_err(|e| e.to_string())?;
+    if hash != sha256 {
+        return Err(format!(
+            "{} no longer has the original content",
+            source.display()
+        ));
+    }
+
+    let restore = || -> std::io::Result<()> {
+        if replace {
+            copy::replace_file_atomic(source, path, &ProgressBar::hidden())?;
+        } else {
+            copy::copy_file_atomic(source, path, &ProgressBar::hidden())?;
+        }
+        // Setting explicit times only needs ownership, so a read-only handle will do
+        if let Some(modified) = modified {
+            File::open(path)?.set_modified(modified.into())?;
+        }
+        let mut permissions = fs::metadata(path)?.permissions();
+        if permissions.readonly() != readonly {
+            permissions.set_readonly(readonly);
+            fs::set_permissions(path, permissions)?;
+        }
+        Ok(())
+    };
+    restore().map_err(|e| e.to_string())
+}
+
+fn modified(metadata: &Metadata) -> Option<DateTime<Utc>> {
+    metadata.modified().ok().map(DateTime::from)
+}
+
+#[cfg(unix)]
+fn same_file(a: &Metadata, b: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    a.dev() == b.dev() && a.ino() == b.ino()
+}
+
+#[cfg(not(unix))]
+fn same_file(_a: &Metadata, _b: &Metadata) -> bool {
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, SystemTime};
+
+    /// Write a file whose modification time is `age` seconds in the past
+    fn write(path: &Path, content: &[u8], age: u64) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+        let modified = SystemTime::now() - Duration::from_secs(age);
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(modified)
+            .unwrap();
+    }
+
+    fn find_groups(
+        backup_dir: &Path,
+        files: &[PathBuf],
+        manifest: &Manifest,
+    ) -> Vec<DuplicateGroup> {
+        let mut cache = HashCache::load(&backup_dir.join("cache.json")).unwrap();
+        find_duplicates(
+            backup_dir,
+            files,
+            manifest,
+            &mut cache,
+            &ProgressBar::hidden(),
+        )
+    }
+
+    #[test]
+    fn hardlink_and_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = dir.path();
+        let (old, new) = (backup.join("a/IMG_1.jpg"), backup.join("b/IMG_1.jpg"));
+        write(&old, b"same", 100);
+        write(&new, b"same", 10);
+        let mut manifest = Manifest::open(backup).unwrap();
+
+        let groups = find_groups(backup, &[old.clone(), new.clone()], &manifest);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].keep, old);
+        let summary = apply(
+            backup,
+            &groups,
+            DedupeMode::Hardlink,
+            &mut manifest,
+            &ProgressBar::hidden(),
+        )
+        .unwrap();
+        assert_eq!(summary.changed, vec![new.clone()]);
+        assert!(same_file(
+            &fs::metadata(&old).unwrap(),
+            &fs::metadata(&new).unwrap()
+        ));
+
+        let session = summary.session.unwrap();
+        let undone = undo(backup, &session, &mut manifest, false).unwrap();
+        assert_eq!(undone.changed, vec![new.clone()]);
+        assert!(!same_file(
+            &fs::metadata(&old).unwrap(),
+            &fs::metadata(&new).unwrap()
+        ));
+        assert_eq!(fs::read(&new).unwrap(), b"same");
+        assert!(journal::load(backup, &session).is_err());
+    }
+
+    #[test]
+    fn keep_oldest_and_undo() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = dir.path();
+        let (old, new) = (backup.join("IMG_1.jpg"), backup.join("IMG_1 copy.jpg"));
+        write(&old, b"same", 100);
+        write(&new, b"same", 10);
+        let mut manifest = Manifest::open(backup).unwrap();
+        let hash = crate::calculate_file_hash(&new).unwrap();
+        let relative = PathBuf::from("IMG_1 copy.jpg");
+        manifest.record(
+            relative.clone(),
+            hash,
+            4,
+            None,
+            PathBuf::from("/export/IMG_1 copy.jpg"),
+            "run",
+        );
+        manifest.save().unwrap();
+
+        let groups = find_groups(backup, &[old.clone(), new.clone()], &manifest);
+        let summary = apply(
+            backup,
+            &groups,
+            DedupeMode::KeepOldest,
+            &mut manifest,
+            &ProgressBar::hidden(),
+        )
+        .unwrap();
+        assert_eq!(summary.changed, vec![new.clone()]);
+        assert_eq!(summary.bytes_freed, 4);
+        assert!(!new.exists());
+        assert!(Manifest::open(backup).unwrap().get(&relative).is_none());
+
+        let mut manifest = Manifest::open(backup).unwrap();
+        undo(backup, &summary.session.unwrap(), &mut manifest, false).unwrap();
+        assert_eq!(fs::read(&new).unwrap(), b"same");
+        let restored = Manifest::open(backup).unwrap();
+        assert_eq!(
+            restored.get(&relative).unwrap().source,
+            PathBuf::from("/export/IMG_1 copy.jpg")
+        );
+    }
+
+    #[test]
+    fn nothing_is_deleted_without_a_journal() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = dir.path();
+        let (old, new) = (backup.join("IMG_1.jpg"), backup.join("IMG_2.jpg"));
+        write(&old, b"same", 100);
+        write(&new, b"same", 10);
+        // A file where the journal directory should be makes creating the journal fail
+        write(&journal::journal_dir(backup), b"", 0);
+        let mut manifest = Manifest::open(backup).unwrap();
+
+        let groups = find_groups(backup, &[old, new.clone()], &manifest);
+        let result = apply(
+            backup,
+            &groups,
+            DedupeMode::KeepOldest,
+            &mut manifest,
+            &ProgressBar::hidden(),
+        );
+        assert!(result.is_err());
+        assert!(new.exists());
+    }
+
+    #[test]
+    fn keep_oldest_leaves_assets_whole() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup = dir.path();
+        // The first copy of a Live Photo, then a second copy whose video differs
+        let files: Vec<PathBuf> = ["a/IMG_1.HEIC", "a/IMG_1.MOV", "b/IMG_1.HEIC", "b/IMG_1.MOV"]
+            .iter()
+            .map(|name| backup.join(name))
+            .collect();
+        write(&files[0], b"still", 100);
+        write(&files[1], b"motion", 100);
+        write(&files[2], b"still", 10);
+        write(&files[3], b"other motion", 10);
+        // And a photo whose sidecar is a duplicate but whose photo is not
+        let (photo, sidecar) = (backup.join("c/IMG_2.jpg"), backup.join("c/IMG_2.xmp"));
+        let (other_photo, other_sidecar) = (backup.join("d/IMG_3.jpg"), backup.join("d/IMG_3.xmp"));
+        write(&other_photo, b"jpeg 3", 100);
+        write(&other_sidecar, b"<xmp/>", 100);
+        write(&photo, b"jpeg 2", 10);
+        write(&sidecar, b"<xmp/>", 10);
+        let all: Vec<PathBuf> = files
+            .iter()
+            .cloned()
+            .chain([photo, sidecar.clone(), other_photo, other_sidecar])
+            .collect();
+        let manifest = Manifest::open(backup).unwrap();
+
+        let mut groups = find_groups(backup, &all, &manifest);
+        assert_eq!(groups.len(), 2);
+        let kept = keep_assets_whole(&mut groups, &all);
+
+        assert!(groups.is_empty());
+        let kept: Vec<&PathBuf> = kept.iter().map(|(file, _)| file).collect();
+        assert_eq!(kept, vec![&files[2], &sidecar]);
+
+        // When both halves are duplicates, the whole copy goes
+        write(&files[3], b"motion", 10);
+        let mut groups = find_groups(backup, &files, &manifest);
+        assert!(keep_assets_whole(&mut groups, &files).is_empty());
+        let mut doomed: Vec<&PathBuf> = groups
+            .iter()
+            .flat_map(|group| group.duplicates.iter().map(|d| &d.path))
+            .collect();
+        doomed.sort();
+        assert_eq!(doomed, vec![&files[2], &files[3]]);
+    }
+}
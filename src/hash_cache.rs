@@ -0,0 +1,502 @@
+use crate::constants;
+use crate::perceptual::{self, PerceptualHash};
+use crate::BackupError;
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::env;
+use std::fs::{self, Metadata};
+use std::path::{Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// The parts of a file's metadata that change whenever its contents might have
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub mtime_secs: i64,
+    pub mtime_nanos: u32,
+    pub inode: u64,
+}
+
+impl FileStamp {
+    pub fn from_metadata(metadata: &Metadata) -> FileStamp {
+        let (mtime_secs, mtime_nanos) = match metadata.modified() {
+            Ok(time) => match time.duration_since(UNIX_EPOCH) {
+                Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+                Err(e) => (-(e.duration().as_secs() as i64), e.duration().subsec_nanos()),
+            },
+            Err(_) => (0, 0),
+        };
+
+        FileStamp {
+            size: metadata.len(),
+            mtime_secs,
+            mtime_nanos,
+            inode: inode(metadata),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn inode(metadata: &Metadata) -> u64 {
+    std::os::unix::fs::MetadataExt::ino(metadata)
+}
+
+#[cfg(not(unix))]
+fn inode(_metadata: &Metadata) -> u64 {
+    0
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    stamp: FileStamp,
+    sha256: String,
+    /// Only computed for files we ask Immich about
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    sha1: Option<String>,
+    /// Only computed for photos matched by how they look
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    perceptual: Option<PerceptualHash>,
+}
+
+/// Which hash of a file to look up
+#[derive(Debug, Clone, Copy)]
+enum Digest {
+    Sha256,
+    Sha1,
+}
+
+/// On-disk cache of SHA-256 hashes keyed by absolute path. An entry is only
+/// trusted while the file's size, mtime and inode are unchanged.
+#[derive(Debug, Default)]
+pub struct HashCache {
+    path: PathBuf,
+    entries: HashMap<PathBuf, CacheEntry>,
+    dirty: bool,
+}
+
+/// Default location of the cache: $XDG_CACHE_HOME/backup-photos/hashes.json
+pub fn default_cache_file() -> Result<PathBuf, BackupError> {
+    let cache_home = match env::var_os("XDG_CACHE_HOME").filter(|v| !v.is_empty()) {
+        Some(dir) => PathBuf::from(dir),
+        None => dirs::home_dir()
+            .ok_or_else(|| {
+                BackupError::DirectoryNotAccessible(
+                    "Could not determine home directory".to_string(),
+                )
+            })?
+            .join(".cache"),
+    };
+
+    Ok(cache_home
+        .join(constants::APP_NAME)
+        .join(constants::HASH_CACHE_FILE_NAME))
+}
+
+impl HashCache {
+    /// Load the cache from its default location
+    pub fn open() -> Result<HashCache, BackupError> {
+        HashCache::load(&default_cache_file()?)
+    }
+
+    /// Load a cache file. A missing file gives an empty cache and an unreadable
+    /// one is discarded, since everything in it can be recomputed.
+    pub fn load(path: &Path) -> Result<HashCache, BackupError> {
+        let entries = match fs::read(path) {
+            Ok(data) => match serde_json::from_slice(&data) {
+                Ok(entries) => entries,
+                Err(e) => {
+                    warn!(
+                        "Ignoring unreadable hash cache {}: {}",
+                        path.display(),
+                        e
+                    );
+                    HashMap::new()
+                }
+            },
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(BackupError::IoError(e)),
+        };
+
+        debug!("Loaded {} cached hashes from {}", entries.len(), path.display());
+
+        Ok(HashCache {
+            path: path.to_path_buf(),
+            entries,
+            dirty: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// The cached hash for a file, if its stamp still matches
+    pub fn get(&self, path: &Path, stamp: &FileStamp) -> Option<&str> {
+        self.entries
+            .get(path)
+            .filter(|entry| entry.stamp == *stamp)
+            .map(|entry| entry.sha256.as_str())
+    }
+
+    pub fn insert(&mut self, path: PathBuf, stamp: FileStamp, sha256: String) {
+        self.entries.insert(
+            path,
+            CacheEntry {
+                stamp,
+                sha256,
+                sha1: None,
+                perceptual: None,
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Hash a file, reusing the cached value when the file hasn't changed
+    pub fn hash(&mut self, path: &Path) -> Result<String, BackupError> {
+        let stamp = FileStamp::from_metadata(&fs::metadata(path)?);
+
+        if let Some(hash) = self.get(path, &stamp) {
+            return Ok(hash.to_string());
+        }
+
+        let hash = crate::calculate_file_hash(path)?;
+        self.insert(path.to_path_buf(), stamp, hash.clone());
+        Ok(hash)
+    }
+
+    /// Hash many files in parallel on the current rayon pool, reusing cached
+    /// values where possible. Results come back in the same order as `files`.
+    pub fn hash_all(
+        &mut self,
+        files: &[PathBuf],
+        progress: &ProgressBar,
+    ) -> Vec<Result<String, BackupError>> {
+        self.hash_all_by(files, progress, Digest::Sha256)
+    }
+
+    /// Like `hash_all`, but returns SHA-1 hashes, the checksum Immich uses.
+    /// Files that need hashing get both hashes cached from a single read.
+    pub fn sha1_all(
+        &mut self,
+        files: &[PathBuf],
+        progress: &ProgressBar,
+    ) -> Vec<Result<String, BackupError>> {
+        self.hash_all_by(files, progress, Digest::Sha1)
+    }
+
+    fn hash_all_by(
+        &mut self,
+        files: &[PathBuf],
+        progress: &ProgressBar,
+        digest: Digest,
+    ) -> Vec<Result<String, BackupError>> {
+        let results: Vec<Result<(String, Option<CacheEntry>), BackupError>> = files
+            .par_iter()
+            .map(|path| {
+                let result = fs::metadata(path)
+                    .map_err(BackupError::IoError)
+                    .and_then(|metadata| {
+                        let stamp = FileStamp::from_metadata(&metadata);
+                        let cached = self
+                            .entries
+                            .get(path)
+                            .filter(|entry| entry.stamp == stamp);
+                        match (digest, cached) {
+                            (Digest::Sha256, Some(entry)) => Ok((entry.sha256.clone(), None)),
+                            (Digest::Sha1, Some(CacheEntry { sha1: Some(sha1), .. })) => {
+                                Ok((sha1.clone(), None))
+                            }
+                            (Digest::Sha256, None) => {
+                                crate::calculate_file_hash(path).map(|sha256| {
+                                    let entry = CacheEntry {
+                                        stamp,
+                                        sha256: sha256.clone(),
+                                        sha1: None,
+                                        perceptual: None,
+                                    };
+                                    (sha256, Some(entry))
+                                })
+                            }
+                            (Digest::Sha1, _) => {
+                                crate::calculate_file_hashes(path).map(|(sha256, sha1)| {
+                                    let entry = CacheEntry {
+                                        stamp,
+                                        sha256,
+                                        sha1: Some(sha1.clone()),
+                                        perceptual: cached.and_then(|entry| entry.perceptual),
+                                    };
+                                    (sha1, Some(entry))
+                                })
+                            }
+                        }
+                    });
+                progress.inc(1);
+                result
+            })
+            .collect();
+
+        files
+            .iter()
+            .zip(results)
+            .map(|(path, result)| {
+                result.map(|(hash, fresh)| {
+                    if let Some(entry) = fresh {
+                        self.entries.insert(path.clone(), entry);
+                        self.dirty = true;
+                    }
+                    hash
+                })
+            })
+            .collect()
+    }
+
+    /// Perceptual hashes of photos, only decoding files that changed since
+    /// they were last hashed. Files the cache doesn't know yet also get their
+    /// SHA-256 computed so they can be cached.
+    pub fn perceptual_all(
+        &mut self,
+        files: &[PathBuf],
+        progress: &ProgressBar,
+    ) -> Vec<Result<PerceptualHash, BackupError>> {
+        let results: Vec<Result<(PerceptualHash, Option<CacheEntry>), BackupError>> = files
+            .par_iter()
+            .map(|path| {
+                let result = fs::metadata(path)
+                    .map_err(BackupError::IoError)
+                    .and_then(|metadata| {
+                        let stamp = FileStamp::from_metadata(&metadata);
+                        let cached = self
+                            .entries
+                            .get(path)
+                            .filter(|entry| entry.stamp == stamp);
+                        if let Some(hash) = cached.and_then(|entry| entry.perceptual) {
+                            return Ok((hash, None));
+                        }
+
+                        let hash = perceptual::hash_file(path).map_err(BackupError::DecodeFailed)?;
+                        let sha256 = match cached {
+                            Some(entry) => entry.sha256.clone(),
+                            None => crate::calculate_file_hash(path)?,
+                        };
+                        let entry = CacheEntry {
+                            stamp,
+                            sha256,
+                            sha1: cached.and_then(|entry| entry.sha1.clone()),
+                            perceptual: Some(hash),
+                        };
+                        Ok((hash, Some(entry)))
+                    });
+                progress.inc(1);
+                result
+            })
+            .collect();
+
+        files
+            .iter()
+            .zip(results)
+            .map(|(path, result)| {
+                result.map(|(hash, fresh)| {
+                    if let Some(entry) = fresh {
+                        self.entries.insert(path.clone(), entry);
+                        self.dirty = true;
+                    }
+                    hash
+                })
+            })
+            .collect()
+    }
+
+    /// Drop every entry whose path starts with `root`
+    pub fn remove_under(&mut self, root: &Path) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, _| !path.starts_with(root));
+        let removed = before - self.entries.len();
+        self.dirty |= removed > 0;
+        removed
+    }
+
+    /// Drop entries for files that no longer exist or have changed since they were hashed
+    pub fn prune(&mut self) -> usize {
+        let before = self.entries.len();
+        self.entries.retain(|path, entry| match fs::metadata(path) {
+            Ok(metadata) => FileStamp::from_metadata(&metadata) == entry.stamp,
+            Err(_) => false,
+        });
+        let removed = before - self.entries.len();
+        self.dirty |= removed > 0;
+        removed
+    }
+
+    /// Write the cache back to disk if anything changed, via a temporary file
+    pub fn save(&mut self) -> Result<(), BackupError> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let data = serde_json::to_vec(&self.entries)
+            .map_err(|e| BackupError::IoError(std::io::Error::other(e)))?;
+        let temp = self.path.with_extension(format!("json.{}.tmp", std::process::id()));
+        fs::write(&temp, data)?;
+        fs::rename(&temp, &self.path)?;
+
+        debug!("Saved {} cached hashes to {}", self.entries.len(), self.path.display());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::File;
+    use std::time::{Duration, SystemTime};
+
+    const BOGUS: &str = "cached";
+
+    /// A cache in `dir` that claims `path`, as it is now, hashes to `BOGUS`,
+    /// so a lookup that returns the real hash must have reread the file
+    fn cache_with_bogus_entry(dir: &Path, path: &Path) -> HashCache {
+        let mut cache = HashCache::load(&dir.join("cache.json")).unwrap();
+        let stamp = FileStamp::from_metadata(&fs::metadata(path).unwrap());
+        cache.insert(path.to_path_buf(), stamp, BOGUS.to_string());
+        cache
+    }
+
+    fn set_modified(path: &Path, time: SystemTime) {
+        File::options()
+            .write(true)
+            .open(path)
+            .unwrap()
+            .set_modified(time)
+            .unwrap();
+    }
+
+    #[test]
+    fn unchanged_file_is_not_rehashed() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("IMG_0001.jpg");
+        fs::write(&file, "photo").unwrap();
+
+        let mut cache = cache_with_bogus_entry(dir.path(), &file);
+        assert_eq!(cache.hash(&file).unwrap(), BOGUS);
+        let results = cache.hash_all(std::slice::from_ref(&file), &ProgressBar::hidden());
+        assert_eq!(results[0].as_ref().unwrap(), BOGUS);
+    }
+
+    #[test]
+    fn size_change_invalidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("IMG_0001.jpg");
+        fs::write(&file, "photo").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let mut cache = cache_with_bogus_entry(dir.path(), &file);
+
+        fs::write(&file, "a longer photo").unwrap();
+        set_modified(&file, modified);
+
+        let hash = cache.hash(&file).unwrap();
+        assert_eq!(hash, crate::calculate_file_hash(&file).unwrap());
+        assert_eq!(cache.hash(&file).unwrap(), hash);
+    }
+
+    #[test]
+    fn mtime_change_invalidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("IMG_0001.jpg");
+        fs::write(&file, "photo").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let mut cache = cache_with_bogus_entry(dir.path(), &file);
+
+        // Same size, same inode, only the modification time moves
+        fs::write(&file, "PHOTO").unwrap();
+        set_modified(&file, modified + Duration::from_secs(1));
+
+        assert_eq!(
+            cache.hash(&file).unwrap(),
+            crate::calculate_file_hash(&file).unwrap()
+        );
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn inode_change_invalidates() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("IMG_0001.jpg");
+        fs::write(&file, "photo").unwrap();
+        let modified = fs::metadata(&file).unwrap().modified().unwrap();
+        let mut cache = cache_with_bogus_entry(dir.path(), &file);
+
+        // Replaced by another file of the same size and mtime
+        let replacement = dir.path().join("replacement");
+        fs::write(&replacement, "PHOTO").unwrap();
+        set_modified(&replacement, modified);
+        fs::rename(&replacement, &file).unwrap();
+
+        let stamp = FileStamp::from_metadata(&fs::metadata(&file).unwrap());
+        assert_eq!(cache.get(&file, &stamp), None);
+        assert_eq!(
+            cache.hash(&file).unwrap(),
+            crate::calculate_file_hash(&file).unwrap()
+        );
+    }
+
+    #[test]
+    fn saved_cache_loads_and_prunes_stale_entries() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("cache/hashes.json");
+        let (kept, changed, deleted) = (
+            dir.path().join("kept.jpg"),
+            dir.path().join("changed.jpg"),
+            dir.path().join("deleted.jpg"),
+        );
+        for file in [&kept, &changed, &deleted] {
+            fs::write(file, file.to_string_lossy().as_bytes()).unwrap();
+        }
+
+        let mut cache = HashCache::load(&cache_file).unwrap();
+        let files = vec![kept.clone(), changed.clone(), deleted.clone()];
+        let sha1 = cache.sha1_all(&files, &ProgressBar::hidden());
+        assert!(sha1.iter().all(Result::is_ok));
+        cache.save().unwrap();
+
+        let mut cache = HashCache::load(&cache_file).unwrap();
+        assert_eq!(cache.len(), 3);
+        let (sha256, sha1) = crate::calculate_file_hashes(&kept).unwrap();
+        let stamp = FileStamp::from_metadata(&fs::metadata(&kept).unwrap());
+        assert_eq!(cache.get(&kept, &stamp), Some(sha256.as_str()));
+        let again = cache.sha1_all(std::slice::from_ref(&kept), &ProgressBar::hidden());
+        assert_eq!(again[0].as_ref().unwrap(), &sha1);
+
+        fs::write(&changed, "something else entirely").unwrap();
+        fs::remove_file(&deleted).unwrap();
+        assert_eq!(cache.prune(), 2);
+        cache.save().unwrap();
+        assert_eq!(HashCache::load(&cache_file).unwrap().len(), 1);
+    }
+
+    #[test]
+    fn unreadable_cache_is_discarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let cache_file = dir.path().join("hashes.json");
+        fs::write(&cache_file, "{ not json").unwrap();
+
+        let cache = HashCache::load(&cache_file).unwrap();
+        assert!(cache.is_empty());
+        assert_eq!(cache.path(), cache_file);
+    }
+}
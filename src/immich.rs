@@ -0,0 +1,724 @@
+use crate::secrets::ApiKey;
+use crate::BackupError;
+use chrono::{DateTime, Utc};
+use indicatif::{ProgressBar, ProgressStyle};
+use log::{debug, warn};
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File};
+use std::io::{self, Cursor, Read};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// Device id reported to Immich for everything we upload
+const DEVICE_ID: &str = "backup-photos";
+
+/// How many checksums to send in one bulk upload check
+const CHECKSUM_BATCH_SIZE: usize = 1000;
+
+/// How often and how patiently to retry a request that failed for a reason
+/// that might go away: a network error, a 5xx response or a 429
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    /// Total number of tries, including the first one
+    pub attempts: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            attempts: 4,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(8),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before the given retry (1 for the first retry), doubling each time
+    fn delay(&self, retry: u32) -> Duration {
+        let factor = 2u32.saturating_pow(retry.saturating_sub(1));
+        self.initial_delay.saturating_mul(factor).min(self.max_delay)
+    }
+}
+
+/// What the server did with one uploaded file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum UploadStatus {
+    /// A new asset was created
+    Created,
+    /// The server already had an asset with the same contents
+    Duplicate,
+    /// The upload failed, even after retrying
+    Error(String),
+}
+
+/// The result of uploading one file
+#[derive(Debug, Clone)]
+pub struct AssetUpload {
+    pub path: PathBuf,
+    pub status: UploadStatus,
+    /// Id of the created or already existing asset
+    pub asset_id: Option<String>,
+}
+
+/// Per-file results of an upload run, in the order the files were given
+#[derive(Debug, Default)]
+pub struct UploadReport {
+    pub assets: Vec<AssetUpload>,
+}
+
+impl UploadReport {
+    pub fn created(&self) -> usize {
+        self.count(|status| *status == UploadStatus::Created)
+    }
+
+    pub fn duplicates(&self) -> usize {
+        self.count(|status| *status == UploadStatus::Duplicate)
+    }
+
+    /// Files that failed to upload, with the reason
+    pub fn errors(&self) -> Vec<(&Path, &str)> {
+        self.assets
+            .iter()
+            .filter_map(|asset| match &asset.status {
+                UploadStatus::Error(e) => Some((asset.path.as_path(), e.as_str())),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn count(&self, matches: impl Fn(&UploadStatus) -> bool) -> usize {
+        self.assets.iter().filter(|asset| matches(&asset.status)).count()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct UploadResponse {
+    id: String,
+    status: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct UserResponse {
+    email: String,
+}
+
+/// Whether the server already has an asset with a given checksum
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerPresence {
+    Present { asset_id: String },
+    /// The asset exists but is in Immich's trash
+    Trashed { asset_id: String },
+    Missing,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkCheckRequest<'a> {
+    assets: Vec<BulkCheckItem<'a>>,
+}
+
+#[derive(Debug, Serialize)]
+struct BulkCheckItem<'a> {
+    id: String,
+    checksum: &'a str,
+}
+
+#[derive(Debug, Deserialize)]
+struct BulkCheckResponse {
+    results: Vec<BulkCheckResult>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BulkCheckResult {
+    id: String,
+    action: String,
+    reason: Option<String>,
+    asset_id: Option<String>,
+    #[serde(default)]
+    is_trashed: bool,
+}
+
+/// A minimal blocking client for the Immich REST API. It talks to whatever
+/// base URL it is given, so it works just as well against a local mock server.
+pub struct ImmichClient {
+    base_url: String,
+    api_key: ApiKey,
+    agent: ureq::Agent,
+    retry: RetryPolicy,
+}
+
+impl ImmichClient {
+    /// Create a client for a server URL such as `http://localhost:2283`.
+    /// The `/api` prefix is added if the URL doesn't already end with it.
+    pub fn new(server: &str, api_key: ApiKey) -> ImmichClient {
+        let server = server.trim_end_matches('/');
+        let base_url = if server.ends_with("/api") {
+            server.to_string()
+        } else {
+            format!("{}/api", server)
+        };
+
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(Duration::from_secs(10))
+            .timeout_read(Duration::from_secs(300))
+            .build();
+
+        ImmichClient {
+            base_url,
+            api_key,
+            agent,
+            retry: RetryPolicy::default(),
+        }
+    }
+
+    pub fn with_retry(mut self, retry: RetryPolicy) -> ImmichClient {
+        self.retry = retry;
+        self
+    }
+
+    pub fn base_url(&self) -> &str {
+        &self.base_url
+    }
+
+    /// Check that the server is reachable and accepts the API key, returning
+    /// the email of the user the key belongs to
+    pub fn check_connection(&self) -> Result<String, BackupError> {
+        let response = self.send("GET /users/me", || {
+            self.request("GET", "/users/me").call().map_err(Box::new)
+        })?;
+        let user: UserResponse = response.into_json().map_err(|e| {
+            BackupError::ImmichApiError(format!("Unexpected response from /users/me: {}", e))
+        })?;
+        Ok(user.email)
+    }
+
+    /// Ask the server which of the given SHA-1 checksums (hex) it already has an
+    /// asset for, using the bulk upload check endpoint. Results come back in
+    /// the same order as `checksums`.
+    pub fn check_checksums(&self, checksums: &[String]) -> Result<Vec<ServerPresence>, BackupError> {
+        let mut presence = Vec::with_capacity(checksums.len());
+
+        for (batch_number, batch) in checksums.chunks(CHECKSUM_BATCH_SIZE).enumerate() {
+            let request = BulkCheckRequest {
+                assets: batch
+                    .iter()
+                    .enumerate()
+                    .map(|(index, checksum)| BulkCheckItem {
+                        id: index.to_string(),
+                        checksum,
+                    })
+                    .collect(),
+            };
+
+            let response = self.send("POST /assets/bulk-upload-check", || {
+                self.request("POST", "/assets/bulk-upload-check")
+                    .send_json(&request)
+                    .map_err(Box::new)
+            })?;
+            let response: BulkCheckResponse = response.into_json().map_err(|e| {
+                BackupError::ImmichApiError(format!(
+                    "Unexpected response from /assets/bulk-upload-check: {}",
+                    e
+                ))
+            })?;
+
+            let mut batch_presence = vec![ServerPresence::Missing; batch.len()];
+            for result in response.results {
+                let Some(slot) = result
+                    .id
+                    .parse::<usize>()
+                    .ok()
+                    .and_then(|index| batch_presence.get_mut(index))
+                else {
+                    warn!("Ignoring bulk upload check result with unknown id '{}'", result.id);
+                    continue;
+                };
+
+                // A rejected duplicate is the only answer that means the server has the file
+                if result.action != "reject" || result.reason.as_deref() != Some("duplicate") {
+                    continue;
+                }
+                let asset_id = result.asset_id.unwrap_or_default();
+                *slot = if result.is_trashed {
+                    ServerPresence::Trashed { asset_id }
+                } else {
+                    ServerPresence::Present { asset_id }
+                };
+            }
+
+            debug!(
+                "Checked batch {} of {} checksums with the server",
+                batch_number + 1,
+                batch.len()
+            );
+            presence.extend(batch_presence);
+        }
+
+        Ok(presence)
+    }
+
+    /// Upload one file, along with its XMP sidecar if there is one. The
+    /// status is Created or Duplicate; a failed upload is an error instead.
+    pub fn upload_asset(&self, path: &Path) -> Result<(UploadStatus, String), BackupError> {
+        let metadata = fs::metadata(path)?;
+        let sidecar = sidecar_for(path);
+
+        let response = self.send(&format!("upload {}", path.display()), || {
+            let body = MultipartBody::for_asset(path, &metadata, sidecar.as_deref())
+                .map_err(|e| Box::new(ureq::Error::from(e)))?;
+            self.request("POST", "/assets")
+                .set("Content-Type", &body.content_type())
+                .set("Content-Length", &body.len().to_string())
+                .send(body.into_reader())
+                .map_err(Box::new)
+        })?;
+
+        let http_status = response.status();
+        let upload: UploadResponse = response.into_json().map_err(|e| {
+            BackupError::ImmichApiError(format!(
+                "Unexpected response to upload of {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        // Immich answers 201 for a new asset and 200 for a duplicate
+        let status = match upload.status.as_str() {
+            "created" => UploadStatus::Created,
+            "duplicate" => UploadStatus::Duplicate,
+            _ if http_status == 200 => UploadStatus::Duplicate,
+            _ => UploadStatus::Created,
+        };
+        Ok((status, upload.id))
+    }
+
+    /// Upload many files in parallel on the current rayon pool. Failures are
+    /// recorded per file rather than stopping the run.
+    pub fn upload_all(&self, files: &[PathBuf]) -> UploadReport {
+        let progress = ProgressBar::new(files.len() as u64);
+        progress.set_style(
+            ProgressStyle::default_bar()
+                .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta}) {msg}")
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("#>-"),
+        );
+        progress.set_message("Uploading to Immich");
+
+        let assets = files
+            .par_iter()
+            .map(|path| {
+                let upload = match self.upload_asset(path) {
+                    Ok((status, id)) => AssetUpload {
+                        path: path.clone(),
+                        status,
+                        asset_id: Some(id),
+                    },
+                    Err(e) => {
+                        warn!("Failed to upload {}: {}", path.display(), e);
+                        AssetUpload {
+                            path: path.clone(),
+                            status: UploadStatus::Error(e.to_string()),
+                            asset_id: None,
+                        }
+                    }
+                };
+                progress.inc(1);
+                upload
+            })
+            .collect();
+
+        progress.finish_with_message("Upload finished");
+        UploadReport { assets }
+    }
+
+    fn request(&self, method: &str, path: &str) -> ureq::Request {
+        self.agent
+            .request(method, &format!("{}{}", self.base_url, path))
+            .set("x-api-key", self.api_key.expose())
+            .set("Accept", "application/json")
+    }
+
+    /// Run a request, retrying transient failures with exponential backoff
+    fn send(
+        &self,
+        what: &str,
+        attempt: impl Fn() -> Result<ureq::Response, Box<ureq::Error>>,
+    ) -> Result<ureq::Response, BackupError> {
+        let attempts = self.retry.attempts.max(1);
+        let mut tries = 0;
+
+        loop {
+            tries += 1;
+            let error = match attempt().map_err(|e| *e) {
+                Ok(response) => return Ok(response),
+                Err(ureq::Error::Status(status, response)) => {
+                    let body = response.into_string().unwrap_or_default();
+                    let message = format!("{} returned HTTP {}: {}", what, status, body.trim());
+                    if status == 401 || status == 403 {
+                        return Err(BackupError::ImmichApiError(format!(
+                            "{} (is the API key correct?)",
+                            message
+                        )));
+                    }
+                    if status != 429 && status < 500 {
+                        return Err(BackupError::ImmichApiError(message));
+                    }
+                    message
+                }
+                Err(ureq::Error::Transport(e)) => format!("{} failed: {}", what, e),
+            };
+
+            if tries >= attempts {
+                return Err(BackupError::ImmichApiError(format!(
+                    "{} (gave up after {} attempts)",
+                    error, tries
+                )));
+            }
+
+            let delay = self.retry.delay(tries);
+            debug!("{}; retrying in {:?}", error, delay);
+            thread::sleep(delay);
+        }
+    }
+}
+
+/// The XMP sidecar next to a file, as either `IMG_0001.HEIC.xmp` or `IMG_0001.xmp`
+fn sidecar_for(path: &Path) -> Option<PathBuf> {
+    let file_name = path.file_name()?.to_string_lossy().to_string();
+    [
+        path.with_file_name(format!("{}.xmp", file_name)),
+        path.with_file_name(format!("{}.XMP", file_name)),
+        path.with_extension("xmp"),
+        path.with_extension("XMP"),
+    ]
+    .into_iter()
+    .find(|candidate| candidate.is_file())
+}
+
+fn timestamp(time: io::Result<SystemTime>) -> String {
+    let time = time.unwrap_or(UNIX_EPOCH);
+    DateTime::<Utc>::from(time).to_rfc3339()
+}
+
+/// A multipart/form-data request body that streams files from disk instead
+/// of reading them into memory
+struct MultipartBody {
+    boundary: String,
+    parts: Vec<Part>,
+}
+
+enum Part {
+    Bytes(Vec<u8>),
+    File(File, u64),
+}
+
+impl MultipartBody {
+    /// The fields Immich's `POST /assets` expects for one file
+    fn for_asset(path: &Path, metadata: &fs::Metadata, sidecar: Option<&Path>) -> io::Result<MultipartBody> {
+        let file_name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+        let modified = timestamp(metadata.modified());
+        let created = timestamp(metadata.created().or_else(|_| metadata.modified()));
+
+        let mut body = MultipartBody::new();
+        body.text("deviceAssetId", &format!("{}-{}", file_name, metadata.len()).replace(char::is_whitespace, ""));
+        body.text("deviceId", DEVICE_ID);
+        body.text("fileCreatedAt", &created);
+        body.text("fileModifiedAt", &modified);
+        body.text("isFavorite", "false");
+        body.file("assetData", path)?;
+        if let Some(sidecar) = sidecar {
+            body.file("sidecarData", sidecar)?;
+        }
+        Ok(body)
+    }
+
+    fn new() -> MultipartBody {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos())
+            .unwrap_or_default();
+        MultipartBody {
+            boundary: format!("----backup-photos-{:x}-{:x}", std::process::id(), nanos),
+            parts: Vec::new(),
+        }
+    }
+
+    fn text(&mut self, name: &str, value: &str) {
+        let part = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"\r\n\r\n{}\r\n",
+            self.boundary, name, value
+        );
+        self.parts.push(Part::Bytes(part.into_bytes()));
+    }
+
+    fn file(&mut self, name: &str, path: &Path) -> io::Result<()> {
+        let file = File::open(path)?;
+        let size = file.metadata()?.len();
+        let file_name = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .replace('"', "");
+        let header = format!(
+            "--{}\r\nContent-Disposition: form-data; name=\"{}\"; filename=\"{}\"\r\nContent-Type: application/octet-stream\r\n\r\n",
+            self.boundary, name, file_name
+        );
+        self.parts.push(Part::Bytes(header.into_bytes()));
+        self.parts.push(Part::File(file, size));
+        self.parts.push(Part::Bytes(b"\r\n".to_vec()));
+        Ok(())
+    }
+
+    fn closing(&self) -> Vec<u8> {
+        format!("--{}--\r\n", self.boundary).into_bytes()
+    }
+
+    fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    fn len(&self) -> u64 {
+        let parts: u64 = self
+            .parts
+            .iter()
+            .map(|part| match part {
+                Part::Bytes(bytes) => bytes.len() as u64,
+                Part::File(_, size) => *size,
+            })
+            .sum();
+        parts + self.closing().len() as u64
+    }
+
+    fn into_reader(self) -> impl Read {
+        let closing = self.closing();
+        self.parts
+            .into_iter()
+            .map(|part| -> Box<dyn Read + Send> {
+                match part {
+                    Part::Bytes(bytes) => Box::new(Cursor::new(bytes)),
+                    Part::File(file, size) => Box::new(file.take(size)),
+                }
+            })
+            .chain(std::iter::once(Box::new(Cursor::new(closing)) as Box<dyn Read + Send>))
+            .fold(Box::new(io::empty()) as Box<dyn Read + Send>, |body, part| {
+                Box::new(body.chain(part))
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::secrets::ApiKeySource;
+    use std::io::{BufRead, BufReader, Write};
+    use std::net::TcpListener;
+    use std::sync::mpsc;
+
+    /// A request as the mock server received it
+    struct Received {
+        request_line: String,
+        headers: String,
+        body: Vec<u8>,
+    }
+
+    /// Serve one canned response per connection, in order, on a local port,
+    /// and pass on every request received. Returns the server URL.
+    fn mock_server(responses: Vec<(u16, &'static str)>) -> (String, mpsc::Receiver<Received>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let (sender, receiver) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (status, body) in responses {
+                let (stream, _) = listener.accept().unwrap();
+                let mut reader = BufReader::new(stream);
+
+                let mut request_line = String::new();
+                reader.read_line(&mut request_line).unwrap();
+                let mut headers = String::new();
+                let mut length = 0;
+                loop {
+                    let mut line = String::new();
+                    reader.read_line(&mut line).unwrap();
+                    if line == "\r\n" || line.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = line.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            length = value.trim().parse().unwrap();
+                        }
+                    }
+                    headers.push_str(&line);
+                }
+                let mut request_body = vec![0; length];
+                reader.read_exact(&mut request_body).unwrap();
+                sender
+                    .send(Received {
+                        request_line: request_line.trim_end().to_string(),
+                        headers,
+                        body: request_body,
+                    })
+                    .unwrap();
+
+                let response = format!(
+                    "HTTP/1.1 {} Mock\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                    status,
+                    body.len(),
+                    body
+                );
+                reader.into_inner().write_all(response.as_bytes()).unwrap();
+            }
+        });
+
+        (url, receiver)
+    }
+
+    fn client(url: &str) -> ImmichClient {
+        let key = ApiKeySource::Value("test-key".to_string(), None).load().unwrap();
+        ImmichClient::new(url, key).with_retry(RetryPolicy {
+            attempts: 3,
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(5),
+        })
+    }
+
+    fn photo(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn upload_asset_reports_created() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = photo(dir.path(), "IMG_0001.HEIC", b"heic data");
+        photo(dir.path(), "IMG_0001.HEIC.xmp", b"<xmp/>");
+        let (url, received) = mock_server(vec![(201, r#"{"id":"asset-1","status":"created"}"#)]);
+
+        let (status, id) = client(&url).upload_asset(&file).unwrap();
+        assert_eq!(status, UploadStatus::Created);
+        assert_eq!(id, "asset-1");
+
+        let request = received.recv().unwrap();
+        assert_eq!(request.request_line, "POST /api/assets HTTP/1.1");
+        assert!(request.headers.to_lowercase().contains("x-api-key: test-key"));
+        let body = String::from_utf8_lossy(&request.body);
+        assert!(body.contains("name=\"assetData\"; filename=\"IMG_0001.HEIC\""));
+        assert!(body.contains("heic data"));
+        assert!(body.contains("name=\"sidecarData\"; filename=\"IMG_0001.HEIC.xmp\""));
+        assert!(body.contains("name=\"deviceId\"\r\n\r\nbackup-photos\r\n"));
+    }
+
+    #[test]
+    fn upload_asset_reports_duplicate() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = photo(dir.path(), "IMG_0002.JPG", b"jpeg data");
+        let (url, received) = mock_server(vec![(200, r#"{"id":"asset-0","status":"duplicate"}"#)]);
+
+        let (status, id) = client(&url).upload_asset(&file).unwrap();
+        assert_eq!(status, UploadStatus::Duplicate);
+        assert_eq!(id, "asset-0");
+        let body = String::from_utf8_lossy(&received.recv().unwrap().body).to_string();
+        assert!(!body.contains("sidecarData"));
+    }
+
+    #[test]
+    fn retries_server_errors_and_rate_limits() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = photo(dir.path(), "IMG_0003.JPG", b"jpeg data");
+        let (url, received) = mock_server(vec![
+            (503, "unavailable"),
+            (429, "slow down"),
+            (201, r#"{"id":"asset-3","status":"created"}"#),
+        ]);
+
+        let (status, id) = client(&url).upload_asset(&file).unwrap();
+        assert_eq!(status, UploadStatus::Created);
+        assert_eq!(id, "asset-3");
+        assert_eq!(received.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_attempt() {
+        let (url, received) = mock_server(vec![(500, "oops"), (502, "oops"), (500, "oops")]);
+
+        let error = client(&url).check_connection().unwrap_err().to_string();
+        assert!(error.contains("HTTP 500"), "{}", error);
+        assert!(error.contains("gave up after 3 attempts"), "{}", error);
+        assert_eq!(received.try_iter().count(), 3);
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_maximum() {
+        let retry = RetryPolicy {
+            attempts: 6,
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(3),
+        };
+        let delays: Vec<Duration> = (1..=4).map(|retry_number| retry.delay(retry_number)).collect();
+        assert_eq!(
+            delays,
+            [500, 1000, 2000, 3000].map(Duration::from_millis).to_vec()
+        );
+    }
+
+    #[test]
+    fn unauthorized_is_not_retried() {
+        let (url, received) = mock_server(vec![
+            (401, r#"{"message":"Invalid API key"}"#),
+            (200, r#"{"email":"someone@example.com"}"#),
+        ]);
+
+        let error = client(&url).check_connection().unwrap_err().to_string();
+        assert!(error.contains("HTTP 401"), "{}", error);
+        assert!(error.contains("is the API key correct?"), "{}", error);
+        assert!(!error.contains("gave up"), "{}", error);
+        assert_eq!(received.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn other_client_errors_are_not_retried() {
+        let (url, received) = mock_server(vec![(404, "not found"), (200, "{}")]);
+
+        let error = client(&url).check_connection().unwrap_err().to_string();
+        assert!(error.contains("HTTP 404"), "{}", error);
+        assert_eq!(received.try_iter().count(), 1);
+    }
+
+    #[test]
+    fn check_checksums_reads_bulk_upload_check() {
+        let (url, received) = mock_server(vec![(
+            200,
+            r#"{"results":[
+                {"id":"2","action":"reject","reason":"duplicate","assetId":"trashed-1","isTrashed":true},
+                {"id":"0","action":"reject","reason":"duplicate","assetId":"asset-1"},
+                {"id":"1","action":"accept"},
+                {"id":"7","action":"reject","reason":"duplicate","assetId":"unknown"},
+                {"id":"3","action":"reject","reason":"unsupported-format"}
+            ]}"#,
+        )]);
+        let checksums = ["aa", "bb", "cc", "dd"].map(String::from);
+
+        let presence = client(&url).check_checksums(&checksums).unwrap();
+        assert_eq!(
+            presence,
+            vec![
+                ServerPresence::Present { asset_id: "asset-1".to_string() },
+                ServerPresence::Missing,
+                ServerPresence::Trashed { asset_id: "trashed-1".to_string() },
+                ServerPresence::Missing,
+            ]
+        );
+
+        let request = received.recv().unwrap();
+        assert_eq!(request.request_line, "POST /api/assets/bulk-upload-check HTTP/1.1");
+        let sent: serde_json::Value = serde_json::from_slice(&request.body).unwrap();
+        assert_eq!(sent["assets"][0]["id"], "0");
+        assert_eq!(sent["assets"][3]["checksum"], "dd");
+    }
+}
@@ -0,0 +1,196 @@
+use crate::constants;
+use crate::manifest::{self, ManifestRecord};
+use crate::BackupError;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// One change made to the backup directory, with what is needed to undo it.
+/// Paths are relative to the backup root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum JournalEntry {
+    /// `path` was replaced by a hard link to `target`, which has the same content
+    Hardlinked {
+        path: PathBuf,
+        target: PathBuf,
+        sha256: String,
+        /// The replaced file's own modification time and permissions
+        modified: Option<DateTime<Utc>>,
+        readonly: bool,
+        at: DateTime<Utc>,
+    },
+    /// `path` was deleted because `kept` has the same content
+    Deleted {
+        path: PathBuf,
+        kept: PathBuf,
+        sha256: String,
+        size: u64,
+        modified: Option<DateTime<Utc>>,
+        readonly: bool,
+        /// The manifest record that was removed with the file
+        manifest: Option<ManifestRecord>,
+        at: DateTime<Utc>,
+    },
+    /// `path` was moved to the trash by sync
+    Trashed {
+        path: PathBuf,
+        /// Where it went, as an absolute path
+        trashed: PathBuf,
+        /// Its `.trashinfo` file, with the freedesktop.org trash
+        info: Option<PathBuf>,
+        sha256: String,
+        at: DateTime<Utc>,
+    },
+}
+
+impl JournalEntry {
+    /// The file the entry changed, relative to the backup root
+    pub fn path(&self) -> &Path {
+        match self {
+            JournalEntry::Hardlinked { path, .. }
+            | JournalEntry::Deleted { path, .. }
+            | JournalEntry::Trashed { path, .. } => path,
+        }
+    }
+}
+
+/// What undoing a session, or one file of it, put back
+#[derive(Debug, Default)]
+pub struct UndoSummary {
+    pub restored: Vec<PathBuf>,
+    pub failed: Vec<(PathBuf, String)>,
+    pub session: Option<String>,
+}
+
+/// Directory holding the journals of a backup directory
+pub fn journal_dir(backup_dir: &Path) -> PathBuf {
+    backup_dir
+        .join(constants::STATE_DIR_NAME)
+        .join(constants::JOURNAL_DIR_NAME)
+}
+
+/// Location of a session's journal
+fn journal_file(backup_dir: &Path, session: &str) -> PathBuf {
+    journal_dir(backup_dir).join(format!("{}.jsonl", session))
+}
+
+/// A JSON Lines record of one session's changes, stored in
+/// `<backup>/.backup-photos/journal/<session>.jsonl`. Every entry is flushed
+/// to disk as soon as it is written, so an interrupted session can still be
+/// undone up to its last change.
+#[derive(Debug)]
+pub struct Journal {
+    session: String,
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// Start a new journal for a run of `command`, named after the command
+    /// and the current time, e.g. `dedupe-20240501T120000`
+    pub fn create(backup_dir: &Path, command: &str) -> Result<Journal, BackupError> {
+        fs::create_dir_all(journal_dir(backup_dir))?;
+
+        let base = format!("{}-{}", command, manifest::new_run_id());
+        let mut session = base.clone();
+        let mut attempt = 1;
+        loop {
+            let path = journal_file(backup_dir, &session);
+            match OpenOptions::new().append(true).create_new(true).open(&path) {
+                Ok(file) => {
+                    return Ok(Journal {
+                        session,
+                        path,
+                        file,
+                    })
+                }
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                    attempt += 1;
+                    session = format!("{}-{}", base, attempt);
+                }
+                Err(e) => return Err(BackupError::IoError(e)),
+            }
+        }
+    }
+
+    pub fn session(&self) -> &str {
+        &self.session
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Append an entry and flush it to disk
+    pub fn append(&mut self, entry: &JournalEntry) -> Result<(), BackupError> {
+        let mut line = serde_json::to_vec(entry)
+            .map_err(|e| BackupError::IoError(std::io::Error::other(e)))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.sync_data()?;
+        Ok(())
+    }
+}
+
+/// Read the entries of a session's journal in the order they were written
+pub fn load(backup_dir: &Path, session: &str) -> Result<Vec<JournalEntry>, BackupError> {
+    let path = journal_file(backup_dir, session);
+    let file = match File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+            return Err(BackupError::JournalNotFound(session.to_string()));
+        }
+        Err(e) => return Err(BackupError::IoError(e)),
+    };
+
+    let mut entries = Vec::new();
+    for (number, line) in BufReader::new(file).lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let entry = serde_json::from_str(&line).map_err(|e| {
+            BackupError::IoError(std::io::Error::other(format!(
+                "line {} of {}: {}",
+                number + 1,
+                path.display(),
+                e
+            )))
+        })?;
+        entries.push(entry);
+    }
+    Ok(entries)
+}
+
+/// Sessions of `command` that have not been undone, oldest first
+pub fn sessions(backup_dir: &Path, command: &str) -> Result<Vec<String>, BackupError> {
+    let dir = journal_dir(backup_dir);
+    if !dir.exists() {
+        return Ok(Vec::new());
+    }
+
+    let prefix = format!("{}-", command);
+    let mut sessions = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let name = entry?.file_name().to_string_lossy().to_string();
+        if let Some(session) = name.strip_suffix(".jsonl") {
+            if session.starts_with(&prefix) && !session.ends_with(".undone") {
+                sessions.push(session.to_string());
+            }
+        }
+    }
+    // Session IDs start with a sortable timestamp
+    sessions.sort();
+    Ok(sessions)
+}
+
+/// Keep a fully undone session's journal as `<session>.undone.jsonl`, so it
+/// can't be undone twice
+pub fn mark_undone(backup_dir: &Path, session: &str) -> Result<PathBuf, BackupError> {
+    let undone = journal_dir(backup_dir).join(format!("{}.undone.jsonl", session));
+    fs::rename(journal_file(backup_dir, session), &undone)?;
+    Ok(undone)
+}
@@ -0,0 +1,419 @@
+use crate::asset::{self, Asset};
+use crate::copy::CopyJob;
+use crate::hash_cache::HashCache;
+use crate::metadata;
+use crate::BackupError;
+use chrono::{DateTime, Local, NaiveDateTime};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Mirror the export directory: the default layout
+pub const DEFAULT_LAYOUT: &str = "{dir}/{filename}";
+
+const PLACEHOLDERS: [&str; 8] = [
+    "year", "month", "day", "camera", "dir", "filename", "stem", "ext",
+];
+
+/// Folder name used for `{camera}` when a file doesn't say what took it
+const UNKNOWN_CAMERA: &str = "Unknown Camera";
+
+/// Where each file goes in the backup, as a template of `/`-separated path
+/// components with placeholders such as `{year}/{month}/{day}/{filename}`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Layout {
+    template: String,
+}
+
+impl Default for Layout {
+    fn default() -> Self {
+        Layout {
+            template: DEFAULT_LAYOUT.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Layout {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.template)
+    }
+}
+
+impl Layout {
+    /// Check a template: only known placeholders, a file name in the last
+    /// component that keeps the extension, and no absolute paths or `..`
+    pub fn parse(template: &str) -> Result<Layout, String> {
+        let template = template.trim().trim_matches('/');
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let end = rest[start..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed '{{' in '{}'", template))?;
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!(
+                    "unknown placeholder {{{}}} in '{}', expected one of {}",
+                    name,
+                    template,
+                    PLACEHOLDERS.map(|p| format!("{{{}}}", p)).join(", ")
+                ));
+            }
+            rest = &rest[start + end + 1..];
+        }
+
+        let last = template.rsplit('/').next().unwrap_or_default();
+        if !last.contains("{filename}") && !last.contains("{stem}") {
+            return Err(format!(
+                "'{}' must end with a file name containing {{filename}} or {{stem}}",
+                template
+            ));
+        }
+        // Without the extension, a Live Photo's HEIC and MOV would get the same name
+        if !last.contains("{filename}") && !last.contains("{ext}") {
+            return Err(format!(
+                "'{}' uses {{stem}} without {{ext}}, so files that differ only in extension would overwrite each other",
+                template
+            ));
+        }
+        if template.split('/').any(|part| part == ".." || part == ".") {
+            return Err(format!("'{}' must not contain '.' or '..'", template));
+        }
+
+        Ok(Layout {
+            template: template.to_string(),
+        })
+    }
+
+    fn uses(&self, placeholder: &str) -> bool {
+        self.template.contains(&format!("{{{}}}", placeholder))
+    }
+
+    fn uses_date(&self) -> bool {
+        self.uses("year") || self.uses("month") || self.uses("day")
+    }
+
+    /// The path of a file relative to the backup root. Empty components,
+    /// such as `{dir}` for a file at the top of the export, are dropped.
+    fn render(&self, fields: &Fields, file_name: &str) -> PathBuf {
+        let (stem, ext) = match file_name.rsplit_once('.') {
+            Some((stem, ext)) if !stem.is_empty() => (stem, ext),
+            _ => (file_name, ""),
+        };
+        let date = |format: &str| {
+            fields
+                .taken
+                .map(|taken| taken.format(format).to_string())
+                .unwrap_or_default()
+        };
+        let rendered = self
+            .template
+            .replace("{year}", &date("%Y"))
+            .replace("{month}", &date("%m"))
+            .replace("{day}", &date("%d"))
+            .replace("{camera}", &fields.camera)
+            .replace("{dir}", &fields.dir)
+            .replace("{filename}", file_name)
+            .replace("{stem}", stem)
+            .replace("{ext}", ext);
+
+        rendered
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect()
+    }
+}
+
+/// Values of the placeholders that are shared by every file of an asset
+struct Fields {
+    taken: Option<NaiveDateTime>,
+    camera: String,
+    /// The asset's directory relative to the export root, `/`-separated
+    dir: String,
+}
+
+impl Fields {
+    /// Read what the layout needs from the asset's first photo or video that
+    /// has it, falling back to the primary file's modification time
+    fn for_asset(layout: &Layout, asset: &Asset, export_root: &Path) -> Fields {
+        let metadata: Vec<metadata::MediaMetadata> = if layout.uses_date() || layout.uses("camera") {
+            asset
+                .media_files()
+                .into_iter()
+                .filter_map(|file| metadata::read_metadata(file).ok())
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let taken = if layout.uses_date() {
+            metadata
+                .iter()
+                .find_map(|metadata| metadata.captured_at)
+                .map(|time| time.local)
+                .or_else(|| {
+                    let modified = fs::metadata(&asset.primary).ok()?.modified().ok()?;
+                    Some(DateTime::<Local>::from(modified).naive_local())
+                })
+        } else {
+            None
+        };
+
+        let camera = if layout.uses("camera") {
+            metadata
+                .iter()
+                .find_map(|metadata| metadata.camera())
+                .map(|camera| sanitize(&camera))
+                .unwrap_or_else(|| UNKNOWN_CAMERA.to_string())
+        } else {
+            String::new()
+        };
+
+        let dir = asset
+            .primary
+            .parent()
+            .and_then(|parent| parent.strip_prefix(export_root).ok())
+            .map(|relative| {
+                relative
+                    .components()
+                    .filter_map(|component| match component {
+                        Component::Normal(part) => Some(part.to_string_lossy().to_string()),
+                        _ => None,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("/")
+            })
+            .unwrap_or_default();
+
+        Fields { taken, camera, dir }
+    }
+}
+
+/// Make a metadata value safe to use as a single path component
+fn sanitize(value: &str) -> String {
+    let cleaned: String = value
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | ':' | '\0' => '_',
+            c if c.is_control() => '_',
+            c => c,
+        })
+        .collect();
+    let cleaned = cleaned.trim().trim_matches('.').to_string();
+    if cleaned.is_empty() {
+        UNKNOWN_CAMERA.to_string()
+    } else {
+        cleaned
+    }
+}
+
+/// Give every copy job its destination under the layout. Files of an asset
+/// share their date and camera, so a Live Photo's halves land together, and
+/// sidecars go next to their photo.
+///
+/// A destination is only reused when it holds, or another file in the plan
+/// brings, the same content. Otherwise the whole asset is renamed with a
+/// suffix from its primary file's SHA-256, such as `IMG_0001_3f2a9c1b.HEIC`
+/// and `IMG_0001_3f2a9c1b.xmp`, and its jobs record the file they would have
+/// replaced. Assets are handled in order of export path, so the same export
+/// always gets the same names.
+pub fn apply(
+    layout: &Layout,
+    export_root: &Path,
+    backup_root: &Path,
+    jobs: Vec<CopyJob>,
+    cache: &mut HashCache,
+) -> Result<Vec<CopyJob>, BackupError> {
+    let assets = asset::group_assets(jobs.iter().map(|job| job.source.clone()));
+    let mut destinations: HashMap<PathBuf, (PathBuf, Option<PathBuf>)> = HashMap::new();
+    let mut claimed: HashMap<String, PathBuf> = HashMap::new();
+
+    for asset in &assets {
+        let fields = Fields::for_asset(layout, asset, export_root);
+        let primary_stem = asset
+            .primary
+            .file_stem()
+            .map(|stem| stem.to_string_lossy().to_string())
+            .unwrap_or_default();
+
+        let wanted: Vec<(PathBuf, PathBuf)> = plan_asset(layout, &fields, asset, &primary_stem, "")
+            .into_iter()
+            .map(|(source, destination)| (source, backup_root.join(destination)))
+            .collect();
+        let mut planned = wanted.clone();
+        let mut collided = false;
+
+        // Lengthen the hash suffix in the unlikely case a shorter one is also taken
+        for hash_len in [8, 16, 64] {
+            let mut conflict = false;
+            for (source, destination) in &planned {
+                if has_conflict(source, destination, &claimed, cache)? {
+                    conflict = true;
+                    break;
+                }
+            }
+            if !conflict {
+                break;
+            }
+            collided = true;
+            let hash = cache.hash(&asset.primary)?;
+            let suffix = format!("_{}", &hash[..hash_len.min(hash.len())]);
+            planned = plan_asset(layout, &fields, asset, &primary_stem, &suffix)
+                .into_iter()
+                .map(|(source, destination)| (source, backup_root.join(destination)))
+                .collect();
+        }
+
+        for ((source, destination), (_, original)) in planned.into_iter().zip(wanted) {
+            claimed
+                .entry(collision_key(&destination))
+                .or_insert_with(|| source.clone());
+            let replaced = collided.then_some(original);
+            destinations.insert(source, (destination, replaced));
+        }
+    }
+
+    Ok(jobs
+        .into_iter()
+        .map(|job| match destinations.remove(&job.source) {
+            Some((destination, collision)) => CopyJob {
+                destination,
+                collision,
+                ..job
+            },
+            None => job,
+        })
+        .collect())
+}
+
+/// Whether putting `source` at `destination` would clash with a different
+/// file, either already in the backup or claimed earlier in this plan
+fn has_conflict(
+    source: &Path,
+    destination: &Path,
+    claimed: &HashMap<String, PathBuf>,
+    cache: &mut HashCache,
+) -> Result<bool, BackupError> {
+    if let Some(other) = claimed.get(&collision_key(destination)) {
+        if !same_content(source, other, cache)? {
+            return Ok(true);
+        }
+    }
+    if destination.exists() && !same_content(source, destination, cache)? {
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/// Compare sizes first and only hash files that are the same size
+fn same_content(a: &Path, b: &Path, cache: &mut HashCache) -> Result<bool, BackupError> {
+    if a == b {
+        return Ok(true);
+    }
+    if fs::metadata(a)?.len() != fs::metadata(b)?.len() {
+        return Ok(false);
+    }
+    Ok(cache.hash(a)? == cache.hash(b)?)
+}
+
+/// Relative destinations for every file of an asset, with `suffix` added to
+/// each file's stem. Sidecars go in the primary's folder.
+fn plan_asset(
+    layout: &Layout,
+    fields: &Fields,
+    asset: &Asset,
+    primary_stem: &str,
+    suffix: &str,
+) -> Vec<(PathBuf, PathBuf)> {
+    let rename = |file: &Path| with_suffix(&file_name(file), suffix);
+    let primary = layout.render(fields, &rename(&asset.primary));
+    let primary_dir = primary.parent().map(Path::to_path_buf).unwrap_or_default();
+
+    let mut planned = vec![(asset.primary.clone(), primary.clone())];
+    if let Some(motion) = &asset.motion {
+        planned.push((motion.clone(), layout.render(fields, &rename(motion))));
+    }
+    for sidecar in &asset.sidecars {
+        let name = file_name(sidecar);
+        // IMG_1.HEIC.xmp and IMG_1.xmp follow the photo's new stem
+        let name = match name.get(..primary_stem.len()) {
+            Some(prefix) if !suffix.is_empty() && prefix.eq_ignore_ascii_case(primary_stem) => {
+                format!("{}{}{}", prefix, suffix, &name[primary_stem.len()..])
+            }
+            _ => name,
+        };
+        planned.push((sidecar.clone(), primary_dir.join(name)));
+    }
+    planned
+}
+
+fn file_name(path: &Path) -> String {
+    path.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// `IMG_1.HEIC` with suffix `_3f2a9c1b` becomes `IMG_1_3f2a9c1b.HEIC`
+fn with_suffix(name: &str, suffix: &str) -> String {
+    match name.rsplit_once('.') {
+        Some((stem, rest)) if !stem.is_empty() => format!("{}{}.{}", stem, suffix, rest),
+        _ => format!("{}{}", name, suffix),
+    }
+}
+
+/// Destinations that differ only in case are the same file on macOS
+fn collision_key(path: &Path) -> String {
+    path.to_string_lossy().to_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn job(source: PathBuf) -> CopyJob {
+        let size = fs::metadata(&source).unwrap().len();
+        CopyJob {
+            destination: PathBuf::new(),
+            source,
+            size,
+            collision: None,
+        }
+    }
+
+    #[test]
+    fn stem_without_ext_is_rejected() {
+        assert!(Layout::parse("{year}/{stem}").is_err());
+        assert!(Layout::parse("{stem}.jpg").is_err());
+        assert!(Layout::parse("{year}/{stem}.{ext}").is_ok());
+        assert!(Layout::parse("{year}/{filename}").is_ok());
+    }
+
+    #[test]
+    fn live_photo_halves_keep_their_own_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let export = dir.path().join("export");
+        let backup = dir.path().join("backup");
+        fs::create_dir_all(&export).unwrap();
+        fs::write(export.join("IMG_1.HEIC"), b"still").unwrap();
+        fs::write(export.join("IMG_1.MOV"), b"motion").unwrap();
+        fs::write(export.join("IMG_1.xmp"), b"<xmp/>").unwrap();
+        let jobs = ["IMG_1.HEIC", "IMG_1.MOV", "IMG_1.xmp"]
+            .iter()
+            .map(|name| job(export.join(name)))
+            .collect();
+
+        let layout = Layout::parse("{stem}.{ext}").unwrap();
+        let mut cache = HashCache::load(&dir.path().join("cache.json")).unwrap();
+        let jobs = apply(&layout, &export, &backup, jobs, &mut cache).unwrap();
+
+        let destinations: Vec<PathBuf> = jobs.into_iter().map(|job| job.destination).collect();
+        assert_eq!(
+            destinations,
+            vec![
+                backup.join("IMG_1.HEIC"),
+                backup.join("IMG_1.MOV"),
+                backup.join("IMG_1.xmp")
+            ]
+        );
+    }
+}
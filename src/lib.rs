@@ -1,6 +1,7 @@
 use anyhow::Result;
 use indicatif::{ProgressBar, ProgressStyle};
 use log::{debug, error, info, warn};
+use rayon::prelude::*;
 use sha2::{Digest, Sha256};
 use std::fs;
 use std::io::{BufReader, Read};
@@ -8,10 +9,34 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use thiserror::Error;
 use walkdir::WalkDir;
-use std::io;
 
+pub mod asset;
+pub mod config;
 pub mod constants;
-pub mod api_key;
+pub mod copy;
+pub mod dedupe;
+pub mod hash_cache;
+pub mod immich;
+pub mod journal;
+pub mod layout;
+pub mod manifest;
+pub mod media;
+pub mod metadata;
+pub mod perceptual;
+pub mod secrets;
+pub mod sniff;
+pub mod sync_plan;
+pub mod trash;
+pub mod tui;
+
+pub use asset::Asset;
+pub use config::Profile;
+pub use copy::CopySummary;
+pub use dedupe::DedupeMode;
+pub use hash_cache::HashCache;
+pub use immich::{ImmichClient, UploadReport};
+pub use manifest::Manifest;
+pub use media::MediaKind;
 
 #[derive(Error, Debug)]
 pub enum BackupError {
@@ -36,10 +61,85 @@ pub enum BackupError {
     #[error("Export directory is empty: {0}")]
     ExportDirEmpty(String),
 
+    #[error("Configuration error: {0}")]
+    ConfigError(String),
+
+    #[error("API key error: {0}")]
+    ApiKeyError(String),
+
+    #[error("{0} files failed to copy to the backup directory")]
+    CopyFailed(usize),
+
+    #[error("{} files in the export directory do not match their backup copy", .0.len())]
+    VerificationFailed(Vec<PathBuf>),
+
+    #[error("Immich API error: {0}")]
+    ImmichApiError(String),
+
+    #[error("{0} files could not be deleted from the export directory")]
+    ClearFailed(usize),
+
+    #[error("{0} files failed to upload to Immich")]
+    UploadFailed(usize),
+
+    #[error("{0} suspicious files found")]
+    SuspiciousFiles(usize),
+
+    #[error("No journal found for session {0}")]
+    JournalNotFound(String),
+
+    #[error("{0} files could not be deduplicated")]
+    DedupeFailed(usize),
+
+    #[error("{0} changes could not be undone")]
+    UndoFailed(usize),
+
+    #[error("Nothing to undo: {0}")]
+    NothingToUndo(String),
+
+    #[error("Invalid sync plan: {0}")]
+    InvalidPlan(String),
+
+    #[error("{0} assets of the plan could not be trashed")]
+    PlanFailed(usize),
+
+    #[error("Not in Immich after uploading: {0}")]
+    NotInImmich(String),
+
+    #[error("Could not decode image: {0}")]
+    DecodeFailed(String),
+
     #[error("I/O error: {0}")]
     IoError(#[from] std::io::Error),
 }
 
+/// Size the global rayon pool used for hashing and copying from the profile's
+/// `jobs` setting. Must be called before any parallel work starts.
+pub fn configure_thread_pool(profile: &Profile) -> Result<(), BackupError> {
+    let Some(jobs) = profile.jobs else {
+        return Ok(());
+    };
+
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs)
+        .build_global()
+        .map_err(|e| BackupError::ConfigError(format!("Failed to start {} worker threads: {}", jobs, e)))?;
+
+    debug!("Using {} worker threads", jobs);
+    Ok(())
+}
+
+/// Make every command recognise the extensions added in the profile on top
+/// of the built-in ones. Must be called before any files are classified.
+pub fn configure_media_types(profile: &Profile) -> Result<(), BackupError> {
+    if !media::configure(profile.media_types.clone()) {
+        return Err(BackupError::ConfigError(
+            "Media types were already in use before they were configured".to_string(),
+        ));
+    }
+    Ok(())
+}
+
 /// Checks if the provided path exists and is accessible
 pub fn check_directory_exists_and_accessible(path: &Path) -> Result<(), BackupError> {
     if !path.exists() {
@@ -107,37 +207,41 @@ pub fn check_external_drive_connected(path: &Path) -> Result<(), BackupError> {
     Ok(())
 }
 
-/// Initialize the required directories from environment variables
-pub fn init_directories() -> Result<(), BackupError> {
-    let vars = [
-        constants::APPLE_PHOTOS_EXPORT_DIR,
-        constants::RAW_PHOTOS_BACKUP_DIR,
-        constants::IMMICH_LIB,
+/// Initialize the required directories for a profile
+pub fn init_directories(profile: &Profile, dry_run: bool) -> Result<(), BackupError> {
+    let dirs = [
+        (&profile.export_dir, "export"),
+        (&profile.backup_dir, "backup"),
+        (&profile.immich_lib, "Immich library"),
     ];
 
-    for var in vars {
-        let path = PathBuf::from(&var);
-
+    for (path, desc) in dirs {
         // Check if directory already exists
         if path.exists() {
-            info!("Directory for {} already exists at {}", var, path.display());
+            info!("Directory for {} already exists at {}", desc, path.display());
             continue;
         }
 
         // Check if it's on an external drive (might not be plugged in)
-        if var.starts_with("/Volumes") {
+        if path.starts_with("/Volumes") {
             warn!("Path {} points to an external drive. Make sure the drive is connected before continuing.", path.display());
         }
 
+        if dry_run {
+            info!("[dry run] Would create directory for {} at {}", desc, path.display());
+            continue;
+        }
+
         // Create the directory
-        info!("Creating directory for {} at {}", var, path.display());
-        match fs::create_dir_all(&path) {
+        info!("Creating directory for {} at {}", desc, path.display());
+        match fs::create_dir_all(path) {
             Ok(_) => info!("Successfully created directory {}", path.display()),
             Err(e) => {
-                return Err(BackupError::IoError(std::io::Error::new(
-                    std::io::ErrorKind::Other,
-                    format!("Failed to create directory {}: {}", path.display(), e),
-                )));
+                return Err(BackupError::IoError(std::io::Error::other(format!(
+                    "Failed to create directory {}: {}",
+                    path.display(),
+                    e
+                ))));
             }
         }
     }
@@ -152,24 +256,15 @@ pub fn init_directories() -> Result<(), BackupError> {
 }
 
 
-/// Count files in a directory that match the given extensions
-pub fn count_files_with_extensions(path: &Path, extensions: &[&str]) -> Result<usize, BackupError> {
-    let mut count = 0;
-
-    for entry in WalkDir::new(path)
+/// Count files in a directory that are one of the given kinds of media
+pub fn count_files_of_kind(path: &Path, kinds: &[MediaKind]) -> Result<usize, BackupError> {
+    let count = WalkDir::new(path)
         .follow_links(true)
         .into_iter()
         .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if extensions.iter().any(|&e| e == ext_str) {
-                    count += 1;
-                }
-            }
-        }
-    }
+        .filter(|entry| entry.file_type().is_file())
+        .filter(|entry| MediaKind::of(entry.path()).is_some_and(|kind| kinds.contains(&kind)))
+        .count();
 
     Ok(count)
 }
@@ -178,44 +273,61 @@ pub fn count_files_with_extensions(path: &Path, extensions: &[&str]) -> Result<u
 /// Fixes XMP files exported by Apple Photos using exiftool.
 /// This repairs GPS and EXIF tags in all .xmp files in the given directory.
 /// Shows a progress bar for the number of XMP files processed.
-pub fn fix_apple_xmp_files(dir: &Path) -> Result<(), BackupError> {
-    // Count XMP files for progress bar
-    let mut xmp_count = 0;
+/// With `dry_run`, lists the files and the exiftool command instead of running it.
+pub fn fix_apple_xmp_files(dir: &Path, dry_run: bool) -> Result<(), BackupError> {
+    let mut xmp_files = Vec::new();
     for entry in WalkDir::new(dir)
         .follow_links(true)
+        .sort_by_file_name()
         .into_iter()
         .filter_map(|e| e.ok())
     {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                if ext.to_string_lossy().eq_ignore_ascii_case("xmp") {
-                    xmp_count += 1;
-                }
-            }
+        if entry.file_type().is_file() && MediaKind::of(entry.path()) == Some(MediaKind::Sidecar) {
+            xmp_files.push(entry.path().to_path_buf());
         }
     }
 
-    if xmp_count == 0 {
+    if xmp_files.is_empty() {
         info!("No XMP files found in {}", dir.display());
         return Ok(());
     }
 
-    info!("Found {} XMP files to repair in {}", xmp_count, dir.display());
-
+    info!("Found {} XMP files to repair in {}", xmp_files.len(), dir.display());
+
+    // Limit exiftool to the sidecar extensions found, which include any configured extras
+    let mut extensions: Vec<String> = xmp_files
+        .iter()
+        .filter_map(|file| file.extension())
+        .map(|ext| ext.to_string_lossy().to_lowercase())
+        .collect();
+    extensions.sort();
+    extensions.dedup();
+
+    let dir_arg = dir.to_string_lossy();
+    let mut args = vec!["-P", "-overwrite_original"];
+    for ext in &extensions {
+        args.extend(["-ext", ext.as_str()]);
+    }
+    args.extend([
+        "-XMP-exif:All=",
+        "-tagsFromFile", "@",
+        "-XMP-exif:All",
+        "-XMP-exif:GPSLongitude<${XMP-exif:GPSLongitude#}${XMP-exif:GPSLongitudeRef#}",
+        "-XMP-exif:GPSLatitude<${XMP-exif:GPSLatitude#}${XMP-exif:GPSLatitudeRef#}",
+        dir_arg.as_ref(),
+    ]);
+
+    if dry_run {
+        for file in &xmp_files {
+            info!("[dry run] Would rewrite {}", file.display());
+        }
+        info!("[dry run] Would run: exiftool {}", args.join(" "));
+        return Ok(());
+    }
 
     // exiftool will process all .xmp files in the directory recursively
     let status = Command::new("exiftool")
-        .args([
-            "-P",
-            "-overwrite_original",
-            "-ext", "xmp",
-            "-XMP-exif:All=",
-            "-tagsFromFile", "@",
-            "-XMP-exif:All",
-            "-XMP-exif:GPSLongitude<${XMP-exif:GPSLongitude#}${XMP-exif:GPSLongitudeRef#}",
-            "-XMP-exif:GPSLatitude<${XMP-exif:GPSLatitude#}${XMP-exif:GPSLatitudeRef#}",
-            dir.to_string_lossy().as_ref(),
-        ])
+        .args(args)
         .current_dir(dir)
         .stdout(Stdio::inherit())
         .stderr(Stdio::inherit())
@@ -238,51 +350,46 @@ pub fn fix_apple_xmp_files(dir: &Path) -> Result<(), BackupError> {
     Ok(())
 }
 
-/// Backup photos and videos from export directory to backup directory
-pub fn backup_photos_to_raw_dir() -> Result<(), BackupError> {
-    let export_dir = PathBuf::from(constants::APPLE_PHOTOS_EXPORT_DIR);
-    let backup_dir = PathBuf::from(constants::RAW_PHOTOS_BACKUP_DIR);
-
-    let photo_extensions = [
-        "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-    ];
-    let video_extensions = [
-        "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts", "m2ts",
-    ];
-    let metadata_extensions = ["xmp"];
-    let all_extensions = [
-        &photo_extensions[..],
-        &video_extensions[..],
-        &metadata_extensions[..],
-    ]
-    .concat();
-
-    // Count files to process
-    let file_count = count_files_with_extensions(&export_dir, &all_extensions)?;
-
-    if file_count == 0 {
-        return Err(BackupError::NoPhotosFound);
-    }
-
-    info!(
-        "Found {} photos/videos and metadata files to backup",
-        file_count
-    );
-
-    // Create progress bar
-    let progress = ProgressBar::new(file_count as u64);
-    match progress.set_style(
+/// A progress bar counting files, in the style every command shares
+fn progress_bar(len: u64) -> ProgressBar {
+    let progress = ProgressBar::new(len);
+    progress.set_style(
         ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
-            )
+            .template("{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})")
             .unwrap_or_else(|_| ProgressStyle::default_bar())
             .progress_chars("#>-"),
-    ) {
-        _ => {} // Ignore any styling errors
+    );
+    progress
+}
+
+/// Map every media and XMP file in the export directory to its place in the
+/// backup, following the profile's layout. With `dry_run`, hashes computed
+/// for the layout aren't saved to the hash cache.
+fn backup_plan(profile: &Profile, dry_run: bool) -> Result<Vec<copy::CopyJob>, BackupError> {
+    let jobs = copy::plan_copy(&profile.export_dir, &profile.backup_dir, |path| {
+        MediaKind::of(path).is_some()
+    })?;
+    let mut cache = HashCache::open()?;
+    let jobs = layout::apply(
+        &profile.layout,
+        &profile.export_dir,
+        &profile.backup_dir,
+        jobs,
+        &mut cache,
+    )?;
+    if !dry_run {
+        cache.save()?;
     }
+    Ok(jobs)
+}
+
+/// Backup photos and videos from export directory to backup directory.
+/// Files that already exist in the backup are skipped. With `dry_run`, the
+/// summary lists what would be copied and skipped and nothing is written.
+pub fn backup_photos_to_raw_dir(profile: &Profile, dry_run: bool) -> Result<CopySummary, BackupError> {
+    let export_dir = &profile.export_dir;
+    let backup_dir = &profile.backup_dir;
 
-    // Run rsync command for backup
     // First, check if the export directory exists and has files
     if !export_dir.exists() {
         return Err(BackupError::DirectoryNotFound(
@@ -291,7 +398,7 @@ pub fn backup_photos_to_raw_dir() -> Result<(), BackupError> {
     }
 
     // Check if there are any files in the export directory
-    let has_files = fs::read_dir(&export_dir)?.next().is_some();
+    let has_files = fs::read_dir(export_dir)?.next().is_some();
     if !has_files {
         return Err(BackupError::ExportDirEmpty(format!(
             "The export directory '{}' exists but is empty. Have you exported photos from Apple Photos?",
@@ -299,274 +406,987 @@ pub fn backup_photos_to_raw_dir() -> Result<(), BackupError> {
         )));
     }
 
+    let jobs = backup_plan(profile, dry_run)?;
+
+    if jobs.is_empty() {
+        return Err(BackupError::NoPhotosFound);
+    }
+
+    let assets = asset::group_assets(jobs.iter().map(|job| job.source.clone()));
+    info!(
+        "Found {} photos/videos and metadata files to backup ({} assets, {} Live Photos)",
+        jobs.len(),
+        assets.len(),
+        assets.iter().filter(|asset| asset.is_live_photo()).count()
+    );
     debug!(
-        "Running rsync from {} to {}",
+        "Copying from {} to {}",
         export_dir.display(),
         backup_dir.display()
     );
 
-     let mut child = Command::new("rsync")
-        .args([
-            "-av", // archive mode, verbose
-            "--progress", // show live progress
-            "--ignore-existing", // skip files already in destination
-            &format!("{}/", export_dir.display()), // source dir contents
-            &format!("{}/", backup_dir.display()), // destination dir
-        ])
-        .stdout(Stdio::inherit()) // stream stdout to terminal
-        .stderr(Stdio::inherit()) // stream stderr to terminal
-        .spawn()
-        .map_err(|e| BackupError::IoError(io::Error::new(io::ErrorKind::Other, format!("Failed to spawn rsync: {e}"))))?;
+    let sources: Vec<PathBuf> = jobs.iter().map(|job| job.source.clone()).collect();
+    let suspects = find_suspicious_files(&sources);
+    if !suspects.is_empty() {
+        report_suspicious_files(&suspects);
+        warn!("These files will be backed up as they are. Run 'backup-photos doctor' for a full report.");
+    }
 
-    let status = child
-        .wait()
-        .map_err(|e| BackupError::IoError(io::Error::new(io::ErrorKind::Other, format!("Failed to wait on rsync: {e}"))))?;
+    if dry_run {
+        let summary = copy::print_plan(&jobs);
+        info!(
+            "[dry run] Would copy {} files ({} bytes) and skip {} already in backup",
+            summary.copied.len(),
+            summary.bytes_copied,
+            summary.skipped.len()
+        );
+        report_collisions(&summary);
+        return Ok(summary);
+    }
 
-    progress.finish_with_message("Backup completed");
+    let export_run = manifest::new_run_id();
+    let summary = copy::copy_files(&jobs);
 
-    if !status.success() {
-        return Err(BackupError::CommandFailed(format!("rsync exited with status: {}", status)));
+    info!(
+        "Backup finished: {} copied ({} bytes), {} already in backup, {} failed",
+        summary.copied.len(),
+        summary.bytes_copied,
+        summary.skipped.len(),
+        summary.failed.len()
+    );
+    for (file, e) in &summary.failed {
+        error!("  - {}: {}", file.display(), e);
     }
+    report_incomplete_assets(&assets, &summary);
+    report_collisions(&summary);
 
-    info!("Successfully backed up photos and videos to raw directory");
+    record_in_manifest(profile, &jobs, &summary, &export_run)?;
 
+    Ok(summary)
+}
+
+/// List the files that were kept under a new name because a different file
+/// already had theirs
+fn report_collisions(summary: &CopySummary) {
+    if summary.collisions.is_empty() {
+        return;
+    }
+
+    warn!(
+        "{} files have the same name as a different file and were kept under a new name:",
+        summary.collisions.len()
+    );
+    for job in &summary.collisions {
+        warn!(
+            "  - {} -> {} ({} is a different file)",
+            job.source.display(),
+            job.destination.display(),
+            job.collision.as_deref().unwrap_or(&job.destination).display()
+        );
+    }
+}
+
+/// Warn about assets that only made it into the backup in part, such as a
+/// Live Photo whose video failed to copy
+fn report_incomplete_assets(assets: &[Asset], summary: &CopySummary) {
+    let failed: std::collections::HashSet<&Path> =
+        summary.failed.iter().map(|(file, _)| file.as_path()).collect();
+    let incomplete: Vec<&Asset> = assets
+        .iter()
+        .filter(|asset| asset.files().iter().any(|file| failed.contains(file)))
+        .collect();
+    if incomplete.is_empty() {
+        return;
+    }
+
+    error!("{} assets are only partly backed up:", incomplete.len());
+    for asset in incomplete {
+        error!("  - {}", asset);
+    }
+}
+
+/// Add newly copied files, and backed-up files the manifest doesn't know yet,
+/// to the backup manifest
+fn record_in_manifest(
+    profile: &Profile,
+    jobs: &[copy::CopyJob],
+    summary: &CopySummary,
+    export_run: &str,
+) -> Result<(), BackupError> {
+    let mut manifest = Manifest::open(&profile.backup_dir)?;
+    let mut cache = HashCache::open()?;
+
+    let copied: std::collections::HashSet<&PathBuf> = summary.copied.iter().collect();
+    let to_record: Vec<&copy::CopyJob> = jobs
+        .iter()
+        .filter(|job| job.destination.exists())
+        .filter(|job| {
+            copied.contains(&job.source)
+                || manifest
+                    .get(&backup_relative_path(profile, &job.destination))
+                    .is_none()
+        })
+        .collect();
+
+    if to_record.is_empty() {
+        return Ok(());
+    }
+
+    info!("Recording {} files in the backup manifest", to_record.len());
+    let destinations: Vec<PathBuf> = to_record.iter().map(|job| job.destination.clone()).collect();
+    let progress = progress_bar(destinations.len() as u64);
+    let hashes = cache.hash_all(&destinations, &progress);
+    progress.finish_and_clear();
+
+    for (job, hash) in to_record.into_iter().zip(hashes) {
+        match hash {
+            Ok(hash) => manifest.record(
+                backup_relative_path(profile, &job.destination),
+                hash,
+                job.size,
+                capture_time(&job.source),
+                job.source.clone(),
+                export_run,
+            ),
+            Err(e) => warn!(
+                "Failed to hash {} for the manifest: {}",
+                job.destination.display(),
+                e
+            ),
+        }
+    }
+
+    cache.save()?;
+    manifest.save()?;
+    debug!("Manifest {} now holds {} records", manifest.path().display(), manifest.len());
     Ok(())
 }
 
-/// Import photos and videos to Immich using the Immich CLI
-pub fn import_to_immich() -> Result<(), BackupError> {
-    let export_dir = PathBuf::from(constants::APPLE_PHOTOS_EXPORT_DIR);
-    let immich_lib = PathBuf::from(constants::IMMICH_LIB);
+/// A file's path relative to the backup root, as stored in the manifest
+fn backup_relative_path(profile: &Profile, path: &Path) -> PathBuf {
+    path.strip_prefix(&profile.backup_dir)
+        .unwrap_or(path)
+        .to_path_buf()
+}
+
+/// Best known capture time of a file, falling back to its modification time
+fn capture_time(path: &Path) -> Option<chrono::DateTime<chrono::Utc>> {
+    metadata::capture_time(path)
+        .map(|time| time.to_utc())
+        .or_else(|| {
+            fs::metadata(path)
+                .and_then(|metadata| metadata.modified())
+                .ok()
+                .map(chrono::DateTime::from)
+        })
+}
+
+/// Import photos and videos to Immich by uploading them, with their XMP
+/// sidecars, through the REST API of `profile.immich_server`. Files that fail
+/// are listed in the returned report rather than stopping the run.
+/// With `dry_run`, lists the files that would be uploaded and where to.
+pub fn import_to_immich(profile: &Profile, dry_run: bool) -> Result<UploadReport, BackupError> {
+    let export_dir = &profile.export_dir;
 
     info!("Reparing XMP to import photos and videos to Immich");
-    fix_apple_xmp_files(&export_dir)?;
+    fix_apple_xmp_files(export_dir, dry_run)?;
 
-    // Import photos and videos to Immich
-    // You'll need to modify this section based on your specific Immich CLI commands
     info!(
-        "Importing media to Immich from {} to {}",
-        export_dir.display(),
-        immich_lib.display()
+        "Importing media to Immich at {} from {}",
+        profile.immich_server,
+        export_dir.display()
     );
 
-    // Count the media files to be imported
-    let photo_extensions = [
-        "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-    ];
-    let video_extensions = [
-        "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts", "m2ts",
-    ];
-    let all_media_extensions = [&photo_extensions[..], &video_extensions[..]].concat();
-
-    let file_count = count_files_with_extensions(&export_dir, &all_media_extensions)?;
+    let mut files = list_media_files(export_dir);
+    files.sort();
 
-    if file_count == 0 {
+    if files.is_empty() {
         warn!("No photos or videos found in export directory for import to Immich");
-        return Ok(());
+        return Ok(UploadReport::default());
+    }
+
+    info!("Found {} photos and videos to import to Immich", files.len());
+
+    if dry_run {
+        for file in &files {
+            info!("[dry run] Would upload {}", file.display());
+        }
+        info!(
+            "[dry run] Would upload {} files to {} using the API key from {}",
+            files.len(),
+            profile.immich_server,
+            profile
+                .immich_api_key
+                .as_ref()
+                .map(|source| source.to_string())
+                .unwrap_or_else(|| "<not configured>".to_string())
+        );
+        return Ok(UploadReport::default());
     }
 
-    info!("Found {} photos and videos to import to Immich", file_count);
+    let client = ImmichClient::new(&profile.immich_server, load_api_key(profile)?);
+    let user = client.check_connection()?;
+    debug!("Connected to {} as {}", client.base_url(), user);
+
+    let report = client.upload_all(&files);
 
-    let output = Command::new("immich-go")
-    .args([
-            "-k", &api_key::API_KEY,
-            "--server", &constants::IMMICH_SERVER,
-            "upload",
-            "from-folder",
-            constants::APPLE_PHOTOS_EXPORT_DIR,
-        ])
-        .output()?;
     info!(
-        "Immich CLI output: {}",
-        String::from_utf8_lossy(&output.stdout)
+        "Upload finished: {} created, {} already in Immich, {} failed",
+        report.created(),
+        report.duplicates(),
+        report.errors().len()
     );
+    for (file, e) in report.errors() {
+        error!("  - {}: {}", file.display(), e);
+    }
 
-    if !output.status.success() {
-        let stderr = String::from_utf8_lossy(&output.stderr);
-        return Err(BackupError::CommandFailed(stderr.to_string()));
+    Ok(report)
+}
+
+/// Export files sorted into those proven to be safely stored elsewhere and those that aren't
+#[derive(Debug, Default)]
+pub struct ClearPlan {
+    pub deletable: Vec<PathBuf>,
+    pub unverified: Vec<(PathBuf, String)>,
+}
+
+/// Work out which export files can be deleted: a file qualifies only when a
+/// file with the same SHA-256 is in the backup directory and, if
+/// `require_immich` is set, photos and videos are also in the Immich library.
+/// With `dry_run`, the hash cache isn't saved.
+pub fn plan_clear(
+    profile: &Profile,
+    require_immich: bool,
+    dry_run: bool,
+) -> Result<ClearPlan, BackupError> {
+    let jobs = backup_plan(profile, dry_run)?;
+    let mut plan = ClearPlan::default();
+
+    if jobs.is_empty() {
+        return Ok(plan);
     }
 
-    Ok(())
+    let mut cache = HashCache::open()?;
+
+    // The manifest only says where to look: a backup copy counts by the hash of
+    // what is on disk now, so a corrupted or replaced copy proves nothing
+    let manifest = Manifest::open(&profile.backup_dir)?;
+    let mut recorded: Vec<PathBuf> = manifest
+        .records()
+        .map(|record| profile.backup_dir.join(&record.path))
+        .filter(|file| file.is_file())
+        .collect();
+    recorded.sort();
+    recorded.dedup();
+    info!("Hashing {} files recorded in the backup manifest", recorded.len());
+    let progress = progress_bar(recorded.len() as u64);
+    let recorded_hashes = cache.hash_all(&recorded, &progress);
+    progress.finish_and_clear();
+    let backup_hashes: std::collections::HashSet<String> = recorded
+        .iter()
+        .zip(recorded_hashes)
+        .filter_map(|(file, hash)| match hash {
+            Ok(hash) => Some(hash),
+            Err(e) => {
+                warn!("Could not hash backup file {}: {}", file.display(), e);
+                None
+            }
+        })
+        .collect();
+
+    let immich_hashes: std::collections::HashSet<String> = if require_immich {
+        let immich_files = list_media_files(&immich_upload_dir(profile));
+        info!("Hashing {} files in the Immich library", immich_files.len());
+        let progress = progress_bar(immich_files.len() as u64);
+        let hashes = cache.hash_all(&immich_files, &progress);
+        progress.finish_and_clear();
+        hashes.into_iter().filter_map(|hash| hash.ok()).collect()
+    } else {
+        std::collections::HashSet::new()
+    };
+
+    info!("Hashing {} files in the export directory", jobs.len());
+    let sources: Vec<PathBuf> = jobs.iter().map(|job| job.source.clone()).collect();
+    let progress = progress_bar(sources.len() as u64);
+    let source_hashes = cache.hash_all(&sources, &progress);
+    progress.finish_and_clear();
+
+    for (job, hash) in jobs.iter().zip(source_hashes) {
+        let hash = match hash {
+            Ok(hash) => hash,
+            Err(e) => {
+                plan.unverified
+                    .push((job.source.clone(), format!("could not be hashed: {}", e)));
+                continue;
+            }
+        };
+
+        // Fall back to the file's usual place in the backup for files the manifest doesn't know
+        let in_backup = backup_hashes.contains(hash.as_str())
+            || (job.destination.exists()
+                && cache.hash(&job.destination).ok().as_deref() == Some(hash.as_str()));
+        if !in_backup {
+            plan.unverified
+                .push((job.source.clone(), "not found in the backup directory".to_string()));
+            continue;
+        }
+
+        let is_sidecar = MediaKind::of(&job.source) == Some(MediaKind::Sidecar);
+        if require_immich && !is_sidecar && !immich_hashes.contains(&hash) {
+            plan.unverified
+                .push((job.source.clone(), "not found in the Immich library".to_string()));
+            continue;
+        }
+
+        plan.deletable.push(job.source.clone());
+    }
+
+    if !dry_run {
+        cache.save()?;
+    }
+    keep_assets_whole(&mut plan);
+    Ok(plan)
 }
 
-/// Clear the export directory
-pub fn clear_export_directory() -> Result<(), BackupError> {
-    let export_dir = PathBuf::from(constants::APPLE_PHOTOS_EXPORT_DIR);
+/// Keep every file of an asset when any of its files has to be kept, so a
+/// Live Photo or a photo and its sidecar are never split up
+fn keep_assets_whole(plan: &mut ClearPlan) {
+    let kept: std::collections::HashMap<PathBuf, String> = plan
+        .unverified
+        .iter()
+        .map(|(file, _)| {
+            let name = file.file_name().unwrap_or_default().to_string_lossy().to_string();
+            (file.clone(), name)
+        })
+        .collect();
+    if kept.is_empty() {
+        return;
+    }
 
-    let photo_extensions = [
-        "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-    ];
-    let video_extensions = [
-        "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts", "m2ts",
-    ];
-    let metadata_extensions = ["xmp"];
-    let all_extensions = [
-        &photo_extensions[..],
-        &video_extensions[..],
-        &metadata_extensions[..],
-    ]
-    .concat();
+    let all_files = plan
+        .deletable
+        .iter()
+        .chain(plan.unverified.iter().map(|(file, _)| file))
+        .cloned();
+    for asset in asset::group_assets(all_files) {
+        let Some(reason) = asset
+            .files()
+            .iter()
+            .find_map(|file| kept.get(*file))
+            .map(|name| format!("kept with {}, which belongs to the same asset", name))
+        else {
+            continue;
+        };
+        plan.deletable.retain(|file| {
+            if asset.contains(file) {
+                plan.unverified.push((file.clone(), reason.clone()));
+                false
+            } else {
+                true
+            }
+        });
+    }
+}
 
-    let file_count = count_files_with_extensions(&export_dir, &all_extensions)?;
+/// Log the files a clear would keep, and why
+fn report_unverified(plan: &ClearPlan) {
+    if plan.unverified.is_empty() {
+        return;
+    }
 
-    if file_count == 0 {
+    warn!(
+        "{} files could not be verified as safely stored and will be kept:",
+        plan.unverified.len()
+    );
+    for (file, reason) in &plan.unverified {
+        warn!("  - {}: {}", file.display(), reason);
+    }
+}
+
+/// Clear the export directory
+/// Shows what a forced clear would delete and keep, without deleting anything
+pub fn clear_export_directory(
+    profile: &Profile,
+    require_immich: bool,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    let plan = plan_clear(profile, require_immich, dry_run)?;
+
+    if plan.deletable.is_empty() && plan.unverified.is_empty() {
         info!("No media files found in export directory");
         return Ok(());
     }
 
+    report_unverified(&plan);
+
     // Ask for confirmation before deleting files
-    warn!("About to delete {} files from export directory", file_count);
+    warn!(
+        "About to delete {} verified files from export directory",
+        plan.deletable.len()
+    );
     info!("Please manually confirm by running with --force flag");
 
     Ok(())
 }
 
-/// Clear the export directory with force option
-pub fn clear_export_directory_force() -> Result<(), BackupError> {
-    let export_dir = PathBuf::from(constants::APPLE_PHOTOS_EXPORT_DIR);
+/// Clear the export directory with force option.
+/// Only files verified by `plan_clear` are deleted unless `unsafe_all` is set,
+/// in which case every media and XMP file goes without any checks.
+pub fn clear_export_directory_force(
+    profile: &Profile,
+    require_immich: bool,
+    unsafe_all: bool,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    if unsafe_all {
+        return clear_export_directory_unchecked(profile, dry_run);
+    }
 
-    let photo_extensions = [
-        "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-    ];
-    let video_extensions = [
-        "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts", "m2ts",
-    ];
-    let metadata_extensions = ["xmp"];
-    let all_extensions = [
-        &photo_extensions[..],
-        &video_extensions[..],
-        &metadata_extensions[..],
-    ]
-    .concat();
+    let plan = plan_clear(profile, require_immich, dry_run)?;
+    report_unverified(&plan);
+
+    if dry_run {
+        for file in &plan.deletable {
+            info!("[dry run] Would delete {}", file.display());
+        }
+        info!(
+            "[dry run] Would delete {} files and keep {} unverified files",
+            plan.deletable.len(),
+            plan.unverified.len()
+        );
+        return Ok(());
+    }
+
+    let mut deleted_count = 0;
+    let mut failed = Vec::new();
+    for file in &plan.deletable {
+        match fs::remove_file(file) {
+            Ok(()) => {
+                debug!("Deleted {}", file.display());
+                deleted_count += 1;
+            }
+            Err(e) => failed.push((file, e)),
+        }
+    }
+
+    info!(
+        "Deleted {} files from export directory, kept {} unverified files",
+        deleted_count,
+        plan.unverified.len()
+    );
+    if !failed.is_empty() {
+        error!("{} files could not be deleted:", failed.len());
+        for (file, e) in &failed {
+            error!("  - {}: {}", file.display(), e);
+        }
+        return Err(BackupError::ClearFailed(failed.len()));
+    }
+
+    Ok(())
+}
+
+/// Delete every media and XMP file in the export directory without checking the backup
+fn clear_export_directory_unchecked(profile: &Profile, dry_run: bool) -> Result<(), BackupError> {
+    let export_dir = &profile.export_dir;
+
+    warn!("Deleting every media file in {} without verifying the backup", export_dir.display());
 
     let mut deleted_count = 0;
+    let mut failed_count = 0;
 
-    for entry in WalkDir::new(&export_dir)
+    for entry in WalkDir::new(export_dir)
         .follow_links(true)
         .into_iter()
         .filter_map(|e| e.ok())
     {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if all_extensions.iter().any(|e| *e == ext_str) {
-                    fs::remove_file(entry.path())?;
-                    deleted_count += 1;
-                }
+        if entry.file_type().is_file() && MediaKind::of(entry.path()).is_some() {
+            if dry_run {
+                info!("[dry run] Would delete {}", entry.path().display());
+            } else if let Err(e) = fs::remove_file(entry.path()) {
+                error!("Could not delete {}: {}", entry.path().display(), e);
+                failed_count += 1;
+                continue;
             }
+            deleted_count += 1;
         }
     }
 
-    info!("Deleted {} files from export directory", deleted_count);
+    if dry_run {
+        info!("[dry run] Would delete {} files from export directory", deleted_count);
+    } else {
+        info!("Deleted {} files from export directory", deleted_count);
+    }
+    if failed_count > 0 {
+        return Err(BackupError::ClearFailed(failed_count));
+    }
 
     Ok(())
 }
 
 /// Calculate SHA-256 hash of a file
 fn calculate_file_hash(path: &Path) -> Result<String, BackupError> {
+    let mut hasher = Sha256::new();
+    read_for_hashing(path, |chunk| hasher.update(chunk))?;
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Calculate the SHA-256 and SHA-1 hashes of a file in a single read.
+/// Immich identifies assets by SHA-1, everything else here uses SHA-256.
+fn calculate_file_hashes(path: &Path) -> Result<(String, String), BackupError> {
+    let mut sha256 = Sha256::new();
+    let mut sha1 = sha1::Sha1::new();
+    read_for_hashing(path, |chunk| {
+        sha256.update(chunk);
+        sha1.update(chunk);
+    })?;
+    Ok((format!("{:x}", sha256.finalize()), format!("{:x}", sha1.finalize())))
+}
+
+/// Feed a file's contents to `update` in 1MB chunks
+fn read_for_hashing(path: &Path, mut update: impl FnMut(&[u8])) -> Result<(), BackupError> {
     let file = fs::File::open(path).map_err(|e| {
-        BackupError::IoError(std::io::Error::new(
-            std::io::ErrorKind::Other,
-            format!("Failed to open file for hashing: {}", e),
-        ))
+        BackupError::IoError(std::io::Error::other(format!(
+            "Failed to open file for hashing: {}",
+            e
+        )))
     })?;
 
     let mut reader = BufReader::new(file);
-    let mut hasher = Sha256::new();
-    let mut buffer = [0; 1024 * 1024]; // 1MB buffer for reading
+    let mut buffer = vec![0; 1024 * 1024]; // 1MB buffer for reading
 
     loop {
         let bytes_read = reader.read(&mut buffer).map_err(|e| {
-            BackupError::IoError(std::io::Error::new(
-                std::io::ErrorKind::Other,
-                format!("Failed to read file for hashing: {}", e),
-            ))
+            BackupError::IoError(std::io::Error::other(format!(
+                "Failed to read file for hashing: {}",
+                e
+            )))
         })?;
 
         if bytes_read == 0 {
             break;
         }
 
-        hasher.update(&buffer[..bytes_read]);
+        update(&buffer[..bytes_read]);
     }
 
-    let hash = hasher.finalize();
-    Ok(format!("{:x}", hash))
+    Ok(())
 }
 
-/// Find files in backup directory that are not in Immich library using content hashing
-pub fn find_files_not_in_immich() -> Result<Vec<PathBuf>, BackupError> {
-    let backup_dir = PathBuf::from(constants::RAW_PHOTOS_BACKUP_DIR);
-    let immich_lib = PathBuf::from(constants::IMMICH_LIB);
+/// Check that every media and XMP file in the export directory has a
+/// byte-identical copy in the backup, comparing SHA-256 hashes.
+/// Returns the number of files verified. With `dry_run`, the results aren't
+/// stamped in the manifest.
+pub fn verify_backup(profile: &Profile, dry_run: bool) -> Result<usize, BackupError> {
+    let jobs = backup_plan(profile, dry_run)?;
 
-    // Get all media files from backup directory (explicitly excluding XMP files)
-    let mut backup_files = Vec::new();
-    let photo_extensions = [
-        "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-    ];
-    let video_extensions = [
-        "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts", "m2ts",
-    ];
-    let all_media_extensions = [&photo_extensions[..], &video_extensions[..]].concat();
+    info!(
+        "Verifying {} files in {} against {}",
+        jobs.len(),
+        profile.export_dir.display(),
+        profile.backup_dir.display()
+    );
 
-    for entry in WalkDir::new(&backup_dir)
-        .follow_links(true)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if all_media_extensions.iter().any(|e| *e == ext_str) {
-                    backup_files.push(entry.path().to_path_buf());
+    let progress = progress_bar(jobs.len() as u64);
+
+    let results: Vec<Result<String, String>> = jobs
+        .par_iter()
+        .map(|job| {
+            let result = verify_copy(job);
+            progress.inc(1);
+            result
+        })
+        .collect();
+
+    progress.finish_with_message("Verification completed");
+
+    // Stamp every match in the manifest so the archive records when it was last checked
+    let mut manifest = Manifest::open(&profile.backup_dir)?;
+    let export_run = manifest::new_run_id();
+    let verified_at = chrono::Utc::now();
+    let mut mismatches = Vec::new();
+
+    for (job, result) in jobs.iter().zip(results) {
+        match result {
+            Ok(hash) => {
+                let relative = backup_relative_path(profile, &job.destination);
+                if !manifest.mark_verified(&relative, &hash, verified_at) {
+                    manifest.record(
+                        relative.clone(),
+                        hash.clone(),
+                        job.size,
+                        capture_time(&job.source),
+                        job.source.clone(),
+                        &export_run,
+                    );
+                    manifest.mark_verified(&relative, &hash, verified_at);
                 }
             }
+            Err(reason) => mismatches.push((job.source.clone(), reason)),
         }
     }
 
-    info!(
-        "Found {} media files in backup directory",
-        backup_files.len()
-    );
+    if dry_run {
+        info!("[dry run] Would record the verification in the backup manifest");
+    } else {
+        manifest.save()?;
+    }
 
-    // Find all media files in Immich library
-    let upload_dir = immich_lib.join("upload");
-    let mut immich_files = Vec::new();
+    if mismatches.is_empty() {
+        info!("All {} files match their backup copy", jobs.len());
+        return Ok(jobs.len());
+    }
 
-    for entry in WalkDir::new(&upload_dir)
+    error!("{} files failed verification:", mismatches.len());
+    for (file, reason) in &mismatches {
+        error!("  - {}: {}", file.display(), reason);
+    }
+
+    Err(BackupError::VerificationFailed(
+        mismatches.into_iter().map(|(file, _)| file).collect(),
+    ))
+}
+
+/// Compare one export file with its backup copy, returning the shared hash
+/// or a description of the difference
+fn verify_copy(job: &copy::CopyJob) -> Result<String, String> {
+    if !job.destination.exists() {
+        return Err(format!("missing from backup ({})", job.destination.display()));
+    }
+
+    let source_hash = calculate_file_hash(&job.source).map_err(|e| e.to_string())?;
+    let backup_hash = calculate_file_hash(&job.destination).map_err(|e| e.to_string())?;
+
+    if source_hash != backup_hash {
+        return Err(format!(
+            "content differs from {} (export {}, backup {})",
+            job.destination.display(),
+            source_hash,
+            backup_hash
+        ));
+    }
+
+    Ok(backup_hash)
+}
+
+/// List photos and videos under a directory (explicitly excluding XMP files)
+fn list_media_files(dir: &Path) -> Vec<PathBuf> {
+    WalkDir::new(dir)
         .follow_links(true)
         .into_iter()
         .filter_map(|e| e.ok())
-    {
-        if entry.file_type().is_file() {
-            if let Some(ext) = entry.path().extension() {
-                let ext_str = ext.to_string_lossy().to_lowercase();
-                if all_media_extensions.iter().any(|e| *e == ext_str) {
-                    immich_files.push(entry.path().to_path_buf());
-                }
-            }
+        .filter(|entry| entry.file_type().is_file() && asset::is_media(entry.path()))
+        .map(|entry| entry.path().to_path_buf())
+        .collect()
+}
+
+/// Check files for empty, unreadable or misnamed contents, with a progress bar
+fn find_suspicious_files(files: &[PathBuf]) -> Vec<sniff::Suspect> {
+    let progress = progress_bar(files.len() as u64);
+    let suspects = sniff::check_all(files, &progress);
+    progress.finish_and_clear();
+    suspects
+}
+
+fn report_suspicious_files(suspects: &[sniff::Suspect]) {
+    if suspects.is_empty() {
+        return;
+    }
+
+    warn!("{} files look damaged or misnamed:", suspects.len());
+    for suspect in suspects {
+        warn!("  - {}: {}", suspect.path.display(), suspect.problem);
+    }
+}
+
+/// Check every photo, video and sidecar in the export directory, and in the
+/// backup directory with `include_backup`, for files that are empty,
+/// unreadable, or whose contents don't match their extension. Logs a report
+/// grouped by problem and returns the suspicious files.
+pub fn doctor(profile: &Profile, include_backup: bool) -> Result<Vec<sniff::Suspect>, BackupError> {
+    let mut dirs = vec![profile.export_dir.as_path()];
+    if include_backup {
+        dirs.push(profile.backup_dir.as_path());
+    }
+
+    let mut suspects = Vec::new();
+    for dir in dirs {
+        check_directory_exists_and_accessible(dir)?;
+        let files: Vec<PathBuf> = WalkDir::new(dir)
+            .follow_links(true)
+            .sort_by_file_name()
+            .into_iter()
+            .filter_map(|e| e.ok())
+            .filter(|entry| entry.file_type().is_file() && MediaKind::of(entry.path()).is_some())
+            .map(|entry| entry.path().to_path_buf())
+            .collect();
+        info!("Checking {} files in {}", files.len(), dir.display());
+        suspects.extend(find_suspicious_files(&files));
+    }
+
+    if suspects.is_empty() {
+        info!("No suspicious files found");
+        return Ok(suspects);
+    }
+
+    for category in sniff::Problem::CATEGORIES {
+        let group: Vec<&sniff::Suspect> = suspects
+            .iter()
+            .filter(|suspect| suspect.problem.category() == category)
+            .collect();
+        if group.is_empty() {
+            continue;
+        }
+        warn!("{} ({}):", category, group.len());
+        for suspect in group {
+            warn!("  - {}: {}", suspect.path.display(), suspect.problem);
+        }
+    }
+
+    Ok(suspects)
+}
+
+/// Find photos, videos and sidecars in the backup directory with identical
+/// content and log the plan for `mode`. Hard linking and deleting only
+/// happen with `force` and without `dry_run`; every change goes to a journal
+/// whose session ID `undo_dedupe` takes.
+pub fn dedupe_backup(
+    profile: &Profile,
+    mode: DedupeMode,
+    force: bool,
+    dry_run: bool,
+) -> Result<dedupe::DedupeSummary, BackupError> {
+    let backup_dir = &profile.backup_dir;
+    check_directory_exists_and_accessible(backup_dir)?;
+
+    let files: Vec<PathBuf> = WalkDir::new(backup_dir)
+        .sort_by_file_name()
+        .into_iter()
+        .filter_entry(|entry| entry.file_name() != constants::STATE_DIR_NAME)
+        .filter_map(|e| e.ok())
+        .filter(|entry| entry.file_type().is_file() && MediaKind::of(entry.path()).is_some())
+        .map(|entry| entry.path().to_path_buf())
+        .collect();
+    info!("Looking for duplicates among {} files in {}", files.len(), backup_dir.display());
+
+    let mut manifest = Manifest::open(backup_dir)?;
+    let mut cache = HashCache::open()?;
+    let progress = progress_bar(0);
+    let mut groups = dedupe::find_duplicates(backup_dir, &files, &manifest, &mut cache, &progress);
+    progress.finish_and_clear();
+    if !dry_run {
+        cache.save()?;
+    }
+    if mode == DedupeMode::KeepOldest {
+        for (file, reason) in dedupe::keep_assets_whole(&mut groups, &files) {
+            info!("Keeping {}: {}", file.display(), reason);
+        }
+    }
+
+    if groups.is_empty() {
+        info!("No duplicates found");
+        return Ok(dedupe::DedupeSummary::default());
+    }
+    dedupe::print_plan(backup_dir, &groups, mode);
+
+    if mode == DedupeMode::Report {
+        return Ok(dedupe::DedupeSummary::default());
+    }
+    if dry_run {
+        info!("[dry run] Nothing was changed");
+        return Ok(dedupe::DedupeSummary::default());
+    }
+    if !force {
+        info!("Run again with --force to carry out this plan");
+        return Ok(dedupe::DedupeSummary::default());
+    }
+
+    let total = groups.iter().map(|group| group.duplicates.len()).sum::<usize>();
+    let progress = progress_bar(total as u64);
+    let summary = dedupe::apply(backup_dir, &groups, mode, &mut manifest, &progress)?;
+    progress.finish_and_clear();
+
+    info!(
+        "Deduplicated {} files ({} bytes freed), {} failed",
+        summary.changed.len(),
+        summary.bytes_freed,
+        summary.failed.len()
+    );
+    if let Some(session) = &summary.session {
+        info!("Undo with: backup-photos dedupe --undo {}", session);
+    }
+    Ok(summary)
+}
+
+/// Reverse a dedupe session from its journal. With `dry_run`, only logs what
+/// would be restored.
+pub fn undo_dedupe(
+    profile: &Profile,
+    session: &str,
+    dry_run: bool,
+) -> Result<dedupe::DedupeSummary, BackupError> {
+    check_directory_exists_and_accessible(&profile.backup_dir)?;
+    let mut manifest = Manifest::open(&profile.backup_dir)?;
+    let summary = dedupe::undo(&profile.backup_dir, session, &mut manifest, dry_run)?;
+
+    let verb = if dry_run { "Would restore" } else { "Restored" };
+    info!(
+        "{} {} files, {} could not be restored",
+        verb,
+        summary.changed.len(),
+        summary.failed.len()
+    );
+    Ok(summary)
+}
+
+/// Where Immich keeps uploaded originals inside its library
+fn immich_upload_dir(profile: &Profile) -> PathBuf {
+    profile.immich_lib.join("upload")
+}
+
+/// Where to look for the files Immich already has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum CompareSource {
+    /// Hash the originals in the local Immich upload folder
+    #[default]
+    Filesystem,
+    /// Ask the Immich server by SHA-1 checksum
+    Server,
+}
+
+/// How backup files are matched with the files Immich has
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, clap::ValueEnum)]
+pub enum MatchMode {
+    /// Only byte-identical files match
+    #[default]
+    Exact,
+    /// Photos without an identical copy are also compared by how they look,
+    /// to catch re-encoded or edited copies
+    Perceptual,
+}
+
+/// How sync asks what to do with each asset
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SyncInterface {
+    /// One prompt per asset on stdin, for any kind of input
+    Prompt,
+    /// A full-screen list of all the assets on the terminal
+    Screen,
+    /// The full-screen list driven by a key script and drawn off-screen,
+    /// printing the final screen
+    Scripted(tui::ScriptedKeys),
+}
+
+/// Find the assets in the backup that Immich doesn't have, either by hashing
+/// its upload folder on disk or by asking the server. An asset only counts as
+/// missing when none of its photos or videos are in Immich, so a Live Photo
+/// whose still was imported is never reported because of its video.
+/// With `dry_run`, the hashes computed along the way aren't saved.
+pub fn find_files_not_in_immich(
+    profile: &Profile,
+    source: CompareSource,
+    dry_run: bool,
+) -> Result<Vec<Asset>, BackupError> {
+    let mut cache = HashCache::open()?;
+
+    let backup_assets = asset::scan_assets(&profile.backup_dir);
+    let backup_files: Vec<PathBuf> = backup_assets
+        .iter()
+        .flat_map(|asset| asset.media_files())
+        .map(Path::to_path_buf)
+        .collect();
+    info!(
+        "Found {} media files in backup directory ({} assets)",
+        backup_files.len(),
+        backup_assets.len()
+    );
+
+    let all_files: Vec<PathBuf> = backup_assets
+        .iter()
+        .flat_map(|asset| asset.files())
+        .map(Path::to_path_buf)
+        .collect();
+    report_suspicious_files(&find_suspicious_files(&all_files));
+
+    let missing_files: std::collections::HashSet<PathBuf> = match source {
+        CompareSource::Filesystem => {
+            missing_from_immich_folder(profile, &mut cache, &backup_files, dry_run)?
+        }
+        CompareSource::Server => missing_from_immich_server(profile, &mut cache, &backup_files)?,
+    }
+    .into_iter()
+    .collect();
+
+    if !dry_run {
+        cache.save()?;
+    }
+
+    let mut assets_not_in_immich = Vec::new();
+    let mut partly_in_immich = Vec::new();
+    for asset in backup_assets {
+        let media = asset.media_files();
+        let missing = media.iter().filter(|file| missing_files.contains(**file)).count();
+        if media.is_empty() || missing == 0 {
+            continue;
+        }
+        if missing == media.len() {
+            assets_not_in_immich.push(asset);
+        } else {
+            partly_in_immich.push(asset);
+        }
+    }
+
+    if !partly_in_immich.is_empty() {
+        warn!(
+            "{} assets from backup are only partly in Immich library:",
+            partly_in_immich.len()
+        );
+        for asset in &partly_in_immich {
+            warn!("  - {}", asset);
+        }
+    }
+
+    if assets_not_in_immich.is_empty() {
+        info!("All media files from backup are present in Immich library (based on content hash)");
+    } else {
+        warn!(
+            "{} assets from backup are not in Immich library:",
+            assets_not_in_immich.len()
+        );
+        for asset in assets_not_in_immich.iter().take(10) {
+            warn!("  - {}", asset);
+        }
+        if assets_not_in_immich.len() > 10 {
+            warn!("  ... and {} more", assets_not_in_immich.len() - 10);
         }
     }
 
+    Ok(assets_not_in_immich)
+}
+
+/// Backup files whose SHA-256 doesn't match any file in the Immich upload
+/// folder. Hashes come from the cache and are only recomputed for changed files.
+fn missing_from_immich_folder(
+    profile: &Profile,
+    cache: &mut HashCache,
+    backup_files: &[PathBuf],
+    dry_run: bool,
+) -> Result<Vec<PathBuf>, BackupError> {
+    // Find all media files in Immich library
+    let immich_files = list_media_files(&immich_upload_dir(profile));
+
     info!("Found {} media files in Immich library", immich_files.len());
-    info!("Calculating hashes for Immich files (this may take a while)...");
+    info!(
+        "Calculating hashes for Immich files (this may take a while, {} hashes cached)...",
+        cache.len()
+    );
 
     // Create a HashSet of Immich file hashes
     let mut immich_hashes = std::collections::HashSet::new();
-    let immich_progress = ProgressBar::new(immich_files.len() as u64);
-    match immich_progress.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
-            )
-            .unwrap_or_else(|_| ProgressStyle::default_bar())
-            .progress_chars("#>-"),
-    ) {
-        _ => {} // Ignore any styling errors
-    }
+    let immich_progress = progress_bar(immich_files.len() as u64);
 
-    for immich_file in &immich_files {
-        match calculate_file_hash(&immich_file) {
+    let immich_results = cache.hash_all(&immich_files, &immich_progress);
+    for (immich_file, result) in immich_files.iter().zip(immich_results) {
+        match result {
             Ok(hash) => {
                 immich_hashes.insert(hash);
             }
@@ -574,28 +1394,21 @@ pub fn find_files_not_in_immich() -> Result<Vec<PathBuf>, BackupError> {
                 warn!("Failed to hash file {}: {}", immich_file.display(), e);
             }
         }
-        immich_progress.inc(1);
     }
 
     immich_progress.finish_with_message("Immich file hashing completed");
+    if !dry_run {
+        cache.save()?;
+    }
 
     // Compare files by content hash
     info!("Comparing backup files with Immich library by content hash...");
     let mut files_not_in_immich = Vec::new();
-    let progress = ProgressBar::new(backup_files.len() as u64);
-    match progress.set_style(
-        ProgressStyle::default_bar()
-            .template(
-                "{spinner:.green} [{elapsed_precise}] [{bar:40.cyan/blue}] {pos}/{len} ({eta})",
-            )
-            .unwrap_or_else(|_| ProgressStyle::default_bar())
-            .progress_chars("#>-"),
-    ) {
-        _ => {} // Ignore any styling errors
-    }
+    let progress = progress_bar(backup_files.len() as u64);
 
-    for backup_file in &backup_files {
-        match calculate_file_hash(&backup_file) {
+    let backup_results = cache.hash_all(backup_files, &progress);
+    for (backup_file, result) in backup_files.iter().zip(backup_results) {
+        match result {
             Ok(hash) => {
                 if !immich_hashes.contains(&hash) {
                     files_not_in_immich.push(backup_file.clone());
@@ -611,44 +1424,244 @@ pub fn find_files_not_in_immich() -> Result<Vec<PathBuf>, BackupError> {
                 files_not_in_immich.push(backup_file.clone());
             }
         }
-
-        progress.inc(1);
     }
 
     progress.finish_with_message("Comparison completed");
+    Ok(files_not_in_immich)
+}
 
-    if files_not_in_immich.is_empty() {
-        info!("All media files from backup are present in Immich library (based on content hash)");
+/// Backup files whose SHA-1 the Immich server doesn't know. Files only found
+/// in Immich's trash count as missing.
+fn missing_from_immich_server(
+    profile: &Profile,
+    cache: &mut HashCache,
+    backup_files: &[PathBuf],
+) -> Result<Vec<PathBuf>, BackupError> {
+    let client = ImmichClient::new(&profile.immich_server, load_api_key(profile)?);
+
+    info!("Calculating SHA-1 checksums of backup files ({} hashes cached)...", cache.len());
+    let progress = progress_bar(backup_files.len() as u64);
+
+    let mut files_not_in_immich = Vec::new();
+    let mut checked_files = Vec::new();
+    let mut checksums = Vec::new();
+    for (backup_file, result) in backup_files.iter().zip(cache.sha1_all(backup_files, &progress)) {
+        match result {
+            Ok(sha1) => {
+                checked_files.push(backup_file);
+                checksums.push(sha1);
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to hash backup file {}: {}",
+                    backup_file.display(),
+                    e
+                );
+                // Add file to not found list since we couldn't verify it
+                files_not_in_immich.push(backup_file.clone());
+            }
+        }
+    }
+    progress.finish_with_message("Checksums calculated");
+
+    info!("Asking {} which of {} checksums it has...", client.base_url(), checksums.len());
+    let presence = client.check_checksums(&checksums)?;
+    for (backup_file, presence) in checked_files.into_iter().zip(presence) {
+        match presence {
+            immich::ServerPresence::Present { .. } => {}
+            immich::ServerPresence::Trashed { asset_id } => {
+                debug!("{} is in the Immich trash as {}", backup_file.display(), asset_id);
+                files_not_in_immich.push(backup_file.clone());
+            }
+            immich::ServerPresence::Missing => files_not_in_immich.push(backup_file.clone()),
+        }
+    }
+
+    Ok(files_not_in_immich)
+}
+
+/// Look for photos in the Immich upload folder that look like the photos of
+/// assets Immich has no identical copy of, such as a HEIC re-exported as
+/// JPEG. Each asset is matched by its primary photo, in every quarter turn,
+/// and the best match at or above the profile's `min_similarity` is
+/// returned, keyed by primary file. With `dry_run`, the perceptual hashes
+/// aren't saved to the hash cache.
+pub fn find_near_matches(
+    profile: &Profile,
+    assets: &[Asset],
+    dry_run: bool,
+) -> Result<std::collections::HashMap<PathBuf, perceptual::NearMatch>, BackupError> {
+    let photos: Vec<&Path> = assets
+        .iter()
+        .map(|asset| asset.primary.as_path())
+        .filter(|file| MediaKind::of(file) == Some(MediaKind::Photo))
+        .collect();
+    if photos.is_empty() {
+        return Ok(Default::default());
+    }
+
+    let immich_photos: Vec<PathBuf> = list_media_files(&immich_upload_dir(profile))
+        .into_iter()
+        .filter(|file| MediaKind::of(file) == Some(MediaKind::Photo))
+        .collect();
+
+    info!(
+        "Computing perceptual hashes of {} photos in Immich library...",
+        immich_photos.len()
+    );
+    let mut cache = HashCache::open()?;
+    let progress = progress_bar(immich_photos.len() as u64);
+    let mut candidates = Vec::new();
+    for (file, result) in immich_photos.iter().zip(cache.perceptual_all(&immich_photos, &progress)) {
+        match result {
+            Ok(hash) => candidates.push((file.clone(), hash)),
+            Err(e) => debug!("No perceptual hash for {}: {}", file.display(), e),
+        }
+    }
+    progress.finish_and_clear();
+    if !dry_run {
+        cache.save()?;
+    }
+    if candidates.is_empty() && !immich_photos.is_empty() {
+        warn!("Could not decode any photo in the Immich library, so no near matches can be found");
+        return Ok(Default::default());
+    }
+
+    info!("Comparing {} backup photos with Immich library by appearance...", photos.len());
+    let progress = progress_bar(photos.len() as u64);
+    let hashed: Vec<(PathBuf, [perceptual::PerceptualHash; 4])> = photos
+        .par_iter()
+        .filter_map(|file| {
+            let result = perceptual::hash_file_rotations(file);
+            progress.inc(1);
+            match result {
+                Ok(hashes) => Some((file.to_path_buf(), hashes)),
+                Err(e) => {
+                    warn!("Could not decode {}: {}", file.display(), e);
+                    None
+                }
+            }
+        })
+        .collect();
+    progress.finish_and_clear();
+
+    let min_similarity = f64::from(profile.min_similarity) / 100.0;
+    let near_matches = perceptual::best_matches(&hashed, &candidates, min_similarity);
+    if near_matches.is_empty() {
+        info!("None of them look like a photo in the Immich library");
     } else {
         warn!(
-            "{} media files from backup are not in Immich library:",
-            files_not_in_immich.len()
+            "{} of them look like photos already in the Immich library:",
+            near_matches.len()
         );
-        for file in files_not_in_immich.iter().take(10) {
-            warn!("  - {}", file.display());
+        for near in &near_matches {
+            warn!("  - {}", near);
         }
-        if files_not_in_immich.len() > 10 {
-            warn!("  ... and {} more", files_not_in_immich.len() - 10);
+    }
+
+    Ok(near_matches
+        .into_iter()
+        .map(|near| (near.file.clone(), near))
+        .collect())
+}
+
+/// Rehash every media file in the backup directory and Immich library,
+/// replacing whatever the hash cache held for them.
+/// Returns the number of files hashed. With `dry_run`, the files are hashed
+/// but the cache file is left as it was.
+pub fn rebuild_hash_cache(profile: &Profile, dry_run: bool) -> Result<usize, BackupError> {
+    let mut cache = HashCache::open()?;
+    let upload_dir = immich_upload_dir(profile);
+
+    let removed = cache.remove_under(&profile.backup_dir) + cache.remove_under(&upload_dir);
+    debug!("Dropped {} cached hashes", removed);
+
+    let mut files = list_media_files(&profile.backup_dir);
+    files.extend(list_media_files(&upload_dir));
+    info!("Hashing {} media files", files.len());
+
+    let progress = progress_bar(files.len() as u64);
+
+    let mut hashed = 0;
+    for (file, result) in files.iter().zip(cache.hash_all(&files, &progress)) {
+        match result {
+            Ok(_) => hashed += 1,
+            Err(e) => warn!("Failed to hash file {}: {}", file.display(), e),
         }
     }
 
-    Ok(files_not_in_immich)
+    progress.finish_with_message("Hashing completed");
+    if dry_run {
+        info!("[dry run] Would save {} entries to {}", cache.len(), cache.path().display());
+        return Ok(hashed);
+    }
+    cache.save()?;
+
+    info!("Hash cache at {} now holds {} entries", cache.path().display(), cache.len());
+    Ok(hashed)
 }
 
-/// Compare files between backup directory and Immich library
-pub fn compare_backup_to_immich() -> Result<(), BackupError> {
-    find_files_not_in_immich()?;
+/// Remove hash cache entries for files that were deleted or changed.
+/// Returns the number of entries removed. With `dry_run`, they are only counted.
+pub fn prune_hash_cache(dry_run: bool) -> Result<usize, BackupError> {
+    let mut cache = HashCache::open()?;
+    let removed = cache.prune();
+    if dry_run {
+        info!("[dry run] Would remove {} stale entries from {}", removed, cache.path().display());
+        return Ok(removed);
+    }
+    cache.save()?;
 
+    info!(
+        "Removed {} stale entries, {} remain in {}",
+        removed,
+        cache.len(),
+        cache.path().display()
+    );
+    Ok(removed)
+}
+
+/// Compare files between backup directory and Immich library. With
+/// `MatchMode::Perceptual`, missing photos are also compared with the Immich
+/// upload folder by appearance and near matches are reported. With
+/// `dry_run`, the hash cache is left untouched.
+pub fn compare_backup_to_immich(
+    profile: &Profile,
+    source: CompareSource,
+    matching: MatchMode,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    check_match_mode(source, matching)?;
+    let assets = find_files_not_in_immich(profile, source, dry_run)?;
+    if matching == MatchMode::Perceptual && !assets.is_empty() {
+        find_near_matches(profile, &assets, dry_run)?;
+    }
+
+    Ok(())
+}
+
+/// Perceptual matching needs the photos themselves, which only the upload folder has
+fn check_match_mode(source: CompareSource, matching: MatchMode) -> Result<(), BackupError> {
+    if source == CompareSource::Server && matching == MatchMode::Perceptual {
+        return Err(BackupError::ConfigError(
+            "--match perceptual compares photos with the Immich upload folder and can't be used with --source server".to_string(),
+        ));
+    }
     Ok(())
 }
 
 /// Run the entire backup workflow
-pub fn full_backup_workflow() -> Result<(), BackupError> {
+/// With `dry_run`, prints the backup and import plans and skips verification.
+pub fn full_backup_workflow(profile: &Profile, dry_run: bool) -> Result<(), BackupError> {
     info!("Starting full backup workflow");
 
     // Step 1: Backup photos to raw directory
     info!("Step 1: Backing up photos to raw directory");
-    match backup_photos_to_raw_dir() {
+    match backup_photos_to_raw_dir(profile, dry_run) {
+        Ok(summary) if !summary.failed.is_empty() => {
+            error!("Not importing to Immich until every file is backed up");
+            return Err(BackupError::CopyFailed(summary.failed.len()));
+        }
         Ok(_) => info!("Successfully backed up photos to raw directory"),
         Err(e) => {
             error!("Failed to backup photos: {}", e);
@@ -656,9 +1669,27 @@ pub fn full_backup_workflow() -> Result<(), BackupError> {
         }
     }
 
+    if dry_run {
+        info!("[dry run] Would verify the backup before importing to Immich");
+    } else {
+        info!("Verifying backup before importing to Immich");
+        match verify_backup(profile, dry_run) {
+            Ok(count) => info!("Verified {} files in the backup", count),
+            Err(e) => {
+                error!("Backup verification failed, not importing to Immich: {}", e);
+                return Err(e);
+            }
+        }
+    }
+
     // Step 2: Import photos to Immich
     info!("Step 2: Importing photos to Immich");
-    match import_to_immich() {
+    match import_to_immich(profile, dry_run) {
+        Ok(report) if !report.errors().is_empty() => {
+            let e = BackupError::UploadFailed(report.errors().len());
+            error!("Failed to import photos to Immich: {}", e);
+            return Err(e);
+        }
         Ok(_) => info!("Successfully imported photos to Immich"),
         Err(e) => {
             error!("Failed to import photos to Immich: {}", e);
@@ -666,105 +1697,458 @@ pub fn full_backup_workflow() -> Result<(), BackupError> {
         }
     }
 
-    // Step 3: Compare backup to Immich
-    info!("Step 3: Comparing backup to Immich library");
-    match compare_backup_to_immich() {
-        Ok(_) => info!("Successfully compared backup to Immich library"),
-        Err(e) => {
-            error!("Failed to compare backup to Immich library: {}", e);
-            return Err(e);
-        }
+    // Step 3: Compare backup to Immich
+    info!("Step 3: Comparing backup to Immich library");
+    match compare_backup_to_immich(
+        profile,
+        CompareSource::Filesystem,
+        MatchMode::Exact,
+        dry_run,
+    ) {
+        Ok(_) => info!("Successfully compared backup to Immich library"),
+        Err(e) => {
+            error!("Failed to compare backup to Immich library: {}", e);
+            return Err(e);
+        }
+    }
+
+    // Step 4: Clear export directory (prompt for confirmation)
+    info!("Step 4: Clearing export directory");
+    info!("Please run the clear command separately with the --force flag to confirm deletion");
+
+    info!("Full backup workflow completed successfully");
+    Ok(())
+}
+
+/// Log when, where and with what camera a photo or video was taken
+fn log_media_metadata(file: &Path) {
+    let metadata = match metadata::read_metadata(file) {
+        Ok(metadata) => metadata,
+        Err(e) => {
+            warn!("Could not read metadata: {}", e);
+            return;
+        }
+    };
+    match metadata.captured_at {
+        Some(time) => info!("Taken: {}", time),
+        None => info!("Taken: unknown (no capture date in metadata)"),
+    }
+    if let Some(camera) = metadata.camera() {
+        info!("Camera: {}", camera);
+    }
+    if let Some(gps) = metadata.gps {
+        info!("Location: {}", gps);
+    }
+}
+
+/// Show a backup photo next to the Immich photo it looks like
+fn log_near_match(near: &perceptual::NearMatch) {
+    let describe = |file: &Path| {
+        let metadata = metadata::read_metadata(file).unwrap_or_default();
+        [
+            file.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            fs::metadata(file)
+                .map(|m| format!("{} bytes", m.len()))
+                .unwrap_or_else(|e| e.to_string()),
+            metadata
+                .captured_at
+                .map(|time| time.to_string())
+                .unwrap_or_else(|| "unknown".to_string()),
+            metadata.camera().unwrap_or_else(|| "unknown".to_string()),
+        ]
+    };
+    let backup = describe(&near.file);
+    let immich = describe(&near.immich_file);
+
+    info!("Looks like a photo in Immich ({:.0}% similar):", near.similarity * 100.0);
+    info!("  {:<8} {:<40} {}", "", "Backup", "Immich");
+    for (label, (ours, theirs)) in ["File", "Size", "Taken", "Camera"]
+        .into_iter()
+        .zip(backup.iter().zip(&immich))
+    {
+        info!("  {:<8} {:<40} {}", label, ours, theirs);
+    }
+    info!("  Immich copy: {}", near.immich_file.display());
+}
+
+/// Move files that belong together to the trash, in the given order, as one
+/// unit: when one of them can't be moved, the ones already in the trash are
+/// put back so an asset is never left split. The moves are recorded in the
+/// sync journal, which is started on the first move, only once all of them
+/// are done. Files already gone from a previous attempt are skipped. With
+/// `dry_run` the destinations are only worked out and logged.
+fn move_files_to_trash(
+    files: &[&Path],
+    trash: &trash::Trash,
+    backup_dir: &Path,
+    journal: &mut Option<journal::Journal>,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    if dry_run {
+        for file in files {
+            let trashed = trash.put(file, true)?;
+            info!(
+                "[dry run] Would move to trash: {} -> {}",
+                file.display(),
+                trashed.trashed.display()
+            );
+        }
+        return Ok(());
+    }
+
+    let mut moved: Vec<(&Path, trash::TrashedFile, String)> = Vec::new();
+    for &file in files {
+        if !file.exists() {
+            continue;
+        }
+        info!("Moving to trash: {}", file.display());
+        let result = calculate_file_hash(file)
+            .and_then(|sha256| Ok((trash.put(file, false)?, sha256)));
+        match result {
+            Ok((trashed, sha256)) => {
+                info!("File successfully moved to {}", trashed.trashed.display());
+                moved.push((file, trashed, sha256));
+            }
+            Err(e) => {
+                error!("Failed to move {} to trash: {}", file.display(), e);
+                put_back_from_trash(&moved);
+                return Err(e);
+            }
+        }
+    }
+    if moved.is_empty() {
+        return Ok(());
+    }
+
+    let recorded = (|| {
+        let journal = match journal {
+            Some(journal) => journal,
+            None => journal.insert(journal::Journal::create(backup_dir, "sync")?),
+        };
+        for (file, trashed, sha256) in &moved {
+            journal.append(&journal::JournalEntry::Trashed {
+                path: file.strip_prefix(backup_dir).unwrap_or(file).to_path_buf(),
+                trashed: trashed.trashed.clone(),
+                info: trashed.info.clone(),
+                sha256: sha256.clone(),
+                at: chrono::Utc::now(),
+            })?;
+        }
+        Ok(())
+    })();
+    if let Err(e) = recorded {
+        error!("Could not record the trash moves in the sync journal: {}", e);
+        put_back_from_trash(&moved);
+        return Err(e);
+    }
+    Ok(())
+}
+
+/// Undo the trash moves of a unit that could only be moved in part, last first
+fn put_back_from_trash(moved: &[(&Path, trash::TrashedFile, String)]) {
+    for (file, trashed, _) in moved.iter().rev() {
+        match trash::restore(&trashed.trashed, trashed.info.as_deref(), &trashed.original) {
+            Ok(()) => info!("Put {} back from the trash", file.display()),
+            Err(e) => error!(
+                "Could not put {} back, it is still at {}: {}",
+                file.display(),
+                trashed.trashed.display(),
+                e
+            ),
+        }
+    }
+}
+
+/// Move every file of an asset to the trash as a unit, sidecars and motion
+/// video before the primary file
+fn move_asset_to_trash(
+    asset: &Asset,
+    trash: &trash::Trash,
+    backup_dir: &Path,
+    journal: &mut Option<journal::Journal>,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    let files: Vec<&Path> = asset.files().into_iter().rev().collect();
+    move_files_to_trash(&files, trash, backup_dir, journal, dry_run)
+}
+
+/// Print `question` and read the answer into `input`, returning false at the
+/// end of input
+fn ask(
+    handle: &mut impl std::io::BufRead,
+    input: &mut String,
+    question: &str,
+) -> std::io::Result<bool> {
+    use std::io::Write;
+
+    print!("{}", question);
+    std::io::stdout().flush()?;
+    input.clear();
+    Ok(handle.read_line(input)? > 0)
+}
+
+/// List the assets of a sync run that failed, leaving out any that succeeded
+/// on a later try
+fn report_sync_failures(what: &str, succeeded: &[PathBuf], failed: &mut Vec<PathBuf>) {
+    failed.retain(|file| !succeeded.contains(file));
+    failed.sort();
+    failed.dedup();
+    if failed.is_empty() {
+        return;
+    }
+    warn!("  - {} of them {}:", failed.len(), what);
+    for file in failed.iter() {
+        warn!("    {}", file.display());
+    }
+}
+
+/// Tell the user how to reverse the trash moves of this sync run
+fn log_sync_undo_hint(journal: &Option<journal::Journal>) {
+    if let Some(journal) = journal {
+        info!("Trash moves were recorded in {}", journal.path().display());
+        info!(
+            "Undo with: backup-photos sync undo --session {}",
+            journal.session()
+        );
+    }
+}
+
+/// Upload the photos and videos of an asset to Immich, which takes its
+/// sidecars along, connecting on first use. The server is then asked by
+/// SHA-1 whether it has every one of them, since an upload reported as a
+/// duplicate may only be in Immich's trash.
+fn upload_asset_to_immich(
+    asset: &Asset,
+    profile: &Profile,
+    client: &mut Option<ImmichClient>,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    let files = asset.media_files();
+    if files.is_empty() {
+        return Err(BackupError::ImmichApiError(format!(
+            "{} has no photo or video to upload",
+            asset.primary.display()
+        )));
+    }
+    if dry_run {
+        for file in files {
+            info!("[dry run] Would upload to Immich: {}", file.display());
+        }
+        return Ok(());
+    }
+
+    let client = match client {
+        Some(client) => client,
+        None => {
+            let new_client = ImmichClient::new(&profile.immich_server, load_api_key(profile)?);
+            let user = new_client.check_connection()?;
+            debug!("Connected to {} as {}", new_client.base_url(), user);
+            client.insert(new_client)
+        }
+    };
+    for &file in &files {
+        let (status, id) = client.upload_asset(file)?;
+        if status == immich::UploadStatus::Duplicate {
+            info!("{} was already in Immich as {}", file.display(), id);
+        } else {
+            info!("Uploaded {} to Immich as {}", file.display(), id);
+        }
+    }
+
+    let checksums = files
+        .iter()
+        .map(|file| calculate_file_hashes(file).map(|(_, sha1)| sha1))
+        .collect::<Result<Vec<_>, _>>()?;
+    for (file, presence) in files.iter().zip(client.check_checksums(&checksums)?) {
+        match presence {
+            immich::ServerPresence::Present { asset_id } => {
+                debug!("{} is in Immich as {}", file.display(), asset_id)
+            }
+            immich::ServerPresence::Trashed { asset_id } => {
+                return Err(BackupError::NotInImmich(format!(
+                    "{} is in the Immich trash as {}, restore it there",
+                    file.display(),
+                    asset_id
+                )));
+            }
+            immich::ServerPresence::Missing => {
+                return Err(BackupError::NotInImmich(file.display().to_string()));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Carries out what is chosen on the sync review screen
+struct SyncExecutor<'a> {
+    profile: &'a Profile,
+    trash: trash::Trash,
+    journal: Option<journal::Journal>,
+    client: Option<ImmichClient>,
+    dry_run: bool,
+}
+
+impl tui::SyncActions for SyncExecutor<'_> {
+    fn trash(&mut self, asset: &Asset) -> Result<(), BackupError> {
+        move_asset_to_trash(asset, &self.trash, &self.profile.backup_dir, &mut self.journal, self.dry_run)
+    }
+
+    fn upload(&mut self, asset: &Asset) -> Result<(), BackupError> {
+        upload_asset_to_immich(asset, self.profile, &mut self.client, self.dry_run)
     }
+}
 
-    // Step 4: Clear export directory (prompt for confirmation)
-    info!("Step 4: Clearing export directory");
-    info!("Please run the clear command separately with the --force flag to confirm deletion");
+/// Review the assets on the full-screen list, then log what was done
+fn review_sync_on_screen(
+    profile: &Profile,
+    assets: &[Asset],
+    near_matches: &std::collections::HashMap<PathBuf, perceptual::NearMatch>,
+    interface: SyncInterface,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    let trash = trash::Trash::for_platform()?;
+    debug!("Using the trash at {}", trash.home().display());
+    let mut executor = SyncExecutor {
+        profile,
+        trash,
+        journal: None,
+        client: None,
+        dry_run,
+    };
+
+    // Log lines would be drawn over the screen, so they wait until it closes
+    let level = log::max_level();
+    log::set_max_level(log::LevelFilter::Off);
+    let root = &profile.backup_dir;
+    let result = match interface {
+        SyncInterface::Scripted(keys) => {
+            tui::review_scripted(root, assets, near_matches, keys, &mut executor, dry_run).map(
+                |(status, screen)| {
+                    print!("{}", screen);
+                    status
+                },
+            )
+        }
+        _ => tui::review_on_terminal(root, assets, near_matches, &mut executor, dry_run),
+    };
+    log::set_max_level(level);
+    let status = result?;
+
+    for (asset, status) in assets.iter().zip(&status) {
+        match status {
+            tui::Status::Trashed if dry_run => info!("[dry run] Would move to trash: {}", asset),
+            tui::Status::Trashed => info!("Moved to trash: {}", asset),
+            tui::Status::Uploaded if dry_run => info!("[dry run] Would upload to Immich: {}", asset),
+            tui::Status::Uploaded => info!("Uploaded to Immich and resolved: {}", asset),
+            tui::Status::Failed(e) => warn!("Failed: {}: {}", asset, e),
+            tui::Status::Pending | tui::Status::Kept => {}
+        }
+    }
+    let count = |wanted: fn(&tui::Status) -> bool| status.iter().filter(|&s| wanted(s)).count();
+    let trash_count = count(|s| *s == tui::Status::Trashed);
+    let upload_count = count(|s| *s == tui::Status::Uploaded);
+    let failed_count = count(|s| matches!(s, tui::Status::Failed(_)));
 
-    info!("Full backup workflow completed successfully");
+    info!("Sync completed. Summary:");
+    if dry_run {
+        info!("  - {} assets would be moved to trash", trash_count);
+        info!("  - {} assets would be uploaded to Immich", upload_count);
+    } else {
+        info!("  - {} assets moved to trash", trash_count);
+        info!("  - {} assets uploaded to Immich and resolved", upload_count);
+    }
+    info!(
+        "  - {} assets kept in backup",
+        assets.len() - trash_count - upload_count - failed_count
+    );
+    if failed_count > 0 {
+        warn!("  - {} assets failed and were left in the backup", failed_count);
+    }
+    log_sync_undo_hint(&executor.journal);
     Ok(())
 }
 
 /// Synchronize backup directory with Immich library
-/// by interactively handling files that are in backup but not in Immich
-pub fn sync_backup_with_immich() -> Result<(), BackupError> {
-    use std::io::{self, BufRead, Write};
+/// by interactively handling files that are in backup but not in Immich,
+/// through `interface`. With `MatchMode::Perceptual`, assets that look like a
+/// photo in Immich are marked with the best match. With `dry_run`, trash
+/// moves and uploads are only reported.
+pub fn sync_backup_with_immich(
+    profile: &Profile,
+    matching: MatchMode,
+    interface: SyncInterface,
+    dry_run: bool,
+) -> Result<(), BackupError> {
+    use std::io;
 
     // Get the list of files that are in the backup but not in Immich
-    let mut files_not_in_immich = find_files_not_in_immich()?;
+    let mut assets = find_files_not_in_immich(profile, CompareSource::Filesystem, dry_run)?;
 
-    if files_not_in_immich.is_empty() {
+    if assets.is_empty() {
         info!("No discrepancies found. All media files from backup are present in Immich library.");
         return Ok(());
     }
 
+    let near_matches = match matching {
+        MatchMode::Perceptual => find_near_matches(profile, &assets, dry_run)?,
+        MatchMode::Exact => Default::default(),
+    };
+
     info!(
-        "Found {} media files in backup that are not in Immich library.",
-        files_not_in_immich.len()
+        "Found {} assets in backup that are not in Immich library.",
+        assets.len()
     );
-
-    // Offer option to filter by media type or pattern
-    print!("Do you want to filter files by media type or pattern? [y/N]: ");
-    io::stdout().flush()?;
+    if interface != SyncInterface::Prompt {
+        return review_sync_on_screen(profile, &assets, &near_matches, interface, dry_run);
+    }
 
     let stdin = io::stdin();
     let mut handle = stdin.lock();
     let mut input = String::new();
-    handle.read_line(&mut input)?;
+
+    // Offer option to filter by media type or pattern
+    let question = "Do you want to filter files by media type or pattern? [y/N]: ";
+    if !ask(&mut handle, &mut input, question)? {
+        info!("No input, so nothing was changed");
+        return Ok(());
+    }
 
     if input.trim().eq_ignore_ascii_case("y") {
-        print!("Filter by (1) Photos only, (2) Videos only, (3) Filename pattern: ");
-        io::stdout().flush()?;
-        input.clear();
-        handle.read_line(&mut input)?;
+        let question = "Filter by (1) Photos only, (2) Videos only, (3) Filename pattern: ";
+        if !ask(&mut handle, &mut input, question)? {
+            info!("No input, so nothing was changed");
+            return Ok(());
+        }
 
         let choice = input.trim();
         match choice {
             "1" => {
                 info!("Filtering by photos only");
-                files_not_in_immich.retain(|path| {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_string_lossy().to_lowercase();
-                        [
-                            "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-                        ]
-                        .contains(&ext_str.as_ref())
-                    } else {
-                        false
-                    }
-                });
-                info!("Found {} photo files to process", files_not_in_immich.len());
+                assets.retain(|asset| MediaKind::of(&asset.primary) == Some(MediaKind::Photo));
+                info!("Found {} photo files to process", assets.len());
             }
             "2" => {
                 info!("Filtering by videos only");
-                files_not_in_immich.retain(|path| {
-                    if let Some(ext) = path.extension() {
-                        let ext_str = ext.to_string_lossy().to_lowercase();
-                        [
-                            "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts",
-                            "m2ts",
-                        ]
-                        .contains(&ext_str.as_ref())
-                    } else {
-                        false
-                    }
-                });
-                info!("Found {} video files to process", files_not_in_immich.len());
+                assets.retain(|asset| MediaKind::of(&asset.primary) == Some(MediaKind::Video));
+                info!("Found {} video files to process", assets.len());
             }
             "3" => {
-                print!("Enter filename pattern to match: ");
-                io::stdout().flush()?;
-                input.clear();
-                handle.read_line(&mut input)?;
+                if !ask(&mut handle, &mut input, "Enter filename pattern to match: ")? {
+                    info!("No input, so nothing was changed");
+                    return Ok(());
+                }
                 let pattern = input.trim().to_lowercase();
                 info!("Filtering by pattern: '{}'", pattern);
 
-                files_not_in_immich.retain(|path| {
-                    path.file_name()
+                assets.retain(|asset| {
+                    asset
+                        .primary
+                        .file_name()
                         .and_then(|n| n.to_str())
                         .map(|name| name.to_lowercase().contains(&pattern))
                         .unwrap_or(false)
                 });
-                info!("Found {} files matching pattern", files_not_in_immich.len());
+                info!("Found {} files matching pattern", assets.len());
             }
             _ => {
                 info!("No filter applied");
@@ -772,7 +2156,7 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
         }
     }
 
-    if files_not_in_immich.is_empty() {
+    if assets.is_empty() {
         info!("No files to process after filtering. Exiting.");
         return Ok(());
     }
@@ -782,6 +2166,7 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
     info!("Options for each file:");
     info!("[t] Move to trash (safer than permanent deletion)");
     info!("[k] Keep in backup (skip this file)");
+    info!("[u] Upload to Immich");
     info!("[v] View file info and optionally open file");
     info!("[d] Open directory containing file");
     info!("[s] Select multiple files for batch processing");
@@ -790,62 +2175,61 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
     info!("[a] Process all remaining files with the same action");
     info!("-------------------------------------------------");
 
-    // Prepare trash directory - on macOS, this is ~/.Trash
-    let home_dir = dirs::home_dir().ok_or_else(|| {
-        BackupError::DirectoryNotAccessible("Could not determine home directory".to_string())
-    })?;
-    let trash_dir = home_dir.join(".Trash");
-
-    if !trash_dir.exists() {
-        warn!(
-            "Trash directory not found at expected location: {}",
-            trash_dir.display()
-        );
-        warn!("Will attempt to use it anyway as macOS should create it if needed");
-    }
+    // ~/.Trash on macOS, the freedesktop.org trash elsewhere
+    let trash = trash::Trash::for_platform()?;
+    debug!("Using the trash at {}", trash.home().display());
+    let backup_dir = &profile.backup_dir;
+    let mut journal: Option<journal::Journal> = None;
 
-    let stdin = io::stdin();
-    let mut handle = stdin.lock();
-    let mut input = String::new();
     let mut i = 0;
     let mut all_action: Option<char> = None;
-
-    while i < files_not_in_immich.len() {
-        let file = &files_not_in_immich[i];
-        let file_name = file.file_name().unwrap_or_default().to_string_lossy();
+    let mut trashed: Vec<PathBuf> = Vec::new();
+    let mut client: Option<ImmichClient> = None;
+    let mut uploaded: Vec<PathBuf> = Vec::new();
+    let mut upload_failed: Vec<PathBuf> = Vec::new();
+    let mut trash_failed: Vec<PathBuf> = Vec::new();
+    let mut quit = false;
+    // Assets already trashed, uploaded or kept, possibly ahead of `i` by the batch and filter modes
+    let mut done = vec![false; assets.len()];
+
+    'assets: while i < assets.len() {
+        if done[i] {
+            i += 1;
+            continue;
+        }
+        let asset = &assets[i];
+        let file = &asset.primary;
 
         // If we have an "all" action set, use it without prompting
         if let Some(action) = all_action {
+            // A failed asset is left pending and listed in the summary
             match action {
                 't' => {
                     // Move to trash
-                    let mut destination = trash_dir.join(&*file_name);
-                    let original_name = file_name.to_string();
-
-                    // Handle name collisions by appending a timestamp
-                    let mut counter = 1;
-                    while destination.exists() {
-                        let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S").to_string();
-                        let new_name = format!("{}-{}-{}", original_name, timestamp, counter);
-                        destination = trash_dir.join(new_name);
-                        counter += 1;
-                    }
-
-                    info!("Moving to trash: {}", file.display());
-                    match fs::copy(file, &destination) {
-                        Ok(_) => match fs::remove_file(file) {
-                            Ok(_) => info!("File successfully moved to trash"),
-                            Err(e) => warn!(
-                                "File was copied to trash but could not be deleted from backup: {}",
-                                e
-                            ),
-                        },
-                        Err(e) => error!("Failed to copy file to trash: {}", e),
+                    if move_asset_to_trash(asset, &trash, backup_dir, &mut journal, dry_run).is_ok() {
+                        trashed.push(file.clone());
+                        done[i] = true;
+                    } else {
+                        trash_failed.push(file.clone());
                     }
                 }
                 'k' => {
                     // Keep in backup
                     info!("Keeping in backup: {}", file.display());
+                    done[i] = true;
+                }
+                'u' => {
+                    // Upload to Immich
+                    match upload_asset_to_immich(asset, profile, &mut client, dry_run) {
+                        Ok(_) => {
+                            uploaded.push(file.clone());
+                            done[i] = true;
+                        }
+                        Err(e) => {
+                            warn!("Failed to upload {}: {}", asset, e);
+                            upload_failed.push(file.clone());
+                        }
+                    }
                 }
                 _ => {
                     warn!("Invalid action for all files. Exiting sync process.");
@@ -856,55 +2240,38 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
             continue;
         }
 
-        info!(
-            "File {}/{}: {}",
-            i + 1,
-            files_not_in_immich.len(),
-            file.display()
-        );
-        print!("Action [t/k/v/q/a]: ");
-        io::stdout().flush()?;
-
-        input.clear();
-        handle.read_line(&mut input)?;
-        let action = input.trim().chars().next().unwrap_or('?');
+        info!("Asset {}/{}: {}", i + 1, assets.len(), asset);
+        if let Some(near) = near_matches.get(file) {
+            info!(
+                "  Looks like {} in Immich ({:.0}% similar)",
+                near.immich_file.display(),
+                near.similarity * 100.0
+            );
+        }
+        let action = if ask(&mut handle, &mut input, "Action [t/k/u/v/q/a]: ")? {
+            input.trim().chars().next().unwrap_or('?')
+        } else {
+            'q'
+        };
 
         match action {
             't' => {
                 // Move to trash
-                let mut destination = trash_dir.join(&*file_name);
-                let original_name = file_name.to_string();
-
-                // Handle name collisions by appending a timestamp
-                let mut counter = 1;
-                while destination.exists() {
-                    let timestamp = chrono::Local::now().format("%Y%m%d%H%M%S").to_string();
-                    let new_name = format!("{}-{}-{}", original_name, timestamp, counter);
-                    destination = trash_dir.join(new_name);
-                    counter += 1;
-                }
-
-                info!("Moving to trash: {}", file.display());
-                match fs::copy(file, &destination) {
+                match move_asset_to_trash(asset, &trash, backup_dir, &mut journal, dry_run) {
                     Ok(_) => {
-                        match fs::remove_file(file) {
-                            Ok(_) => info!("File successfully moved to trash"),
-                            Err(e) => {
-                                warn!("File was copied to trash but could not be deleted from backup: {}", e);
-                                warn!("Manual deletion may be required");
-                            }
-                        }
+                        trashed.push(file.clone());
+                        done[i] = true;
                     }
-                    Err(e) => {
-                        error!("Failed to copy file to trash: {}", e);
-                        print!("Try again? [Y/n]: ");
-                        io::stdout().flush()?;
-                        input.clear();
-                        handle.read_line(&mut input)?;
+                    Err(_) => {
+                        if !ask(&mut handle, &mut input, "Try again? [Y/n]: ")? {
+                            quit = true;
+                            break 'assets;
+                        }
                         if !input.trim().eq_ignore_ascii_case("n") {
                             // Don't increment i so we try this file again
                             continue;
                         }
+                        trash_failed.push(file.clone());
                     }
                 }
                 i += 1;
@@ -912,6 +2279,29 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
             'k' => {
                 // Keep in backup
                 info!("Keeping in backup: {}", file.display());
+                done[i] = true;
+                i += 1;
+            }
+            'u' => {
+                // Upload to Immich
+                match upload_asset_to_immich(asset, profile, &mut client, dry_run) {
+                    Ok(_) => {
+                        uploaded.push(file.clone());
+                        done[i] = true;
+                    }
+                    Err(e) => {
+                        warn!("Failed to upload {}: {}", asset, e);
+                        if !ask(&mut handle, &mut input, "Try again? [Y/n]: ")? {
+                            quit = true;
+                            break 'assets;
+                        }
+                        if !input.trim().eq_ignore_ascii_case("n") {
+                            // Don't increment i so we try this file again
+                            continue;
+                        }
+                        upload_failed.push(file.clone());
+                    }
+                }
                 i += 1;
             }
             'v' => {
@@ -921,51 +2311,40 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                 let modified = metadata.modified().ok();
 
                 info!("File info for {}", file.display());
+                for other in asset.files().into_iter().skip(1) {
+                    info!("Part of the same asset: {}", other.display());
+                }
                 info!("Size: {} bytes", metadata.len());
                 if let Some(time) = created {
-                    info!("Created: {:?}", time);
+                    info!("File created: {:?}", time);
                 }
                 if let Some(time) = modified {
-                    info!("Modified: {:?}", time);
+                    info!("File modified: {:?}", time);
                 }
 
-                // Media type detection based on extension
-                if let Some(ext) = file.extension() {
-                    let ext_str = ext.to_string_lossy().to_lowercase();
-                    let media_type = if [
-                        "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-                    ]
-                    .contains(&ext_str.as_ref())
-                    {
-                        "Photo"
-                    } else if [
-                        "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts",
-                        "m2ts",
-                    ]
-                    .contains(&ext_str.as_ref())
-                    {
-                        "Video"
-                    } else {
-                        "Unknown"
-                    };
-                    info!("Media Type: {}", media_type);
+                match MediaKind::of(file) {
+                    Some(kind) => info!("Media Type: {}", kind),
+                    None => info!("Media Type: unknown"),
+                }
+                log_media_metadata(file);
+                if let Some(near) = near_matches.get(file) {
+                    log_near_match(near);
                 }
 
                 // Optionally open the image for viewing (macOS only)
-                print!("View this file? [y/N]: ");
-                io::stdout().flush()?;
-
-                input.clear();
-                handle.read_line(&mut input)?;
+                if !ask(&mut handle, &mut input, "View this file? [y/N]: ")? {
+                    quit = true;
+                    break 'assets;
+                }
                 if input.trim().eq_ignore_ascii_case("y") {
                     info!("Opening file with default application...");
                     let _ = Command::new("open").arg(file).spawn()?;
 
                     // Give user a moment to view the file
-                    print!("Press Enter to continue...");
-                    io::stdout().flush()?;
-                    input.clear();
-                    handle.read_line(&mut input)?;
+                    if !ask(&mut handle, &mut input, "Press Enter to continue...")? {
+                        quit = true;
+                        break 'assets;
+                    }
                 }
 
                 // Don't increment i so we process this file again
@@ -977,10 +2356,10 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                     let _ = Command::new("open").arg(parent).spawn()?;
 
                     // Give user a moment
-                    print!("Press Enter to continue...");
-                    io::stdout().flush()?;
-                    input.clear();
-                    handle.read_line(&mut input)?;
+                    if !ask(&mut handle, &mut input, "Press Enter to continue...")? {
+                        quit = true;
+                        break 'assets;
+                    }
                 } else {
                     warn!("Could not determine parent directory for file");
                 }
@@ -993,24 +2372,29 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                     "Starting batch selection mode. You'll be shown each file to select or skip."
                 );
                 let mut selected_indices = Vec::new();
-                let start_idx = i;
-                let mut batch_idx = start_idx;
+                let mut batch_idx = i;
 
                 // Loop through remaining files to select
-                while batch_idx < files_not_in_immich.len() {
-                    let batch_file = &files_not_in_immich[batch_idx];
-
-                    info!(
-                        "File {}/{}: {}",
-                        batch_idx + 1,
-                        files_not_in_immich.len(),
-                        batch_file.display()
-                    );
-                    print!("Select this file? [y/n/v/d/q]: ");
-                    io::stdout().flush()?;
-
-                    input.clear();
-                    handle.read_line(&mut input)?;
+                while batch_idx < assets.len() {
+                    if done[batch_idx] {
+                        batch_idx += 1;
+                        continue;
+                    }
+                    let batch_asset = &assets[batch_idx];
+                    let batch_file = &batch_asset.primary;
+
+                    info!("Asset {}/{}: {}", batch_idx + 1, assets.len(), batch_asset);
+                    if let Some(near) = near_matches.get(batch_file) {
+                        info!(
+                            "  Looks like {} in Immich ({:.0}% similar)",
+                            near.immich_file.display(),
+                            near.similarity * 100.0
+                        );
+                    }
+                    if !ask(&mut handle, &mut input, "Select this file? [y/n/v/d/q]: ")? {
+                        quit = true;
+                        break 'assets;
+                    }
                     let select_action = input.trim().chars().next().unwrap_or('?');
 
                     match select_action {
@@ -1028,20 +2412,24 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                             let metadata = fs::metadata(batch_file)?;
                             info!("File info for {}", batch_file.display());
                             info!("Size: {} bytes", metadata.len());
+                            log_media_metadata(batch_file);
+                            if let Some(near) = near_matches.get(batch_file) {
+                                log_near_match(near);
+                            }
 
-                            print!("View this file? [y/N]: ");
-                            io::stdout().flush()?;
-                            input.clear();
-                            handle.read_line(&mut input)?;
+                            if !ask(&mut handle, &mut input, "View this file? [y/N]: ")? {
+                                quit = true;
+                                break 'assets;
+                            }
 
                             if input.trim().eq_ignore_ascii_case("y") {
                                 info!("Opening file with default application...");
                                 let _ = Command::new("open").arg(batch_file).spawn()?;
 
-                                print!("Press Enter to continue...");
-                                io::stdout().flush()?;
-                                input.clear();
-                                handle.read_line(&mut input)?;
+                                if !ask(&mut handle, &mut input, "Press Enter to continue...")? {
+                                    quit = true;
+                                    break 'assets;
+                                }
                             }
                             // Don't increment batch_idx to see this file again
                         }
@@ -1051,10 +2439,10 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                                 info!("Opening directory containing file...");
                                 let _ = Command::new("open").arg(parent).spawn()?;
 
-                                print!("Press Enter to continue...");
-                                io::stdout().flush()?;
-                                input.clear();
-                                handle.read_line(&mut input)?;
+                                if !ask(&mut handle, &mut input, "Press Enter to continue...")? {
+                                    quit = true;
+                                    break 'assets;
+                                }
                             }
                             // Don't increment batch_idx to see this file again
                         }
@@ -1080,52 +2468,25 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                     );
                     info!("[t] Move all selected files to trash");
                     info!("[k] Keep all selected files in backup");
-                    print!("Action for selected files [t/k]: ");
-                    io::stdout().flush()?;
-
-                    input.clear();
-                    handle.read_line(&mut input)?;
+                    info!("[u] Upload all selected files to Immich");
+                    if !ask(&mut handle, &mut input, "Action for selected files [t/k/u]: ")? {
+                        quit = true;
+                        break 'assets;
+                    }
                     let batch_action = input.trim().chars().next().unwrap_or('?');
 
                     match batch_action {
                         't' => {
                             info!("Moving {} selected files to trash", selected_indices.len());
 
-                            // Process in reverse order to avoid index issues if we're removing from files_not_in_immich
-                            for &idx in selected_indices.iter().rev() {
-                                let batch_file = &files_not_in_immich[idx];
-                                let file_name =
-                                    batch_file.file_name().unwrap_or_default().to_string_lossy();
-
-                                // Create unique name in trash to avoid collisions
-                                let mut destination = trash_dir.join(&*file_name);
-                                let original_name = file_name.to_string();
-
-                                let mut counter = 1;
-                                while destination.exists() {
-                                    let timestamp =
-                                        chrono::Local::now().format("%Y%m%d%H%M%S").to_string();
-                                    let new_name =
-                                        format!("{}-{}-{}", original_name, timestamp, counter);
-                                    destination = trash_dir.join(new_name);
-                                    counter += 1;
+                            for &idx in &selected_indices {
+                                let batch_asset = &assets[idx];
+                                if move_asset_to_trash(batch_asset, &trash, backup_dir, &mut journal, dry_run).is_ok() {
+                                    trashed.push(batch_asset.primary.clone());
+                                    done[idx] = true;
+                                } else {
+                                    trash_failed.push(batch_asset.primary.clone());
                                 }
-
-                                info!("Moving to trash: {}", batch_file.display());
-                                match fs::copy(batch_file, &destination) {
-                                    Ok(_) => {
-                                        match fs::remove_file(batch_file) {
-                                            Ok(_) => info!("File successfully moved to trash"),
-                                            Err(e) => warn!("File was copied to trash but could not be deleted from backup: {}", e)
-                                        }
-                                    },
-                                    Err(e) => error!("Failed to copy file to trash: {}", e)
-                                }
-                            }
-
-                            // Update our position in the list to avoid re-processing files
-                            if selected_indices.contains(&start_idx) {
-                                i += 1;
                             }
                         }
                         'k' => {
@@ -1133,8 +2494,25 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                                 "Keeping {} selected files in backup",
                                 selected_indices.len()
                             );
-                            // Skip to the next file after the last one we just processed
-                            i = start_idx + 1;
+                            for &idx in &selected_indices {
+                                done[idx] = true;
+                            }
+                        }
+                        'u' => {
+                            info!("Uploading {} selected files to Immich", selected_indices.len());
+                            for &idx in &selected_indices {
+                                let batch_asset = &assets[idx];
+                                match upload_asset_to_immich(batch_asset, profile, &mut client, dry_run) {
+                                    Ok(_) => {
+                                        uploaded.push(batch_asset.primary.clone());
+                                        done[idx] = true;
+                                    }
+                                    Err(e) => {
+                                        warn!("Failed to upload {}: {}", batch_asset, e);
+                                        upload_failed.push(batch_asset.primary.clone());
+                                    }
+                                }
+                            }
                         }
                         _ => {
                             warn!(
@@ -1151,63 +2529,44 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
             }
             'f' => {
                 // Apply filter to remaining files
-                print!("Filter by (1) Photos only, (2) Videos only, (3) Filename pattern: ");
-                io::stdout().flush()?;
-                input.clear();
-                handle.read_line(&mut input)?;
+                let question = "Filter by (1) Photos only, (2) Videos only, (3) Filename pattern: ";
+                if !ask(&mut handle, &mut input, question)? {
+                    quit = true;
+                    break 'assets;
+                }
 
-                let choice = input.trim();
-                let mut filtered_files = Vec::new();
+                let choice = input.trim().to_string();
+                let pattern = if choice == "3" {
+                    if !ask(&mut handle, &mut input, "Enter filename pattern to match: ")? {
+                        quit = true;
+                        break 'assets;
+                    }
+                    input.trim().to_lowercase()
+                } else {
+                    String::new()
+                };
 
-                for idx in i..files_not_in_immich.len() {
-                    let file = &files_not_in_immich[idx];
-                    match choice {
-                        "1" => {
-                            if let Some(ext) = file.extension() {
-                                let ext_str = ext.to_string_lossy().to_lowercase();
-                                if [
-                                    "jpg", "jpeg", "png", "heic", "dng", "raw", "arw", "cr2", "nef",
-                                ]
-                                .contains(&ext_str.as_ref())
-                                {
-                                    filtered_files.push(idx);
-                                }
-                            }
-                        }
-                        "2" => {
-                            if let Some(ext) = file.extension() {
-                                let ext_str = ext.to_string_lossy().to_lowercase();
-                                if [
-                                    "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv",
-                                    "mts", "m2ts",
-                                ]
-                                .contains(&ext_str.as_ref())
-                                {
-                                    filtered_files.push(idx);
-                                }
-                            }
-                        }
-                        "3" => {
-                            print!("Enter filename pattern to match: ");
-                            io::stdout().flush()?;
-
-                            // Use a separate string for pattern to avoid borrow conflicts
-                            let mut pattern_input = String::new();
-                            handle.read_line(&mut pattern_input)?;
-                            let pattern = pattern_input.trim().to_lowercase();
-
-                            if file
-                                .file_name()
-                                .and_then(|n| n.to_str())
-                                .map(|name| name.to_lowercase().contains(&pattern))
-                                .unwrap_or(false)
-                            {
-                                filtered_files.push(idx);
-                            }
-                        }
+                let mut filtered_files = Vec::new();
+                for (idx, asset) in assets.iter().enumerate().skip(i) {
+                    if done[idx] {
+                        continue;
+                    }
+                    let file = &asset.primary;
+                    let matches = match choice.as_str() {
+                        "1" => MediaKind::of(file) == Some(MediaKind::Photo),
+                        "2" => MediaKind::of(file) == Some(MediaKind::Video),
+                        "3" => file
+                            .file_name()
+                            .and_then(|n| n.to_str())
+                            .map(|name| name.to_lowercase().contains(&pattern))
+                            .unwrap_or(false),
                         _ => {
                             warn!("Invalid choice. Filter not applied.");
+                            break;
                         }
+                    };
+                    if matches {
+                        filtered_files.push(idx);
                     }
                 }
 
@@ -1216,54 +2575,49 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                         "Found {} files matching filter criteria",
                         filtered_files.len()
                     );
-                    print!("Apply action to all filtered files? [t/k/n]: ");
-                    io::stdout().flush()?;
-
                     // Create a new String to avoid borrowing issues
                     let mut action_input = String::new();
-                    handle.read_line(&mut action_input)?;
+                    let question = "Apply action to all filtered files? [t/k/u/n]: ";
+                    if !ask(&mut handle, &mut action_input, question)? {
+                        quit = true;
+                        break 'assets;
+                    }
                     let filter_action = action_input.trim().chars().next().unwrap_or('?');
 
                     match filter_action {
                         't' => {
                             info!("Moving {} filtered files to trash", filtered_files.len());
-                            for &idx in filtered_files.iter().rev() {
-                                let file = &files_not_in_immich[idx];
-                                let file_name =
-                                    file.file_name().unwrap_or_default().to_string_lossy();
-                                let mut destination = trash_dir.join(&*file_name);
-
-                                let original_name = file_name.to_string();
-                                let mut counter = 1;
-                                while destination.exists() {
-                                    let timestamp =
-                                        chrono::Local::now().format("%Y%m%d%H%M%S").to_string();
-                                    let new_name =
-                                        format!("{}-{}-{}", original_name, timestamp, counter);
-                                    destination = trash_dir.join(new_name);
-                                    counter += 1;
+                            for &idx in &filtered_files {
+                                let asset = &assets[idx];
+                                if move_asset_to_trash(asset, &trash, backup_dir, &mut journal, dry_run).is_ok() {
+                                    trashed.push(asset.primary.clone());
+                                    done[idx] = true;
+                                } else {
+                                    trash_failed.push(asset.primary.clone());
                                 }
-
-                                info!("Moving to trash: {}", file.display());
-                                match fs::copy(file, &destination) {
-                                    Ok(_) => {
-                                        match fs::remove_file(file) {
-                                            Ok(_) => info!("File successfully moved to trash"),
-                                            Err(e) => warn!("File was copied to trash but could not be deleted from backup: {}", e)
-                                        }
-                                    },
-                                    Err(e) => error!("Failed to copy file to trash: {}", e)
-                                }
-                            }
-
-                            // Update our position in the list and skip processed files
-                            if i == filtered_files[0] {
-                                i += 1;
                             }
                         }
                         'k' => {
                             info!("Keeping {} filtered files in backup", filtered_files.len());
-                            i = *filtered_files.iter().max().unwrap_or(&i) + 1;
+                            for &idx in &filtered_files {
+                                done[idx] = true;
+                            }
+                        }
+                        'u' => {
+                            info!("Uploading {} filtered files to Immich", filtered_files.len());
+                            for &idx in &filtered_files {
+                                let asset = &assets[idx];
+                                match upload_asset_to_immich(asset, profile, &mut client, dry_run) {
+                                    Ok(_) => {
+                                        uploaded.push(asset.primary.clone());
+                                        done[idx] = true;
+                                    }
+                                    Err(e) => {
+                                        warn!("Failed to upload {}: {}", asset, e);
+                                        upload_failed.push(asset.primary.clone());
+                                    }
+                                }
+                            }
                         }
                         _ => {
                             info!("No bulk action taken. Continuing with standard processing.");
@@ -1274,24 +2628,20 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
                 }
             }
             'q' => {
-                // Quit sync process
-                info!(
-                    "Sync process cancelled. Processed {} of {} files.",
-                    i,
-                    files_not_in_immich.len()
-                );
-                return Ok(());
+                // Quit sync process, also at the end of input
+                quit = true;
+                break;
             }
             'a' => {
                 // Apply an action to all remaining files
-                print!("Apply which action to all remaining files? [t/k]: ");
-                io::stdout().flush()?;
-
-                input.clear();
-                handle.read_line(&mut input)?;
+                let question = "Apply which action to all remaining files? [t/k/u]: ";
+                if !ask(&mut handle, &mut input, question)? {
+                    quit = true;
+                    break 'assets;
+                }
                 let all_char = input.trim().chars().next().unwrap_or('?');
 
-                if all_char == 't' || all_char == 'k' {
+                if matches!(all_char, 't' | 'k' | 'u') {
                     all_action = Some(all_char);
                     info!("Applying '{}' to all remaining files.", all_char);
                 } else {
@@ -1302,7 +2652,7 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
             }
             _ => {
                 warn!(
-                    "Invalid action '{}'. Please choose [t/k/v/d/s/f/q/a].",
+                    "Invalid action '{}'. Please choose [t/k/u/v/d/s/f/q/a].",
                     action
                 );
                 // Don't increment i so we process this file again
@@ -1310,36 +2660,343 @@ pub fn sync_backup_with_immich() -> Result<(), BackupError> {
         }
     }
 
+    if quit {
+        info!(
+            "Sync process cancelled. Processed {} of {} assets.",
+            done.iter().filter(|&&done| done).count(),
+            assets.len()
+        );
+        report_sync_failures("could not be moved to trash", &trashed, &mut trash_failed);
+        report_sync_failures("failed to upload or are not in Immich afterwards", &uploaded, &mut upload_failed);
+        log_sync_undo_hint(&journal);
+        return Ok(());
+    }
+
     // Count how many files were processed in different ways
-    let mut trash_count = 0;
-    let mut kept_count = 0;
+    let trash_count = trashed.len();
+    let upload_count = uploaded.len();
+    let kept_count = assets.len() - trash_count - upload_count;
 
-    for original_file in files_not_in_immich {
-        if !original_file.exists() {
-            // File was moved to trash
-            trash_count += 1;
-        } else {
-            // File was kept
-            kept_count += 1;
+    info!("Sync completed. Summary:");
+    if dry_run {
+        info!("  - {} assets would be moved to trash", trash_count);
+        info!("  - {} assets would be uploaded to Immich", upload_count);
+    } else {
+        info!("  - {} assets moved to trash", trash_count);
+        info!("  - {} assets uploaded to Immich and resolved", upload_count);
+    }
+    info!("  - {} assets kept in backup", kept_count);
+    report_sync_failures("could not be moved to trash", &trashed, &mut trash_failed);
+    report_sync_failures("failed to upload or are not in Immich afterwards", &uploaded, &mut upload_failed);
+    info!("  - {} total assets processed", trash_count + upload_count + kept_count);
+    log_sync_undo_hint(&journal);
+
+    Ok(())
+}
+
+/// Write a plan proposing to trash or keep every asset that is in the backup
+/// but not in Immich, chosen by `rules`, for review before `apply_sync_plan`.
+/// With `dry_run`, the plan is only logged and the hash cache isn't saved.
+pub fn plan_sync(
+    profile: &Profile,
+    rules: &sync_plan::PlanRules,
+    out: &Path,
+    dry_run: bool,
+) -> Result<sync_plan::SyncPlan, BackupError> {
+    let assets = find_files_not_in_immich(profile, CompareSource::Filesystem, dry_run)?;
+    if rules.is_empty() {
+        warn!("No rules given, so every asset is proposed to be kept. Edit the plan to choose what to trash");
+    }
+
+    info!("Hashing the files of {} assets for the plan...", assets.len());
+    let mut cache = HashCache::open()?;
+    let progress = progress_bar(0);
+    let plan = sync_plan::plan(&profile.backup_dir, &assets, rules, &mut cache, &progress)?;
+    progress.finish_and_clear();
+    if !dry_run {
+        cache.save()?;
+    }
+
+    let trash_count = plan
+        .assets
+        .iter()
+        .filter(|asset| asset.action == sync_plan::PlanAction::Trash)
+        .count();
+    if dry_run {
+        for asset in plan.assets.iter().filter(|asset| asset.action == sync_plan::PlanAction::Trash) {
+            info!("[dry run] Would propose trashing {} ({})", asset.path.display(), asset.reason);
         }
+        info!(
+            "[dry run] Would write a plan to trash {} and keep {} assets to {}",
+            trash_count,
+            plan.assets.len() - trash_count,
+            out.display()
+        );
+        return Ok(plan);
     }
+    sync_plan::save(&plan, out)?;
+    info!(
+        "Wrote a plan to trash {} and keep {} assets to {}",
+        trash_count,
+        plan.assets.len() - trash_count,
+        out.display()
+    );
+    info!("Review it, then carry it out with: backup-photos sync apply {}", out.display());
+    Ok(plan)
+}
 
-    info!("Sync completed. Summary:");
-    info!("  - {} files moved to trash", trash_count);
-    info!("  - {} files kept in backup", kept_count);
-    info!("  - {} total files processed", trash_count + kept_count);
+/// Carry out a reviewed sync plan, moving the assets it marks for the trash.
+/// An asset is left alone if any of its files no longer has the hash it had
+/// when the plan was made. Trash moves are journaled like those of an
+/// interactive sync, so `sync undo` can reverse them.
+pub fn apply_sync_plan(
+    profile: &Profile,
+    plan_file: &Path,
+    dry_run: bool,
+) -> Result<sync_plan::ApplySummary, BackupError> {
+    check_directory_exists_and_accessible(&profile.backup_dir)?;
+    let backup_dir = &profile.backup_dir;
+    let plan = sync_plan::load(plan_file, backup_dir)?;
+    info!(
+        "Applying the plan made {} for {} assets",
+        plan.created.with_timezone(&chrono::Local).format("%Y-%m-%d %H:%M"),
+        plan.assets.len()
+    );
 
-    Ok(())
+    let trash = trash::Trash::for_platform()?;
+    let mut journal: Option<journal::Journal> = None;
+    let mut summary = sync_plan::ApplySummary::default();
+
+    for planned in &plan.assets {
+        let primary = backup_dir.join(&planned.path);
+        if planned.action == sync_plan::PlanAction::Keep {
+            debug!("Keeping {}: {}", primary.display(), planned.reason);
+            summary.kept += 1;
+            continue;
+        }
+        if let Err(e) = sync_plan::check_unchanged(backup_dir, planned) {
+            warn!("Refusing to trash {}: {}", primary.display(), e);
+            summary.failed.push((primary, e));
+            continue;
+        }
+
+        // Sidecars and motion video before the primary file, as sync does
+        let files: Vec<PathBuf> = planned.files.iter().rev().map(|file| backup_dir.join(&file.path)).collect();
+        let files: Vec<&Path> = files.iter().map(PathBuf::as_path).collect();
+        match move_files_to_trash(&files, &trash, backup_dir, &mut journal, dry_run) {
+            Ok(()) => summary.trashed.push(primary),
+            Err(e) => summary.failed.push((primary, e.to_string())),
+        }
+    }
+
+    summary.session = journal.as_ref().map(|journal| journal.session().to_string());
+    info!("Plan applied. Summary:");
+    if dry_run {
+        info!("  - {} assets would be moved to trash", summary.trashed.len());
+    } else {
+        info!("  - {} assets moved to trash", summary.trashed.len());
+    }
+    info!("  - {} assets kept in backup", summary.kept);
+    if !summary.failed.is_empty() {
+        info!("  - {} assets could not be trashed", summary.failed.len());
+    }
+    log_sync_undo_hint(&journal);
+    Ok(summary)
+}
+
+/// Put files that sync moved to the trash back where they were in the backup
+/// directory: every file of `session`, only `file`, or with neither, the most
+/// recent session. A file is only restored if it still has the content it was
+/// trashed with and nothing has taken its place. Files that are already back
+/// are skipped, and a session is retired once all of it is undone.
+pub fn undo_sync(
+    profile: &Profile,
+    session: Option<&str>,
+    file: Option<&Path>,
+    dry_run: bool,
+) -> Result<journal::UndoSummary, BackupError> {
+    check_directory_exists_and_accessible(&profile.backup_dir)?;
+    let backup_dir = &profile.backup_dir;
+    let mut sessions = journal::sessions(backup_dir, "sync")?;
+
+    let mut summary = journal::UndoSummary::default();
+    let mut entries = Vec::new();
+    match (session, file) {
+        (Some(session), _) => {
+            if !sessions.iter().any(|s| s == session) {
+                return Err(BackupError::JournalNotFound(session.to_string()));
+            }
+            entries = journal::load(backup_dir, session)?;
+            summary.session = Some(session.to_string());
+        }
+        (None, Some(file)) => {
+            // Accept the original path as printed, relative to the current
+            // directory, or relative to the backup directory
+            let absolute = std::env::current_dir()?.join(file);
+            let wanted = absolute
+                .strip_prefix(backup_dir)
+                .unwrap_or(file)
+                .to_path_buf();
+
+            // The most recent move of the file that is still in the trash
+            for session in sessions.iter().rev() {
+                let entry = journal::load(backup_dir, session)?
+                    .into_iter()
+                    .rev()
+                    .find(|entry| match entry {
+                        journal::JournalEntry::Trashed { path, trashed, .. } => {
+                            *path == wanted && fs::symlink_metadata(trashed).is_ok()
+                        }
+                        _ => false,
+                    });
+                if let Some(entry) = entry {
+                    entries.push(entry);
+                    summary.session = Some(session.clone());
+                    break;
+                }
+            }
+            if entries.is_empty() {
+                return Err(BackupError::NothingToUndo(format!(
+                    "no file sync moved to the trash is recorded as {}",
+                    wanted.display()
+                )));
+            }
+        }
+        (None, None) => match sessions.pop() {
+            Some(session) => {
+                info!("Undoing the most recent sync session, {}", session);
+                entries = journal::load(backup_dir, &session)?;
+                summary.session = Some(session);
+            }
+            None => {
+                return Err(BackupError::NothingToUndo(
+                    "no sync session has moved files to the trash".to_string(),
+                ))
+            }
+        },
+    }
+
+    for entry in entries.iter().rev() {
+        let journal::JournalEntry::Trashed {
+            path,
+            trashed,
+            info,
+            sha256,
+            ..
+        } = entry
+        else {
+            continue;
+        };
+        let original = backup_dir.join(path);
+        match restore_trashed(&original, trashed, info.as_deref(), sha256, dry_run) {
+            Ok(true) => summary.restored.push(original),
+            Ok(false) => info!("{} was already restored", original.display()),
+            Err(e) => {
+                warn!("Failed to restore {}: {}", original.display(), e);
+                summary.failed.push((original, e));
+            }
+        }
+    }
+
+    if let (Some(session), None, false) = (&summary.session, file, dry_run) {
+        if summary.failed.is_empty() {
+            let retired = journal::mark_undone(backup_dir, session)?;
+            info!(
+                "Session {} is undone, its journal is kept at {}",
+                session,
+                retired.display()
+            );
+        }
+    }
+
+    let verb = if dry_run { "Would restore" } else { "Restored" };
+    info!(
+        "{} {} files, {} could not be restored",
+        verb,
+        summary.restored.len(),
+        summary.failed.len()
+    );
+    Ok(summary)
+}
+
+/// Move one trashed file back to `original` after checking it still has the
+/// content it was trashed with. Returns false if it is already back.
+fn restore_trashed(
+    original: &Path,
+    trashed: &Path,
+    info: Option<&Path>,
+    sha256: &str,
+    dry_run: bool,
+) -> Result<bool, String> {
+    let in_trash = fs::symlink_metadata(trashed).is_ok();
+    if fs::symlink_metadata(original).is_ok() {
+        return match calculate_file_hash(original) {
+            Ok(hash) if hash == sha256 && !in_trash => Ok(false),
+            Ok(_) => Err(format!("a different file is now at {}", original.display())),
+            Err(e) => Err(e.to_string()),
+        };
+    }
+    if !in_trash {
+        return Err(format!("{} is no longer in the trash", trashed.display()));
+    }
+
+    let hash = calculate_file_hash(trashed).map_err(|e| e.to_string())?;
+    if hash != sha256 {
+        return Err(format!("{} changed in the trash", trashed.display()));
+    }
+
+    if dry_run {
+        info!(
+            "[dry run] Would restore {} from {}",
+            original.display(),
+            trashed.display()
+        );
+        return Ok(true);
+    }
+    trash::restore(trashed, info, original).map_err(|e| e.to_string())?;
+    info!("Restored {} from {}", original.display(), trashed.display());
+    Ok(true)
+}
+
+/// Load the profile's Immich API key from wherever it is configured
+pub fn load_api_key(profile: &Profile) -> Result<secrets::ApiKey, BackupError> {
+    match &profile.immich_api_key {
+        Some(source) => source.load(),
+        None => Err(BackupError::ApiKeyError(format!(
+            "No Immich API key configured for profile '{}'. Set immich_api_key_command, \
+             immich_api_key_file or the IMMICH_API_KEY environment variable",
+            profile.name
+        ))),
+    }
+}
+
+/// The profile's docker compose file, which is only needed to manage a local server
+fn docker_compose_file(profile: &Profile) -> Result<&Path, BackupError> {
+    profile.immich_docker_compose.as_deref().ok_or_else(|| {
+        BackupError::ConfigError(format!(
+            "immich_docker_compose is not set for profile '{}'",
+            profile.name
+        ))
+    })
 }
 
 /// Start the immich server with docker compose
-pub fn start_immich_server() -> Result<(), BackupError> {
+pub fn start_immich_server(profile: &Profile, dry_run: bool) -> Result<(), BackupError> {
     info!("Starting Immich server with Docker Compose...");
+    let docker_compose = docker_compose_file(profile)?;
+
+    if dry_run {
+        info!(
+            "[dry run] Would run: docker compose -f {} up -d",
+            docker_compose.display()
+        );
+        return Ok(());
+    }
 
     let output = Command::new("docker")
         .arg("compose")  // "compose" is an argument, not part of the command name
         .arg("-f")
-        .arg(constants::IMMICH_DOCKER_COMPOSE)
+        .arg(docker_compose)
         .arg("up")
         .arg("-d")
         .output()
@@ -1356,13 +3013,22 @@ pub fn start_immich_server() -> Result<(), BackupError> {
 }
 
 /// Stop the immich server with docker compose
-pub fn stop_immich_server() -> Result<(), BackupError> {
+pub fn stop_immich_server(profile: &Profile, dry_run: bool) -> Result<(), BackupError> {
     info!("Stopping Immich server with Docker Compose...");
+    let docker_compose = docker_compose_file(profile)?;
+
+    if dry_run {
+        info!(
+            "[dry run] Would run: docker compose -f {} down",
+            docker_compose.display()
+        );
+        return Ok(());
+    }
 
     let output = Command::new("docker")
         .arg("compose")  // "compose" is an argument, not part of the command name
         .arg("-f")
-        .arg(constants::IMMICH_DOCKER_COMPOSE)
+        .arg(docker_compose)
         .arg("down")
         .output()
         .map_err(|e| BackupError::CommandFailed(e.to_string()))?;
@@ -1375,4 +3041,161 @@ pub fn stop_immich_server() -> Result<(), BackupError> {
 
     info!("Immich server stopped successfully");
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+    use std::sync::Mutex;
+
+    /// Held by tests that point XDG directories somewhere else
+    pub(crate) static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    fn trash_in(dir: &Path) -> trash::Trash {
+        trash::Trash::new(trash::Backend::Freedesktop, dir.join("Trash"))
+    }
+
+    fn profile_in(dir: &Path) -> Profile {
+        Profile {
+            name: "test".to_string(),
+            export_dir: dir.join("export"),
+            backup_dir: dir.join("backup"),
+            immich_lib: dir.join("immich"),
+            immich_server: constants::DEFAULT_IMMICH_SERVER.to_string(),
+            immich_docker_compose: None,
+            immich_api_key: None,
+            jobs: None,
+            layout: layout::Layout::default(),
+            media_types: media::MediaTypes::default(),
+            min_similarity: 90,
+        }
+    }
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    /// Every file and directory under `dir` with its inode, modification
+    /// time and contents, to tell whether anything was created, replaced or
+    /// rewritten
+    #[cfg(unix)]
+    fn snapshot(dir: &Path) -> BTreeMap<PathBuf, (u64, std::time::SystemTime, Vec<u8>)> {
+        use std::os::unix::fs::MetadataExt;
+
+        WalkDir::new(dir)
+            .into_iter()
+            .map(|entry| {
+                let entry = entry.unwrap();
+                let metadata = entry.metadata().unwrap();
+                let contents = if metadata.is_file() {
+                    fs::read(entry.path()).unwrap()
+                } else {
+                    Vec::new()
+                };
+                let stamp = (metadata.ino(), metadata.modified().unwrap(), contents);
+                (entry.path().to_path_buf(), stamp)
+            })
+            .collect()
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn dry_run_changes_nothing() {
+        let _env = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        std::env::set_var("XDG_CACHE_HOME", dir.path().join("cache"));
+        std::env::set_var("XDG_DATA_HOME", dir.path().join("data"));
+        let profile = profile_in(dir.path());
+        fs::create_dir_all(profile.immich_lib.join("upload")).unwrap();
+
+        // A real backup first, so there is a manifest and a hash cache to leave alone
+        write(&profile.export_dir.join("IMG_0001.jpg"), "first");
+        write(&profile.export_dir.join("IMG_0002.jpg"), "second");
+        backup_photos_to_raw_dir(&profile, false).unwrap();
+        let state_dir = profile.backup_dir.join(constants::STATE_DIR_NAME);
+        assert!(state_dir.join(constants::MANIFEST_FILE_NAME).is_file());
+        write(&profile.export_dir.join("IMG_0003.jpg"), "third");
+        write(&profile.backup_dir.join("old/IMG_0002.jpg"), "second");
+
+        let plan_file = dir.path().join("plan.json");
+        let rules = sync_plan::PlanRules {
+            globs: vec!["*".to_string()],
+            ..Default::default()
+        };
+        let plan = plan_sync(&profile, &rules, &plan_file, true).unwrap();
+        assert!(!plan_file.exists());
+        assert!(plan.assets.iter().any(|asset| asset.action == sync_plan::PlanAction::Trash));
+        let reviewed = dir.path().join("reviewed.json");
+        sync_plan::save(&plan, &reviewed).unwrap();
+        let before = snapshot(dir.path());
+
+        let summary = backup_photos_to_raw_dir(&profile, true).unwrap();
+        assert_eq!(summary.copied, [profile.export_dir.join("IMG_0003.jpg")]);
+        assert!(verify_backup(&profile, true).is_err());
+        for mode in [DedupeMode::Hardlink, DedupeMode::KeepOldest] {
+            let summary = dedupe_backup(&profile, mode, true, true).unwrap();
+            assert!(summary.session.is_none());
+        }
+        let applied = apply_sync_plan(&profile, &reviewed, true).unwrap();
+        assert!(!applied.trashed.is_empty());
+        plan_sync(&profile, &rules, &plan_file, true).unwrap();
+        clear_export_directory_force(&profile, false, false, true).unwrap();
+        rebuild_hash_cache(&profile, true).unwrap();
+        prune_hash_cache(true).unwrap();
+
+        assert_eq!(snapshot(dir.path()), before);
+        assert!(!dir.path().join("data/Trash").exists());
+    }
+
+    #[test]
+    fn asset_is_trashed_and_journaled_as_a_unit() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backup");
+        fs::create_dir_all(&backup_dir).unwrap();
+        let mut asset = Asset::new(backup_dir.join("IMG_0001.HEIC"));
+        asset.sidecars.push(backup_dir.join("IMG_0001.xmp"));
+        fs::write(&asset.primary, b"heic").unwrap();
+        fs::write(&asset.sidecars[0], b"<xmp/>").unwrap();
+
+        let mut journal = None;
+        move_asset_to_trash(&asset, &trash_in(dir.path()), &backup_dir, &mut journal, false).unwrap();
+
+        assert!(!asset.primary.exists());
+        assert!(!asset.sidecars[0].exists());
+        let session = journal.unwrap().session().to_string();
+        let paths: Vec<PathBuf> = journal::load(&backup_dir, &session)
+            .unwrap()
+            .into_iter()
+            .filter_map(|entry| match entry {
+                journal::JournalEntry::Trashed { path, .. } => Some(path),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(paths, [PathBuf::from("IMG_0001.xmp"), PathBuf::from("IMG_0001.HEIC")]);
+    }
+
+    #[test]
+    fn failed_move_puts_the_rest_of_the_asset_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backup");
+        fs::create_dir_all(&backup_dir).unwrap();
+        // The sidecar goes first; the primary can't be hashed, so it fails after it
+        let mut asset = Asset::new(backup_dir.join("IMG_0002.HEIC"));
+        asset.sidecars.push(backup_dir.join("IMG_0002.xmp"));
+        fs::create_dir(&asset.primary).unwrap();
+        fs::write(&asset.sidecars[0], b"<xmp/>").unwrap();
+
+        let trash = trash_in(dir.path());
+        let mut journal = None;
+        let result = move_asset_to_trash(&asset, &trash, &backup_dir, &mut journal, false);
+
+        assert!(result.is_err());
+        assert_eq!(fs::read(&asset.sidecars[0]).unwrap(), b"<xmp/>");
+        assert!(asset.primary.is_dir());
+        assert!(journal.is_none());
+        assert!(journal::sessions(&backup_dir, "sync").unwrap().is_empty());
+        assert_eq!(fs::read_dir(trash.home().join("files")).unwrap().count(), 0);
+        assert_eq!(fs::read_dir(trash.home().join("info")).unwrap().count(), 0);
+    }
+}
@@ -1,12 +1,12 @@
 use anyhow::Result;
+use backup_photos::config::{ConfigFile, ConfigLayers, Overrides, Source};
 use backup_photos::*;
 use clap::{Parser, Subcommand};
 use dotenv::dotenv;
 use env_logger::Env;
 use log::{error, info};
-use std::io::{self, Write};
+use std::io::{self, IsTerminal, Write};
 use std::path::PathBuf;
-use constants;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
@@ -16,46 +16,165 @@ struct Cli {
     #[arg(short, long)]
     debug: bool,
 
+    /// Config file to read instead of $XDG_CONFIG_HOME/backup-photos/config.toml
+    #[arg(long, global = true, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Named profile from the config file to use (overrides BACKUP_PHOTOS_PROFILE)
+    #[arg(short, long, global = true, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Apple Photos export directory
+    #[arg(long, global = true, value_name = "PATH")]
+    export_dir: Option<PathBuf>,
+
+    /// Raw photos backup directory
+    #[arg(long, global = true, value_name = "PATH")]
+    backup_dir: Option<PathBuf>,
+
+    /// Immich library directory
+    #[arg(long, global = true, value_name = "PATH")]
+    immich_lib: Option<PathBuf>,
+
+    /// Immich server URL
+    #[arg(long, global = true, value_name = "URL")]
+    immich_server: Option<String>,
+
+    /// Docker compose file for the Immich server
+    #[arg(long, global = true, value_name = "PATH")]
+    immich_docker_compose: Option<PathBuf>,
+
+    /// File containing the Immich API key (must not be readable by group or others)
+    #[arg(long, global = true, value_name = "PATH")]
+    immich_api_key_file: Option<PathBuf>,
+
+    /// Command that prints the Immich API key, e.g. "pass show immich"
+    #[arg(long, global = true, value_name = "COMMAND")]
+    immich_api_key_command: Option<String>,
+
+    /// Number of worker threads for hashing and copying (defaults to one per CPU)
+    #[arg(short, long, global = true, value_name = "N")]
+    jobs: Option<usize>,
+
+    /// Where files go in the backup, e.g. "{year}/{month}/{day}/{filename}"
+    #[arg(long, global = true, value_name = "TEMPLATE")]
+    layout: Option<String>,
+
+    /// How alike, in percent, photos must look to count as a near match with --match perceptual
+    #[arg(long, global = true, value_name = "PERCENT")]
+    min_similarity: Option<u8>,
+
+    /// Print what would be copied, uploaded, rewritten, deleted or run, and change nothing
+    #[arg(long, global = true)]
+    dry_run: bool,
+
     #[command(subcommand)]
     command: Commands,
 }
 
 #[derive(Subcommand)]
 enum Commands {
-    /// Initialize required directories based on environment variables
+    /// Initialize required directories based on the configuration
     /// Creates export, backup, and Immich directories if they don't exist
     Init,
     
-    /// Backup photos and videos from Apple Photos export directory to backup directory
-    /// using rsync to avoid duplicates and preserve metadata
-    Backup,
+    /// Backup photos and videos from Apple Photos export directory to backup directory,
+    /// skipping files already in the backup and preserving timestamps and permissions
+    Backup {
+        /// Hash every file afterwards and compare it with its backup copy
+        #[arg(long)]
+        verify: bool,
+    },
+
+    /// Verify that every file in the export directory has a byte-identical copy
+    /// in the backup directory, comparing SHA-256 hashes
+    Verify,
     
-    /// Import photos and videos from export directory to Immich
-    /// using the Immich CLI
+    /// Upload photos and videos from export directory to Immich
+    /// through its REST API, with their XMP sidecars
     Import,
     
     /// Clear the export directory after successful backup
-    /// Without --force, this only shows what would be deleted
+    /// Only files whose contents are found in the backup directory are deleted;
+    /// without --force, this only shows what would be deleted and kept
     Clear {
         /// Force deletion without additional prompts
         #[arg(short, long)]
         force: bool,
+
+        /// Also require photos and videos to be in the Immich library
+        #[arg(long)]
+        require_immich: bool,
+
+        /// Delete every media file without checking the backup (dangerous)
+        #[arg(long, requires = "force")]
+        unsafe_all: bool,
     },
     
     /// Compare media files between backup directory and Immich library
     /// Reports any discrepancies found
-    Compare,
+    Compare {
+        /// Hash the local Immich upload folder, or ask the server by checksum
+        #[arg(long, value_enum, default_value_t)]
+        source: CompareSource,
+
+        /// Also look for photos that look like one in Immich without being identical
+        #[arg(long = "match", value_enum, default_value_t)]
+        matching: MatchMode,
+    },
     
     /// Sync backup with Immich by interactively handling discrepancies
     /// Provides options to view, filter, batch select, and process files
     /// that are in backup but missing from Immich
-    Sync,
+    Sync {
+        #[command(subcommand)]
+        command: Option<SyncCommands>,
+
+        /// Also look for photos that look like one in Immich without being identical
+        #[arg(long = "match", value_enum, default_value_t)]
+        matching: MatchMode,
+
+        /// Ask about one asset at a time on stdin instead of showing the
+        /// full-screen list, which is only used when stdin and stdout are a terminal
+        #[arg(long)]
+        no_tui: bool,
+
+        /// Drive the full-screen list with these keys instead of the keyboard and
+        /// print the final screen, e.g. "<down><space><down><space>t y q"
+        #[arg(long, value_name = "KEYS", value_parser = tui::ScriptedKeys::parse, conflicts_with = "no_tui")]
+        keys: Option<tui::ScriptedKeys>,
+    },
     
-    /// Run the full backup workflow (backup -> import -> compare)
+    /// Run the full backup workflow (backup -> verify -> import -> compare)
     /// in a single command
     Full,
     
-    /// Check environment variable paths for existence and accessibility
+    /// Report photos, videos and sidecars in the export directory that are empty,
+    /// unreadable, or whose contents don't match their extension
+    Doctor {
+        /// Also check the files already in the backup directory
+        #[arg(long)]
+        backup: bool,
+    },
+
+    /// Find identical files in the backup directory and report, hard link or delete
+    /// the extra copies. Prints the full plan first; changes need --force and are
+    /// journaled so they can be undone with --undo
+    Dedupe {
+        /// What to do with the duplicates
+        #[arg(long, value_enum, default_value_t)]
+        mode: DedupeMode,
+
+        /// Carry out the plan for hardlink or keep-oldest
+        #[arg(short, long)]
+        force: bool,
+
+        /// Reverse an earlier dedupe run, by the session ID it printed
+        #[arg(long, value_name = "SESSION", conflicts_with_all = ["mode", "force"])]
+        undo: Option<String>,
+    },
+
+    /// Check configured paths for existence and accessibility
     /// Verifies that external drives are connected if paths point to them
     CheckPaths,
 
@@ -67,7 +186,152 @@ enum Commands {
 
     /// Stop the docker server for immich
     StopServer,
-    
+
+    /// Manage the persistent content hash cache used by compare, sync and full
+    Cache {
+        #[command(subcommand)]
+        command: CacheCommands,
+    },
+
+    /// Inspect the resolved configuration
+    Config {
+        #[command(subcommand)]
+        command: ConfigCommands,
+    },
+
+    /// Manage the named profiles defined in the config file
+    Profiles {
+        #[command(subcommand)]
+        command: ProfilesCommands,
+    },
+}
+
+#[derive(Subcommand)]
+enum ProfilesCommands {
+    /// List every profile with its paths and whether they are usable
+    List,
+}
+
+#[derive(Subcommand)]
+enum SyncCommands {
+    /// Write every asset that is in the backup but not in Immich to a JSON plan,
+    /// proposing to trash the ones matching all the given rules and keep the rest
+    Plan {
+        /// Where to write the plan
+        #[arg(long, value_name = "PATH")]
+        out: PathBuf,
+
+        #[command(flatten)]
+        rules: sync_plan::PlanRules,
+    },
+
+    /// Carry out a reviewed plan, skipping any asset whose files changed since
+    Apply {
+        /// Plan written by sync plan
+        #[arg(value_name = "PLAN")]
+        plan: PathBuf,
+    },
+
+    /// Put files that sync moved to the trash back in the backup directory,
+    /// checking their contents first. Undoes the most recent session by default
+    Undo {
+        /// Session ID printed by sync
+        #[arg(long, value_name = "SESSION")]
+        session: Option<String>,
+
+        /// Only restore this file, by its original path
+        #[arg(long, value_name = "PATH", conflicts_with = "session")]
+        file: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+enum CacheCommands {
+    /// Rehash every media file in the backup directory and Immich library
+    Rebuild,
+
+    /// Drop cached hashes for files that were deleted or changed
+    Prune,
+}
+
+#[derive(Subcommand)]
+enum ConfigCommands {
+    /// Print every resolved value and where it came from
+    /// (default, config file, environment variable or command line flag)
+    Show,
+}
+
+/// Print the merged configuration layers, flagging missing or invalid values
+fn show_config(layers: &ConfigLayers) {
+    println!(
+        "Config file: {} ({})",
+        layers.config_file.display(),
+        if layers.config_file_found { "loaded" } else { "not found" }
+    );
+    println!("Profile: {} [{}]", layers.profile, layers.profile_source);
+
+    for key in ConfigLayers::keys() {
+        match layers.get(key) {
+            Some(setting) => {
+                let value = if ConfigLayers::is_secret(key) {
+                    "<redacted>"
+                } else {
+                    setting.value.as_str()
+                };
+                print!("{:<24} = {:<48} [{}]", key, value, setting.source);
+                match layers.validate(key) {
+                    Ok(_) => println!(),
+                    Err(e) => println!(" ❌ {}", e),
+                }
+            }
+            None => println!("{:<24} = <unset>", key),
+        }
+    }
+}
+
+/// Print each profile in the config file with its paths and status
+fn list_profiles(overrides: &Overrides) -> Result<()> {
+    let file = ConfigFile::load(overrides)?;
+    let (selected, _) = file.select_profile(overrides);
+
+    for name in file.profile_names() {
+        let marker = if name == selected { "*" } else { " " };
+        let layers = ConfigLayers::for_profile(&file, &name, Source::Default, overrides)?;
+
+        let profile = match layers.resolve() {
+            Ok(profile) => profile,
+            Err(e) => {
+                println!("{} {} - ❌ {}", marker, name, e);
+                continue;
+            }
+        };
+
+        let problems: Vec<String> = [
+            (&profile.export_dir, "export_dir"),
+            (&profile.backup_dir, "backup_dir"),
+            (&profile.immich_lib, "immich_lib"),
+        ]
+        .into_iter()
+        .filter_map(|(path, key)| {
+            check_directory_exists_and_accessible(path)
+                .and_then(|_| check_external_drive_connected(path))
+                .err()
+                .map(|e| format!("{}: {}", key, e))
+        })
+        .collect();
+
+        if problems.is_empty() {
+            println!("{} {} - ✓ ready", marker, name);
+        } else {
+            println!("{} {} - ❌ {}", marker, name, problems.join("; "));
+        }
+        println!("    export_dir:    {}", profile.export_dir.display());
+        println!("    backup_dir:    {}", profile.backup_dir.display());
+        println!("    immich_lib:    {}", profile.immich_lib.display());
+        println!("    immich_server: {}", profile.immich_server);
+    }
+
+    Ok(())
 }
 
 fn main() -> Result<()> {
@@ -96,11 +360,60 @@ fn main() -> Result<()> {
         })
         .init();
     
+    let overrides = Overrides {
+        config_file: cli.config.clone(),
+        profile: cli.profile.clone(),
+        export_dir: cli.export_dir.clone(),
+        backup_dir: cli.backup_dir.clone(),
+        immich_lib: cli.immich_lib.clone(),
+        immich_server: cli.immich_server.clone(),
+        immich_docker_compose: cli.immich_docker_compose.clone(),
+        immich_api_key_file: cli.immich_api_key_file.clone(),
+        immich_api_key_command: cli.immich_api_key_command.clone(),
+        jobs: cli.jobs,
+        layout: cli.layout.clone(),
+        min_similarity: cli.min_similarity,
+    };
+
+    match &cli.command {
+        Commands::Config { command } => {
+            match command {
+                ConfigCommands::Show => show_config(&ConfigLayers::load(&overrides)?),
+            }
+            return Ok(());
+        }
+        Commands::Profiles { command } => {
+            match command {
+                ProfilesCommands::List => list_profiles(&overrides)?,
+            }
+            return Ok(());
+        }
+        _ => {}
+    }
+
+    let profile = match Profile::load(&overrides) {
+        Ok(profile) => {
+            info!("Using profile '{}'", profile.name);
+            profile
+        }
+        Err(e) => {
+            error!("Failed to load configuration: {}", e);
+            return Err(e.into());
+        }
+    };
+    configure_thread_pool(&profile)?;
+    configure_media_types(&profile)?;
+
+    let dry_run = cli.dry_run;
+    if dry_run {
+        info!("Dry run: nothing will be changed");
+    }
+
     // Execute the appropriate command
     match &cli.command {
         Commands::Init => {
             info!("Initializing required directories");
-            match init_directories() {
+            match init_directories(&profile, dry_run) {
                 Ok(_) => info!("Directories initialized successfully"),
                 Err(e) => {
                     error!("Failed to initialize directories: {}", e);
@@ -109,20 +422,53 @@ fn main() -> Result<()> {
             }
         },
         
-        Commands::Backup => {
+        Commands::Backup { verify } => {
             info!("Running backup command");
-            match backup_photos_to_raw_dir() {
+            match backup_photos_to_raw_dir(&profile, dry_run) {
+                Ok(summary) if !summary.failed.is_empty() => {
+                    let e = BackupError::CopyFailed(summary.failed.len());
+                    error!("Backup failed: {}", e);
+                    return Err(e.into());
+                }
                 Ok(_) => info!("Backup completed successfully"),
                 Err(e) => {
                     error!("Backup failed: {}", e);
                     return Err(e.into());
                 }
             }
+
+            if *verify && dry_run {
+                info!("[dry run] Would verify the backup");
+            } else if *verify {
+                match verify_backup(&profile, dry_run) {
+                    Ok(count) => info!("Verified {} files in the backup", count),
+                    Err(e) => {
+                        error!("Verification failed: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+        }
+
+        Commands::Verify => {
+            info!("Running verify command");
+            match verify_backup(&profile, dry_run) {
+                Ok(count) => info!("Verified {} files in the backup", count),
+                Err(e) => {
+                    error!("Verification failed: {}", e);
+                    return Err(e.into());
+                }
+            }
         }
         
         Commands::Import => {
             info!("Running import command");
-            match import_to_immich() {
+            match import_to_immich(&profile, dry_run) {
+                Ok(report) if !report.errors().is_empty() => {
+                    let e = BackupError::UploadFailed(report.errors().len());
+                    error!("Import failed: {}", e);
+                    return Err(e.into());
+                }
                 Ok(_) => info!("Import completed successfully"),
                 Err(e) => {
                     error!("Import failed: {}", e);
@@ -131,10 +477,14 @@ fn main() -> Result<()> {
             }
         }
         
-        Commands::Clear { force } => {
+        Commands::Clear {
+            force,
+            require_immich,
+            unsafe_all,
+        } => {
             info!("Running clear command");
             if *force {
-                match clear_export_directory_force() {
+                match clear_export_directory_force(&profile, *require_immich, *unsafe_all, dry_run) {
                     Ok(_) => info!("Export directory cleared successfully"),
                     Err(e) => {
                         error!("Failed to clear export directory: {}", e);
@@ -142,7 +492,7 @@ fn main() -> Result<()> {
                     }
                 }
             } else {
-                match clear_export_directory() {
+                match clear_export_directory(&profile, *require_immich, dry_run) {
                     Ok(_) => info!("Please run with --force to confirm deletion"),
                     Err(e) => {
                         error!("Failed to analyze export directory: {}", e);
@@ -152,9 +502,9 @@ fn main() -> Result<()> {
             }
         }
         
-        Commands::Compare => {
+        Commands::Compare { source, matching } => {
             info!("Running compare command");
-            match compare_backup_to_immich() {
+            match compare_backup_to_immich(&profile, *source, *matching, dry_run) {
                 Ok(_) => info!("Comparison completed successfully"),
                 Err(e) => {
                     error!("Comparison failed: {}", e);
@@ -163,20 +513,69 @@ fn main() -> Result<()> {
             }
         }
         
-        Commands::Sync => {
-            info!("Running sync command");
-            match sync_backup_with_immich() {
-                Ok(_) => info!("Sync completed successfully"),
-                Err(e) => {
-                    error!("Sync failed: {}", e);
-                    return Err(e.into());
+        Commands::Sync { command, matching, no_tui, keys } => match command {
+            Some(SyncCommands::Plan { out, rules }) => {
+                info!("Planning sync");
+                match plan_sync(&profile, rules, out, dry_run) {
+                    Ok(_) => info!("Sync plan completed successfully"),
+                    Err(e) => {
+                        error!("Sync plan failed: {}", e);
+                        return Err(e.into());
+                    }
                 }
             }
-        }
+            Some(SyncCommands::Apply { plan }) => {
+                info!("Applying sync plan {}", plan.display());
+                match apply_sync_plan(&profile, plan, dry_run) {
+                    Ok(summary) if !summary.failed.is_empty() => {
+                        let e = BackupError::PlanFailed(summary.failed.len());
+                        error!("Sync apply failed: {}", e);
+                        return Err(e.into());
+                    }
+                    Ok(_) => info!("Sync apply completed successfully"),
+                    Err(e) => {
+                        error!("Sync apply failed: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            Some(SyncCommands::Undo { session, file }) => {
+                info!("Undoing sync trash moves");
+                match undo_sync(&profile, session.as_deref(), file.as_deref(), dry_run) {
+                    Ok(summary) if !summary.failed.is_empty() => {
+                        let e = BackupError::UndoFailed(summary.failed.len());
+                        error!("Undo failed: {}", e);
+                        return Err(e.into());
+                    }
+                    Ok(_) => info!("Undo completed successfully"),
+                    Err(e) => {
+                        error!("Undo failed: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            None => {
+                info!("Running sync command");
+                let interface = match keys {
+                    Some(keys) => SyncInterface::Scripted(keys.clone()),
+                    None if *no_tui || !(io::stdin().is_terminal() && io::stdout().is_terminal()) => {
+                        SyncInterface::Prompt
+                    }
+                    None => SyncInterface::Screen,
+                };
+                match sync_backup_with_immich(&profile, *matching, interface, dry_run) {
+                    Ok(_) => info!("Sync completed successfully"),
+                    Err(e) => {
+                        error!("Sync failed: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+        },
         
         Commands::Full => {
             info!("Running full backup workflow");
-            match full_backup_workflow() {
+            match full_backup_workflow(&profile, dry_run) {
                 Ok(_) => info!("Full backup workflow completed successfully"),
                 Err(e) => {
                     error!("Full backup workflow failed: {}", e);
@@ -185,39 +584,83 @@ fn main() -> Result<()> {
             }
         }
         
+        Commands::Doctor { backup } => {
+            info!("Checking media files for damaged or misnamed contents");
+            match doctor(&profile, *backup) {
+                Ok(suspects) if suspects.is_empty() => info!("All files look fine"),
+                Ok(suspects) => {
+                    error!("Found {} suspicious files", suspects.len());
+                    return Err(BackupError::SuspiciousFiles(suspects.len()).into());
+                }
+                Err(e) => {
+                    error!("Doctor failed: {}", e);
+                    return Err(e.into());
+                }
+            }
+        }
+        Commands::Dedupe { mode, force, undo } => match undo {
+            Some(session) => {
+                info!("Undoing dedupe session {}", session);
+                match undo_dedupe(&profile, session, dry_run) {
+                    Ok(summary) if !summary.failed.is_empty() => {
+                        let e = BackupError::UndoFailed(summary.failed.len());
+                        error!("Undo failed: {}", e);
+                        return Err(e.into());
+                    }
+                    Ok(_) => info!("Undo completed successfully"),
+                    Err(e) => {
+                        error!("Undo failed: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            None => {
+                info!("Running dedupe command");
+                match dedupe_backup(&profile, *mode, *force, dry_run) {
+                    Ok(summary) if !summary.failed.is_empty() => {
+                        let e = BackupError::DedupeFailed(summary.failed.len());
+                        error!("Dedupe failed: {}", e);
+                        return Err(e.into());
+                    }
+                    Ok(_) => info!("Dedupe completed successfully"),
+                    Err(e) => {
+                        error!("Dedupe failed: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+        },
+
         Commands::CheckPaths => {
-            info!("Checking environment variable paths");
+            info!("Checking configured paths");
             let paths = [
-                (constants::APPLE_PHOTOS_EXPORT_DIR, "Photos export directory"),
-                (constants::RAW_PHOTOS_BACKUP_DIR, "Raw photos backup directory"),
-                (constants::IMMICH_LIB, "Immich library directory"),
+                (&profile.export_dir, "Photos export directory"),
+                (&profile.backup_dir, "Raw photos backup directory"),
+                (&profile.immich_lib, "Immich library directory"),
             ];
-            
-            for (var, desc) in paths.iter() {
-                let path = var;
-                        let path_buf = PathBuf::from(path);
-                        print!("{}: {} - ", desc, path_buf.display());
+
+            for (path, desc) in paths {
+                print!("{}: {} - ", desc, path.display());
+                io::stdout().flush()?;
+
+                match check_directory_exists_and_accessible(path) {
+                    Ok(_) => {
+                        print!("✓ exists and is accessible");
                         io::stdout().flush()?;
-                        
-                        match check_directory_exists_and_accessible(&path_buf) {
-                            Ok(_) => {
-                                print!("✓ exists and is accessible");
-                                io::stdout().flush()?;
-                                
-                                match check_external_drive_connected(&path_buf) {
-                                    Ok(_) => println!(" - ✓ drive connected"),
-                                    Err(e) => println!(" - ❌ drive not connected: {}", e),
-                                }
-                            }
-                            Err(e) => println!("❌ {}", e),
+
+                        match check_external_drive_connected(path) {
+                            Ok(_) => println!(" - ✓ drive connected"),
+                            Err(e) => println!(" - ❌ drive not connected: {}", e),
                         }
                     }
+                    Err(e) => println!("❌ {}", e),
+                }
+            }
         }
-    
 
         Commands::RepairXMP => {
             info!("Running repair command");
-            match fix_apple_xmp_files(&PathBuf::from(constants::APPLE_PHOTOS_EXPORT_DIR)) {
+            match fix_apple_xmp_files(&profile.export_dir, dry_run) {
                 Ok(_) => info!("Repair completed successfully"),
                 Err(e) => {
                     error!("Repair failed: {}", e);
@@ -228,7 +671,7 @@ fn main() -> Result<()> {
 
         Commands::StartServer => {
             info!("Starting Immich server");
-            match start_immich_server() {
+            match start_immich_server(&profile, dry_run) {
                 Ok(_) => info!("Immich server started successfully"),
                 Err(e) => {
                     error!("Failed to start Immich server: {}", e);
@@ -239,7 +682,7 @@ fn main() -> Result<()> {
 
         Commands::StopServer => {
             info!("Stopping Immich server");
-            match stop_immich_server() {
+            match stop_immich_server(&profile, dry_run) {
                 Ok(_) => info!("Immich server stopped successfully"),
                 Err(e) => {
                     error!("Failed to stop Immich server: {}", e);
@@ -247,6 +690,30 @@ fn main() -> Result<()> {
                 }
             }
         }
+
+        Commands::Cache { command } => match command {
+            CacheCommands::Rebuild => {
+                info!("Rebuilding hash cache");
+                match rebuild_hash_cache(&profile, dry_run) {
+                    Ok(count) => info!("Hashed {} files", count),
+                    Err(e) => {
+                        error!("Failed to rebuild hash cache: {}", e);
+                        return Err(e.into());
+                    }
+                }
+            }
+            CacheCommands::Prune => {
+                info!("Pruning hash cache");
+                if let Err(e) = prune_hash_cache(dry_run) {
+                    error!("Failed to prune hash cache: {}", e);
+                    return Err(e.into());
+                }
+            }
+        },
+
+        Commands::Config { .. } | Commands::Profiles { .. } => {
+            unreachable!("handled before the profile is resolved")
+        }
     }
     
     Ok(())
@@ -0,0 +1,341 @@
+use crate::constants;
+use crate::BackupError;
+use chrono::{DateTime, Utc};
+use log::{debug, warn};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// One file in the backup, as recorded in the manifest
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ManifestRecord {
+    /// Path relative to the backup root
+    pub path: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+    /// When the photo or video was taken, as far as we know
+    pub capture_time: Option<DateTime<Utc>>,
+    /// The file in the export directory it was copied from
+    pub source: PathBuf,
+    /// Identifier of the backup run that first copied it
+    pub export_run: String,
+    pub first_seen: DateTime<Utc>,
+    pub last_verified: Option<DateTime<Utc>>,
+}
+
+/// JSON Lines manifest of every file in a backup directory, stored in
+/// `<backup>/.backup-photos/manifest.jsonl`. Lines are loaded in order and a
+/// later record for the same path replaces an earlier one.
+#[derive(Debug)]
+pub struct Manifest {
+    path: PathBuf,
+    records: BTreeMap<PathBuf, ManifestRecord>,
+    dirty: bool,
+}
+
+/// Location of the manifest for a backup directory
+pub fn manifest_file(backup_dir: &Path) -> PathBuf {
+    backup_dir
+        .join(constants::STATE_DIR_NAME)
+        .join(constants::MANIFEST_FILE_NAME)
+}
+
+/// Identifier for a new backup run, based on the current time
+pub fn new_run_id() -> String {
+    chrono::Local::now().format("%Y%m%dT%H%M%S").to_string()
+}
+
+impl Manifest {
+    /// Load the manifest of a backup directory, or start an empty one
+    pub fn open(backup_dir: &Path) -> Result<Manifest, BackupError> {
+        let path = manifest_file(backup_dir);
+        let mut records = BTreeMap::new();
+
+        match fs::File::open(&path) {
+            Ok(file) => {
+                for (number, line) in BufReader::new(file).lines().enumerate() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    match serde_json::from_str::<ManifestRecord>(&line) {
+                        Ok(record) => {
+                            records.insert(record.path.clone(), record);
+                        }
+                        Err(e) => warn!(
+                            "Skipping unreadable line {} of {}: {}",
+                            number + 1,
+                            path.display(),
+                            e
+                        ),
+                    }
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(BackupError::IoError(e)),
+        }
+
+        debug!("Loaded {} manifest records from {}", records.len(), path.display());
+
+        Ok(Manifest {
+            path,
+            records,
+            dirty: false,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn len(&self) -> usize {
+        self.records.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.records.is_empty()
+    }
+
+    pub fn get(&self, path: &Path) -> Option<&ManifestRecord> {
+        self.records.get(path)
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &ManifestRecord> {
+        self.records.values()
+    }
+
+    /// Record a file as present in the backup. An existing record with the
+    /// same content keeps its first-seen time and export run.
+    pub fn record(
+        &mut self,
+        path: PathBuf,
+        sha256: String,
+        size: u64,
+        capture_time: Option<DateTime<Utc>>,
+        source: PathBuf,
+        export_run: &str,
+    ) {
+        if let Some(existing) = self.records.get(&path) {
+            if existing.sha256 == sha256 {
+                return;
+            }
+        }
+
+        self.records.insert(
+            path.clone(),
+            ManifestRecord {
+                path,
+                sha256,
+                size,
+                capture_time,
+                source,
+                export_run: export_run.to_string(),
+                first_seen: Utc::now(),
+                last_verified: None,
+            },
+        );
+        self.dirty = true;
+    }
+
+    /// Note that a file was checked and still has the recorded hash
+    pub fn mark_verified(&mut self, path: &Path, sha256: &str, at: DateTime<Utc>) -> bool {
+        match self.records.get_mut(path) {
+            Some(record) if record.sha256 == sha256 => {
+                record.last_verified = Some(at);
+                self.dirty = true;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Forget a file that is no longer in the backup
+    pub fn remove(&mut self, path: &Path) -> Option<ManifestRecord> {
+        let removed = self.records.remove(path);
+        self.dirty |= removed.is_some();
+        removed
+    }
+
+    /// Put back a record removed earlier, as it was
+    pub fn restore(&mut self, record: ManifestRecord) {
+        self.records.insert(record.path.clone(), record);
+        self.dirty = true;
+    }
+
+    /// Rewrite the manifest, one record per line, via a temporary file
+    pub fn save(&mut self) -> Result<(), BackupError> {
+        if !self.dirty {
+            return Ok(());
+        }
+
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+
+        let temp = self.path.with_extension(format!("jsonl.{}.tmp", std::process::id()));
+        {
+            let mut writer = std::io::BufWriter::new(fs::File::create(&temp)?);
+            for record in self.records.values() {
+                serde_json::to_writer(&mut writer, record)
+                    .map_err(|e| BackupError::IoError(std::io::Error::other(e)))?;
+                writer.write_all(b"\n")?;
+            }
+            writer
+                .into_inner()
+                .map_err(|e| BackupError::IoError(e.into_error()))?
+                .sync_all()?;
+        }
+        fs::rename(&temp, &self.path)?;
+
+        debug!("Saved {} manifest records to {}", self.records.len(), self.path.display());
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn taken() -> DateTime<Utc> {
+        Utc.with_ymd_and_hms(2024, 7, 14, 9, 30, 0).unwrap()
+    }
+
+    #[test]
+    fn records_survive_a_round_trip() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::open(dir.path()).unwrap();
+        assert!(manifest.is_empty());
+        manifest.record(
+            PathBuf::from("2024/07/IMG_0001.HEIC"),
+            "aaa".to_string(),
+            1234,
+            Some(taken()),
+            PathBuf::from("/export/IMG_0001.HEIC"),
+            "20240714T093000",
+        );
+        manifest.record(
+            PathBuf::from("IMG_0002.xmp"),
+            "bbb".to_string(),
+            56,
+            None,
+            PathBuf::from("/export/IMG_0002.xmp"),
+            "20240714T093000",
+        );
+        assert!(manifest.mark_verified(Path::new("IMG_0002.xmp"), "bbb", taken()));
+        manifest.save().unwrap();
+
+        let path = manifest_file(dir.path());
+        assert_eq!(manifest.path(), path);
+        assert_eq!(fs::read_to_string(&path).unwrap().lines().count(), 2);
+        let state_dir = path.parent().unwrap();
+        assert_eq!(
+            fs::read_dir(state_dir).unwrap().count(),
+            1,
+            "temporary file left behind"
+        );
+
+        let reopened = Manifest::open(dir.path()).unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(
+            reopened.records().collect::<Vec<_>>(),
+            manifest.records().collect::<Vec<_>>()
+        );
+        let record = reopened.get(Path::new("2024/07/IMG_0001.HEIC")).unwrap();
+        assert_eq!(record.capture_time, Some(taken()));
+        assert_eq!(record.size, 1234);
+        assert_eq!(record.last_verified, None);
+        let sidecar = reopened.get(Path::new("IMG_0002.xmp")).unwrap();
+        assert_eq!(sidecar.last_verified, Some(taken()));
+    }
+
+    #[test]
+    fn later_lines_win_and_unreadable_ones_are_skipped() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = manifest_file(dir.path());
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let line = |sha256: &str| {
+            format!(
+                "{{\"path\":\"IMG_0001.jpg\",\"sha256\":\"{}\",\"size\":5,\"capture_time\":null,\
+                 \"source\":\"/export/IMG_0001.jpg\",\"export_run\":\"r1\",\
+                 \"first_seen\":\"2024-07-14T09:30:00Z\",\"last_verified\":null}}\n",
+                sha256
+            )
+        };
+        fs::write(&path, format!("{}not json\n\n{}", line("old"), line("new"))).unwrap();
+
+        let manifest = Manifest::open(dir.path()).unwrap();
+        assert_eq!(manifest.len(), 1);
+        assert_eq!(
+            manifest.get(Path::new("IMG_0001.jpg")).unwrap().sha256,
+            "new"
+        );
+    }
+
+    #[test]
+    fn same_content_keeps_its_history() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::open(dir.path()).unwrap();
+        let file = PathBuf::from("IMG_0001.jpg");
+        let source = PathBuf::from("/export/IMG_0001.jpg");
+        manifest.record(
+            file.clone(),
+            "aaa".to_string(),
+            5,
+            None,
+            source.clone(),
+            "r1",
+        );
+        manifest.mark_verified(&file, "aaa", taken());
+        let first = manifest.get(&file).unwrap().clone();
+
+        manifest.record(
+            file.clone(),
+            "aaa".to_string(),
+            5,
+            None,
+            source.clone(),
+            "r2",
+        );
+        assert_eq!(manifest.get(&file).unwrap(), &first);
+
+        manifest.record(file.clone(), "bbb".to_string(), 6, None, source, "r2");
+        let replaced = manifest.get(&file).unwrap();
+        assert_eq!(replaced.export_run, "r2");
+        assert_eq!(replaced.last_verified, None);
+        assert!(!manifest.mark_verified(&file, "aaa", taken()));
+    }
+
+    #[test]
+    fn removed_record_can_be_restored() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::open(dir.path()).unwrap();
+        let file = PathBuf::from("IMG_0001.jpg");
+        manifest.record(file.clone(), "aaa".to_string(), 5, None, file.clone(), "r1");
+        manifest.save().unwrap();
+
+        let record = manifest.remove(&file).unwrap();
+        assert!(manifest.remove(&file).is_none());
+        manifest.save().unwrap();
+        assert!(Manifest::open(dir.path()).unwrap().is_empty());
+
+        manifest.restore(record.clone());
+        manifest.save().unwrap();
+        assert_eq!(
+            Manifest::open(dir.path()).unwrap().get(&file),
+            Some(&record)
+        );
+    }
+
+    #[test]
+    fn unchanged_manifest_is_not_written() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut manifest = Manifest::open(dir.path()).unwrap();
+        assert!(!manifest.mark_verified(Path::new("missing.jpg"), "aaa", taken()));
+        manifest.save().unwrap();
+        assert!(!dir.path().join(constants::STATE_DIR_NAME).exists());
+    }
+}
@@ -0,0 +1,264 @@
+use crate::constants;
+use crate::sniff;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::sync::OnceLock;
+
+const PHOTO_EXTENSIONS: [&str; 19] = [
+    "jpg", "jpeg", "png", "heic", "heif", "avif", "webp", "gif", "tif", "tiff", "dng", "raw",
+    "arw", "cr2", "nef", "raf", "orf", "rw2", "cr3",
+];
+const VIDEO_EXTENSIONS: [&str; 13] = [
+    "mp4", "mov", "avi", "m4v", "3gp", "mkv", "webm", "flv", "wmv", "mts", "m2ts", "mpg", "mpeg",
+];
+const SIDECAR_EXTENSIONS: [&str; 1] = ["xmp"];
+
+static MEDIA_TYPES: OnceLock<MediaTypes> = OnceLock::new();
+
+/// What a file in a photo library is
+#[derive(
+    Debug,
+    Clone,
+    Copy,
+    PartialEq,
+    Eq,
+    PartialOrd,
+    Ord,
+    Hash,
+    clap::ValueEnum,
+    serde::Serialize,
+    serde::Deserialize,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaKind {
+    Photo,
+    Video,
+    /// An XMP file carrying metadata for a photo or video
+    Sidecar,
+}
+
+impl MediaKind {
+    pub const ALL: [MediaKind; 3] = [MediaKind::Photo, MediaKind::Video, MediaKind::Sidecar];
+
+    /// Classify a file by its extension, or by its first bytes when it has no
+    /// extension at all. Files with an unknown extension aren't opened, nor
+    /// are hidden files, such as the temporary files of an interrupted copy,
+    /// or anything in the tool's state directory. None for anything that
+    /// isn't media.
+    pub fn of(path: &Path) -> Option<MediaKind> {
+        if path
+            .components()
+            .any(|c| c.as_os_str() == constants::STATE_DIR_NAME)
+        {
+            return None;
+        }
+        if path.extension().is_some() {
+            return MediaKind::from_extension(path);
+        }
+        let hidden = path
+            .file_name()
+            .is_some_and(|name| name.to_string_lossy().starts_with('.'));
+        if hidden {
+            return None;
+        }
+        sniff::detect(path).ok().flatten().map(sniff::Format::kind)
+    }
+
+    /// Classify a file by its extension alone, without reading it
+    pub fn from_extension(path: &Path) -> Option<MediaKind> {
+        media_types().kind_of_extension(&path.extension()?.to_string_lossy())
+    }
+
+    /// Photos and videos, as opposed to sidecars
+    pub fn is_media(self) -> bool {
+        self != MediaKind::Sidecar
+    }
+
+    /// The config key listing extra extensions of this kind
+    pub fn config_key(self) -> &'static str {
+        match self {
+            MediaKind::Photo => "extra_photo_extensions",
+            MediaKind::Video => "extra_video_extensions",
+            MediaKind::Sidecar => "extra_sidecar_extensions",
+        }
+    }
+
+    fn builtin_extensions(self) -> &'static [&'static str] {
+        match self {
+            MediaKind::Photo => &PHOTO_EXTENSIONS,
+            MediaKind::Video => &VIDEO_EXTENSIONS,
+            MediaKind::Sidecar => &SIDECAR_EXTENSIONS,
+        }
+    }
+}
+
+impl fmt::Display for MediaKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MediaKind::Photo => write!(f, "photo"),
+            MediaKind::Video => write!(f, "video"),
+            MediaKind::Sidecar => write!(f, "sidecar"),
+        }
+    }
+}
+
+/// The extensions recognised as each kind of media: the built-in formats
+/// plus any added in the config file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MediaTypes {
+    extensions: BTreeMap<String, MediaKind>,
+}
+
+impl Default for MediaTypes {
+    fn default() -> Self {
+        let extensions = MediaKind::ALL
+            .into_iter()
+            .flat_map(|kind| {
+                kind.builtin_extensions()
+                    .iter()
+                    .map(move |ext| (ext.to_string(), kind))
+            })
+            .collect();
+        MediaTypes { extensions }
+    }
+}
+
+impl MediaTypes {
+    /// Recognise another extension as `kind`. A leading dot is ignored and
+    /// case doesn't matter. Fails if the extension already means another kind.
+    pub fn add(&mut self, kind: MediaKind, extension: &str) -> Result<(), String> {
+        let ext = extension.trim().trim_start_matches('.').to_lowercase();
+        if ext.is_empty() || !ext.chars().all(|c| c.is_ascii_alphanumeric()) {
+            return Err(format!("'{}' is not a file extension", extension.trim()));
+        }
+
+        match self.extensions.get(&ext) {
+            Some(existing) if *existing != kind => Err(format!(
+                "'{}' is already a {} extension and can't also be a {} extension",
+                ext, existing, kind
+            )),
+            _ => {
+                self.extensions.insert(ext, kind);
+                Ok(())
+            }
+        }
+    }
+
+    pub fn kind_of_extension(&self, extension: &str) -> Option<MediaKind> {
+        self.extensions.get(&extension.to_lowercase()).copied()
+    }
+
+    /// Every extension recognised as `kind`, sorted
+    pub fn extensions(&self, kind: MediaKind) -> Vec<&str> {
+        self.extensions
+            .iter()
+            .filter(|(_, k)| **k == kind)
+            .map(|(ext, _)| ext.as_str())
+            .collect()
+    }
+}
+
+/// Install the media types every command classifies files with. Only the
+/// first call has any effect; returns false if the types were already set.
+pub fn configure(types: MediaTypes) -> bool {
+    MEDIA_TYPES.set(types).is_ok()
+}
+
+/// The media types in effect, the built-in ones unless `configure` was called
+pub fn media_types() -> &'static MediaTypes {
+    MEDIA_TYPES.get_or_init(MediaTypes::default)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn added_extensions_ignore_case_and_leading_dot() {
+        let mut types = MediaTypes::default();
+        types.add(MediaKind::Photo, ".PSD").unwrap();
+        types.add(MediaKind::Photo, " jxl ").unwrap();
+
+        assert_eq!(types.kind_of_extension("psd"), Some(MediaKind::Photo));
+        assert_eq!(types.kind_of_extension("Psd"), Some(MediaKind::Photo));
+        assert_eq!(types.kind_of_extension("JXL"), Some(MediaKind::Photo));
+        assert_eq!(types.kind_of_extension("JPG"), Some(MediaKind::Photo));
+        assert!(types.extensions(MediaKind::Photo).contains(&"psd"));
+        assert!(!types.extensions(MediaKind::Video).contains(&"psd"));
+    }
+
+    #[test]
+    fn extension_of_another_kind_is_rejected() {
+        let mut types = MediaTypes::default();
+        let err = types.add(MediaKind::Video, "JPG").unwrap_err();
+        assert!(err.contains("already a photo extension"), "{}", err);
+        assert_eq!(types.kind_of_extension("jpg"), Some(MediaKind::Photo));
+
+        types.add(MediaKind::Sidecar, "aae").unwrap();
+        assert!(types.add(MediaKind::Photo, "AAE").is_err());
+        // Adding an extension again as the same kind is fine
+        types.add(MediaKind::Photo, "jpeg").unwrap();
+        assert_eq!(types, {
+            let mut expected = MediaTypes::default();
+            expected.add(MediaKind::Sidecar, "aae").unwrap();
+            expected
+        });
+    }
+
+    #[test]
+    fn invalid_extensions_are_rejected() {
+        let mut types = MediaTypes::default();
+        for ext in ["", ".", "tar.gz", "j pg", "*"] {
+            assert!(types.add(MediaKind::Photo, ext).is_err(), "{:?}", ext);
+        }
+        assert_eq!(types, MediaTypes::default());
+    }
+
+    #[test]
+    fn configure_only_takes_effect_once() {
+        media_types();
+        let mut types = MediaTypes::default();
+        types.add(MediaKind::Photo, "psd").unwrap();
+        assert!(!configure(types));
+        assert_eq!(media_types().kind_of_extension("psd"), None);
+    }
+
+    #[test]
+    fn only_files_without_an_extension_are_sniffed() {
+        let dir = tempfile::tempdir().unwrap();
+        let jpeg = [0xff, 0xd8, 0xff, 0xe0, 0, 0x10, b'J', b'F', b'I', b'F'];
+        let write = |name: &str| {
+            let path = dir.path().join(name);
+            fs::create_dir_all(path.parent().unwrap()).unwrap();
+            fs::write(&path, jpeg).unwrap();
+            path
+        };
+
+        assert_eq!(MediaKind::of(&write("IMG_0001")), Some(MediaKind::Photo));
+        assert_eq!(
+            MediaKind::of(&write("IMG_0002.JPG")),
+            Some(MediaKind::Photo)
+        );
+        assert_eq!(MediaKind::of(&write("clip.MOV")), Some(MediaKind::Video));
+        assert_eq!(MediaKind::of(&write("IMG_0003.bak")), None);
+        assert_eq!(MediaKind::of(&write(".IMG_0004")), None);
+        assert_eq!(MediaKind::of(&dir.path().join("missing")), None);
+    }
+
+    #[test]
+    fn state_directory_is_not_media() {
+        let dir = tempfile::tempdir().unwrap();
+        let state = dir.path().join(constants::STATE_DIR_NAME);
+        fs::create_dir(&state).unwrap();
+        let copy = state.join("IMG_0001.jpg");
+        fs::write(&copy, [0xff, 0xd8, 0xff]).unwrap();
+        let plan = state.join("plan");
+        fs::write(&plan, [0xff, 0xd8, 0xff]).unwrap();
+
+        assert_eq!(MediaKind::of(&copy), None);
+        assert_eq!(MediaKind::of(&plan), None);
+        assert_eq!(MediaKind::from_extension(&copy), Some(MediaKind::Photo));
+    }
+}
@@ -0,0 +1,891 @@
+use crate::media::MediaKind;
+use chrono::{DateTime, FixedOffset, Local, NaiveDateTime, TimeZone, Utc};
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// QuickTime metadata key Apple uses to pair the video half of a Live Photo
+const QUICKTIME_CONTENT_IDENTIFIER: &str = "com.apple.quicktime.content.identifier";
+
+/// Apple MakerNote tag holding the Live Photo content identifier of a still
+const APPLE_CONTENT_IDENTIFIER_TAG: u16 = 0x0011;
+
+const MAKE_TAG: u16 = 0x010f;
+const MODEL_TAG: u16 = 0x0110;
+const DATE_TIME_TAG: u16 = 0x0132;
+const EXIF_IFD_POINTER_TAG: u16 = 0x8769;
+const GPS_IFD_POINTER_TAG: u16 = 0x8825;
+const DATE_TIME_ORIGINAL_TAG: u16 = 0x9003;
+const DATE_TIME_DIGITIZED_TAG: u16 = 0x9004;
+const OFFSET_TIME_ORIGINAL_TAG: u16 = 0x9011;
+const MAKER_NOTE_TAG: u16 = 0x927c;
+
+const GPS_LATITUDE_REF_TAG: u16 = 0x0001;
+const GPS_LATITUDE_TAG: u16 = 0x0002;
+const GPS_LONGITUDE_REF_TAG: u16 = 0x0003;
+const GPS_LONGITUDE_TAG: u16 = 0x0004;
+const GPS_ALTITUDE_REF_TAG: u16 = 0x0005;
+const GPS_ALTITUDE_TAG: u16 = 0x0006;
+
+const QUICKTIME_CREATION_DATE: &str = "com.apple.quicktime.creationdate";
+const QUICKTIME_MAKE: &str = "com.apple.quicktime.make";
+const QUICKTIME_MODEL: &str = "com.apple.quicktime.model";
+const QUICKTIME_LOCATION: &str = "com.apple.quicktime.location.ISO6709";
+
+/// Seconds from the QuickTime epoch (1904-01-01) to the Unix epoch
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+
+/// Largest box or segment we are willing to read into memory
+const MAX_METADATA_SIZE: u64 = 64 * 1024 * 1024;
+
+/// The Live Photo content identifier of a photo or video, which Apple writes
+/// to both halves of a Live Photo so they can be paired even when renamed
+pub fn content_identifier(path: &Path) -> Option<String> {
+    match MediaKind::of(path)? {
+        MediaKind::Video => quicktime_content_identifier(path).ok().flatten(),
+        MediaKind::Photo => {
+            let tiff = read_exif(path).ok().flatten()?;
+            apple_content_identifier(&tiff)
+        }
+        MediaKind::Sidecar => None,
+    }
+}
+
+/// When a photo or video was taken, as the camera's clock read it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CaptureTime {
+    /// Wall-clock time where the photo was taken
+    pub local: NaiveDateTime,
+    /// Offset from UTC, when the file records one
+    pub offset: Option<FixedOffset>,
+}
+
+impl CaptureTime {
+    /// The moment in UTC, assuming this machine's time zone when the file
+    /// has no offset
+    pub fn to_utc(&self) -> DateTime<Utc> {
+        match self.offset {
+            Some(offset) => offset
+                .from_local_datetime(&self.local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+            None => Local
+                .from_local_datetime(&self.local)
+                .earliest()
+                .map(|time| time.with_timezone(&Utc)),
+        }
+        .unwrap_or_else(|| Utc.from_utc_datetime(&self.local))
+    }
+}
+
+impl From<DateTime<FixedOffset>> for CaptureTime {
+    fn from(time: DateTime<FixedOffset>) -> Self {
+        CaptureTime {
+            local: time.naive_local(),
+            offset: Some(*time.offset()),
+        }
+    }
+}
+
+impl fmt::Display for CaptureTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.local.format("%Y-%m-%d %H:%M:%S"))?;
+        match self.offset {
+            Some(offset) => write!(f, " {}", offset),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Where a photo or video was taken, in degrees, north and east positive
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GpsPosition {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Metres above sea level
+    pub altitude: Option<f64>,
+}
+
+impl fmt::Display for GpsPosition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.6}, {:.6}", self.latitude, self.longitude)?;
+        match self.altitude {
+            Some(altitude) => write!(f, " ({:.0} m)", altitude),
+            None => Ok(()),
+        }
+    }
+}
+
+/// What a photo or video's own metadata says about it
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct MediaMetadata {
+    pub captured_at: Option<CaptureTime>,
+    pub gps: Option<GpsPosition>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+}
+
+impl MediaMetadata {
+    /// Make and model together, without repeating a make the model starts with
+    pub fn camera(&self) -> Option<String> {
+        match (&self.camera_make, &self.camera_model) {
+            (Some(make), Some(model)) if model.starts_with(make.as_str()) => Some(model.clone()),
+            (Some(make), Some(model)) => Some(format!("{} {}", make, model)),
+            (make, model) => make.clone().or_else(|| model.clone()),
+        }
+    }
+}
+
+/// Read the capture time, GPS position and camera of a photo (EXIF in JPEG,
+/// HEIF/HEIC or TIFF-based RAW) or video (QuickTime/MP4 atoms). Fields the
+/// file doesn't record are left empty.
+pub fn read_metadata(path: &Path) -> io::Result<MediaMetadata> {
+    match MediaKind::of(path) {
+        Some(MediaKind::Photo) => Ok(read_exif(path)?
+            .map(|tiff| exif_metadata(&tiff))
+            .unwrap_or_default()),
+        Some(MediaKind::Video) => Ok(read_moov(path)?
+            .map(|moov| quicktime_media_metadata(&moov))
+            .unwrap_or_default()),
+        _ => Ok(MediaMetadata::default()),
+    }
+}
+
+/// The capture time recorded in a photo or video, if any
+pub fn capture_time(path: &Path) -> Option<CaptureTime> {
+    read_metadata(path).ok()?.captured_at
+}
+
+/// A TIFF structure (the body of an EXIF block) held in memory
+pub(crate) struct Tiff {
+    data: Vec<u8>,
+    big_endian: bool,
+    /// Offset of the first IFD
+    first_ifd: usize,
+}
+
+/// One entry of an image file directory
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct IfdEntry {
+    pub tag: u16,
+    pub kind: u16,
+    pub count: u32,
+    /// Offset of the entry's 4-byte value/offset field
+    value_field: usize,
+}
+
+impl Tiff {
+    /// Parse a TIFF header ("II*\0" or "MM\0*") at the start of `data`, also
+    /// accepting the variants used by Olympus ORF and Panasonic RW2 files
+    pub(crate) fn parse(data: Vec<u8>) -> Option<Tiff> {
+        let big_endian = match data.get(..4)? {
+            [b'I', b'I', 42, 0] | [b'I', b'I', b'R', b'O' | b'S'] | [b'I', b'I', b'U', 0] => false,
+            [b'M', b'M', 0, 42] | [b'M', b'M', b'O', b'R'] => true,
+            _ => return None,
+        };
+        let mut tiff = Tiff {
+            data,
+            big_endian,
+            first_ifd: 0,
+        };
+        tiff.first_ifd = tiff.u32(4)? as usize;
+        Some(tiff)
+    }
+
+    pub(crate) fn first_ifd(&self) -> usize {
+        self.first_ifd
+    }
+
+    pub(crate) fn u16(&self, offset: usize) -> Option<u16> {
+        let bytes: [u8; 2] = self.data.get(offset..offset.checked_add(2)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u16::from_be_bytes(bytes)
+        } else {
+            u16::from_le_bytes(bytes)
+        })
+    }
+
+    pub(crate) fn u32(&self, offset: usize) -> Option<u32> {
+        let bytes: [u8; 4] = self.data.get(offset..offset.checked_add(4)?)?.try_into().ok()?;
+        Some(if self.big_endian {
+            u32::from_be_bytes(bytes)
+        } else {
+            u32::from_le_bytes(bytes)
+        })
+    }
+
+    /// The entries of the IFD at `offset`
+    pub(crate) fn entries(&self, offset: usize) -> Vec<IfdEntry> {
+        let Some(count) = self.u16(offset) else {
+            return Vec::new();
+        };
+        (0..count as usize)
+            .map_while(|i| {
+                let entry = offset.checked_add(2 + i * 12)?;
+                Some(IfdEntry {
+                    tag: self.u16(entry)?,
+                    kind: self.u16(entry.checked_add(2)?)?,
+                    count: self.u32(entry.checked_add(4)?)?,
+                    value_field: entry + 8,
+                })
+            })
+            .collect()
+    }
+
+    pub(crate) fn find(&self, ifd: usize, tag: u16) -> Option<IfdEntry> {
+        self.entries(ifd).into_iter().find(|entry| entry.tag == tag)
+    }
+
+    /// The raw bytes of an entry's value, following the offset when it
+    /// doesn't fit in the entry itself
+    pub(crate) fn value_bytes(&self, entry: &IfdEntry) -> Option<&[u8]> {
+        let (start, len) = self.value_range(entry)?;
+        self.data.get(start..start.checked_add(len)?)
+    }
+
+    /// Where an entry's value starts and how many bytes it takes
+    fn value_range(&self, entry: &IfdEntry) -> Option<(usize, usize)> {
+        let unit: usize = match entry.kind {
+            1 | 2 | 6 | 7 => 1,
+            3 | 8 => 2,
+            4 | 9 | 11 => 4,
+            5 | 10 | 12 => 8,
+            _ => return None,
+        };
+        let len = unit.checked_mul(entry.count as usize)?;
+        let start = if len <= 4 {
+            entry.value_field
+        } else {
+            self.u32(entry.value_field)? as usize
+        };
+        Some((start, len))
+    }
+
+    /// An ASCII value, without its trailing NULs
+    pub(crate) fn ascii(&self, entry: &IfdEntry) -> Option<String> {
+        let bytes = self.value_bytes(entry)?;
+        let text = String::from_utf8_lossy(bytes);
+        let text = text.trim_end_matches('\0').trim();
+        (!text.is_empty()).then(|| text.to_string())
+    }
+
+    /// Where the IFD an entry points to starts (for EXIF and GPS pointers)
+    pub(crate) fn pointer(&self, entry: &IfdEntry) -> Option<usize> {
+        self.u32(entry.value_field).map(|offset| offset as usize)
+    }
+
+    /// The `index`th value of an unsigned RATIONAL entry
+    pub(crate) fn rational(&self, entry: &IfdEntry, index: usize) -> Option<f64> {
+        if entry.kind != 5 || index >= entry.count as usize {
+            return None;
+        }
+        let (start, _) = self.value_range(entry)?;
+        let start = start.checked_add(index * 8)?;
+        let numerator = self.u32(start)?;
+        let denominator = self.u32(start.checked_add(4)?)?;
+        (denominator != 0).then(|| numerator as f64 / denominator as f64)
+    }
+}
+
+/// The content identifier stored in an Apple MakerNote, if the photo has one
+fn apple_content_identifier(tiff: &Tiff) -> Option<String> {
+    let exif_ifd = tiff.pointer(&tiff.find(tiff.first_ifd(), EXIF_IFD_POINTER_TAG)?)?;
+    let maker_note = tiff.value_bytes(&tiff.find(exif_ifd, MAKER_NOTE_TAG)?)?;
+
+    // "Apple iOS\0", a 2-byte version and "MM", then an IFD whose offsets are
+    // relative to the start of the MakerNote
+    if !maker_note.starts_with(b"Apple iOS\0") || maker_note.get(12..14)? != b"MM" {
+        return None;
+    }
+    let note = Tiff {
+        data: maker_note.to_vec(),
+        big_endian: true,
+        first_ifd: 14,
+    };
+    note.ascii(&note.find(14, APPLE_CONTENT_IDENTIFIER_TAG)?)
+}
+
+fn exif_metadata(tiff: &Tiff) -> MediaMetadata {
+    let ifd0 = tiff.first_ifd();
+    let exif_ifd = tiff
+        .find(ifd0, EXIF_IFD_POINTER_TAG)
+        .and_then(|entry| tiff.pointer(&entry));
+    let ascii = |ifd: Option<usize>, tag: u16| tiff.ascii(&tiff.find(ifd?, tag)?);
+
+    let local = [DATE_TIME_ORIGINAL_TAG, DATE_TIME_DIGITIZED_TAG]
+        .into_iter()
+        .find_map(|tag| ascii(exif_ifd, tag))
+        .or_else(|| ascii(Some(ifd0), DATE_TIME_TAG))
+        .and_then(|text| NaiveDateTime::parse_from_str(&text, "%Y:%m:%d %H:%M:%S").ok());
+    let offset = ascii(exif_ifd, OFFSET_TIME_ORIGINAL_TAG).and_then(|text| parse_offset(&text));
+
+    MediaMetadata {
+        captured_at: local.map(|local| CaptureTime { local, offset }),
+        gps: tiff
+            .find(ifd0, GPS_IFD_POINTER_TAG)
+            .and_then(|entry| tiff.pointer(&entry))
+            .and_then(|gps_ifd| exif_gps(tiff, gps_ifd)),
+        camera_make: ascii(Some(ifd0), MAKE_TAG),
+        camera_model: ascii(Some(ifd0), MODEL_TAG),
+    }
+}
+
+/// Parse an EXIF time zone offset such as "+02:00"
+fn parse_offset(text: &str) -> Option<FixedOffset> {
+    let sign = match text.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let (hours, minutes) = text.get(1..)?.split_once(':')?;
+    let hours = hours.parse::<i32>().ok()?.checked_mul(3600)?;
+    let minutes = minutes.parse::<i32>().ok()?.checked_mul(60)?;
+    FixedOffset::east_opt(sign * hours.checked_add(minutes)?)
+}
+
+/// Latitude and longitude are stored as degrees, minutes and seconds with
+/// N/S and E/W references; altitude has a below-sea-level flag
+fn exif_gps(tiff: &Tiff, ifd: usize) -> Option<GpsPosition> {
+    let degrees = |tag: u16| {
+        let entry = tiff.find(ifd, tag)?;
+        Some(
+            tiff.rational(&entry, 0)?
+                + tiff.rational(&entry, 1).unwrap_or(0.0) / 60.0
+                + tiff.rational(&entry, 2).unwrap_or(0.0) / 3600.0,
+        )
+    };
+    let reference = |tag: u16| tiff.find(ifd, tag).and_then(|entry| tiff.ascii(&entry));
+
+    let mut latitude = degrees(GPS_LATITUDE_TAG)?;
+    let mut longitude = degrees(GPS_LONGITUDE_TAG)?;
+    if reference(GPS_LATITUDE_REF_TAG).as_deref() == Some("S") {
+        latitude = -latitude;
+    }
+    if reference(GPS_LONGITUDE_REF_TAG).as_deref() == Some("W") {
+        longitude = -longitude;
+    }
+
+    let altitude = tiff
+        .find(ifd, GPS_ALTITUDE_TAG)
+        .and_then(|entry| tiff.rational(&entry, 0))
+        .map(|altitude| {
+            let below_sea_level = tiff
+                .find(ifd, GPS_ALTITUDE_REF_TAG)
+                .and_then(|entry| tiff.value_bytes(&entry)?.first().copied())
+                == Some(1);
+            if below_sea_level {
+                -altitude
+            } else {
+                altitude
+            }
+        });
+
+    Some(GpsPosition {
+        latitude,
+        longitude,
+        altitude,
+    })
+}
+
+/// Find and read the EXIF block of a JPEG, HEIF/HEIC or TIFF-based file
+pub(crate) fn read_exif(path: &Path) -> io::Result<Option<Tiff>> {
+    let mut file = File::open(path)?;
+    let mut magic = [0u8; 12];
+    let read = file.read(&mut magic)?;
+    file.seek(SeekFrom::Start(0))?;
+
+    if read >= 2 && magic[..2] == [0xff, 0xd8] {
+        jpeg_exif(&mut file)
+    } else if read >= 12 && &magic[4..8] == b"ftyp" {
+        heif_exif(&mut file)
+    } else if read >= 4 && (magic[..2] == *b"II" || magic[..2] == *b"MM") {
+        let mut data = Vec::new();
+        file.take(MAX_METADATA_SIZE).read_to_end(&mut data)?;
+        Ok(Tiff::parse(data))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Walk the JPEG segments up to the image data looking for an APP1 Exif segment
+fn jpeg_exif(file: &mut File) -> io::Result<Option<Tiff>> {
+    file.seek(SeekFrom::Start(2))?;
+    loop {
+        let mut marker = [0u8; 2];
+        if file.read_exact(&mut marker).is_err() || marker[0] != 0xff {
+            return Ok(None);
+        }
+        match marker[1] {
+            // Padding, and markers that have no length
+            0xff => {
+                file.seek(SeekFrom::Current(-1))?;
+                continue;
+            }
+            0x01 | 0xd0..=0xd7 => continue,
+            // Start of scan or end of image: no more metadata
+            0xda | 0xd9 => return Ok(None),
+            _ => {}
+        }
+
+        let mut length = [0u8; 2];
+        file.read_exact(&mut length)?;
+        let length = u16::from_be_bytes(length).saturating_sub(2) as usize;
+
+        if marker[1] == 0xe1 {
+            let mut segment = vec![0u8; length];
+            file.read_exact(&mut segment)?;
+            if let Some(tiff) = segment.strip_prefix(b"Exif\0\0") {
+                return Ok(Tiff::parse(tiff.to_vec()));
+            }
+        } else {
+            file.seek(SeekFrom::Current(length as i64))?;
+        }
+    }
+}
+
+/// An ISO base media box header: the box type, where its payload starts and how long it is
+struct BoxHeader {
+    kind: [u8; 4],
+    payload_start: u64,
+    payload_len: u64,
+}
+
+/// Read the box header at the current position, or None at the end of the file
+fn read_box_header(file: &mut File, file_len: u64) -> io::Result<Option<BoxHeader>> {
+    let start = file.stream_position()?;
+    if start.checked_add(8).is_none_or(|end| end > file_len) {
+        return Ok(None);
+    }
+    let mut header = [0u8; 8];
+    file.read_exact(&mut header)?;
+    let size = u32::from_be_bytes([header[0], header[1], header[2], header[3]]) as u64;
+    let kind = [header[4], header[5], header[6], header[7]];
+
+    let (header_len, size) = match size {
+        0 => (8, file_len - start),
+        1 => {
+            let mut large = [0u8; 8];
+            file.read_exact(&mut large)?;
+            (16, u64::from_be_bytes(large))
+        }
+        size => (8, size),
+    };
+    // A size running past the end of the file, or past u64, means the file is damaged
+    if size < header_len || start.checked_add(size).is_none_or(|end| end > file_len) {
+        return Ok(None);
+    }
+
+    Ok(Some(BoxHeader {
+        kind,
+        payload_start: start + header_len,
+        payload_len: size - header_len,
+    }))
+}
+
+/// Read the payload of the first top-level box of the given type
+fn read_top_level_box(file: &mut File, kind: &[u8; 4]) -> io::Result<Option<Vec<u8>>> {
+    let file_len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(0))?;
+
+    while let Some(header) = read_box_header(file, file_len)? {
+        if &header.kind == kind {
+            if header.payload_len > MAX_METADATA_SIZE {
+                return Ok(None);
+            }
+            let mut payload = vec![0u8; header.payload_len as usize];
+            file.read_exact(&mut payload)?;
+            return Ok(Some(payload));
+        }
+        file.seek(SeekFrom::Start(header.payload_start + header.payload_len))?;
+    }
+
+    Ok(None)
+}
+
+/// Iterate over the boxes packed in a payload: (type, payload)
+fn child_boxes(data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+    let mut boxes = Vec::new();
+    let mut offset = 0usize;
+    while offset + 8 <= data.len() {
+        let size = u32::from_be_bytes(data[offset..offset + 4].try_into().unwrap_or_default()) as usize;
+        let kind: [u8; 4] = data[offset + 4..offset + 8].try_into().unwrap_or_default();
+        let (header_len, size) = match size {
+            0 => (8, data.len() - offset),
+            1 => match data
+                .get(offset + 8..offset + 16)
+                .and_then(|large| usize::try_from(u64::from_be_bytes(large.try_into().ok()?)).ok())
+            {
+                Some(large) => (16, large),
+                None => break,
+            },
+            size => (8, size),
+        };
+        let Some(end) = offset.checked_add(size).filter(|&end| end <= data.len()) else {
+            break;
+        };
+        if size < header_len {
+            break;
+        }
+        boxes.push((kind, &data[offset + header_len..end]));
+        offset = end;
+    }
+    boxes
+}
+
+fn find_child<'a>(data: &'a [u8], kind: &[u8; 4]) -> Option<&'a [u8]> {
+    child_boxes(data)
+        .into_iter()
+        .find(|(child, _)| child == kind)
+        .map(|(_, payload)| payload)
+}
+
+/// Read a big-endian unsigned integer of 0, 2, 4 or 8 bytes
+fn read_uint(data: &[u8], offset: &mut usize, size: usize) -> Option<u64> {
+    let bytes = data.get(*offset..offset.checked_add(size)?)?;
+    *offset += size;
+    Some(bytes.iter().fold(0u64, |value, byte| (value << 8) | *byte as u64))
+}
+
+/// Find the Exif item of a HEIF/HEIC file through its `iinf` and `iloc` boxes
+fn heif_exif(file: &mut File) -> io::Result<Option<Tiff>> {
+    let Some(meta) = read_top_level_box(file, b"meta")? else {
+        return Ok(None);
+    };
+    // `meta` is a full box: skip version and flags
+    let Some(children) = meta.get(4..) else {
+        return Ok(None);
+    };
+
+    let Some(item_id) = find_child(children, b"iinf").and_then(exif_item_id) else {
+        return Ok(None);
+    };
+    let Some(location) = find_child(children, b"iloc").and_then(|iloc| item_location(iloc, item_id)) else {
+        return Ok(None);
+    };
+
+    let item = match location {
+        ItemLocation::File { offset, length } => {
+            if length > MAX_METADATA_SIZE {
+                return Ok(None);
+            }
+            let mut item = vec![0u8; length as usize];
+            file.seek(SeekFrom::Start(offset))?;
+            file.read_exact(&mut item)?;
+            item
+        }
+        ItemLocation::Idat { offset, length } => {
+            let Some(idat) = find_child(children, b"idat") else {
+                return Ok(None);
+            };
+            let range = offset
+                .checked_add(length)
+                .and_then(|end| Some(usize::try_from(offset).ok()?..usize::try_from(end).ok()?));
+            match range.and_then(|range| idat.get(range)) {
+                Some(item) => item.to_vec(),
+                None => return Ok(None),
+            }
+        }
+    };
+
+    // The item starts with the offset of the TIFF header, usually past "Exif\0\0"
+    let Some(skip) = item.get(..4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize) else {
+        return Ok(None);
+    };
+    Ok(skip
+        .checked_add(4)
+        .and_then(|start| item.get(start..))
+        .and_then(|tiff| Tiff::parse(tiff.to_vec())))
+}
+
+/// The item id of the `Exif` entry in an `iinf` box
+fn exif_item_id(iinf: &[u8]) -> Option<u32> {
+    let version = *iinf.first()?;
+    let entries = if version == 0 { iinf.get(6..)? } else { iinf.get(8..)? };
+
+    child_boxes(entries).into_iter().find_map(|(kind, infe)| {
+        if &kind != b"infe" {
+            return None;
+        }
+        let version = *infe.first()?;
+        let (item_id, type_offset) = match version {
+            2 => (u16::from_be_bytes(infe.get(4..6)?.try_into().ok()?) as u32, 8),
+            3 => (u32::from_be_bytes(infe.get(4..8)?.try_into().ok()?), 10),
+            _ => return None,
+        };
+        (infe.get(type_offset..type_offset + 4)? == b"Exif").then_some(item_id)
+    })
+}
+
+enum ItemLocation {
+    File { offset: u64, length: u64 },
+    Idat { offset: u64, length: u64 },
+}
+
+/// Where the first extent of an item is stored, according to an `iloc` box
+fn item_location(iloc: &[u8], wanted: u32) -> Option<ItemLocation> {
+    let version = *iloc.first()?;
+    let sizes = *iloc.get(4)?;
+    let more_sizes = *iloc.get(5)?;
+    let offset_size = (sizes >> 4) as usize;
+    let length_size = (sizes & 0x0f) as usize;
+    let base_offset_size = (more_sizes >> 4) as usize;
+    let index_size = if version == 1 || version == 2 {
+        (more_sizes & 0x0f) as usize
+    } else {
+        0
+    };
+
+    let mut offset = 6;
+    let item_count = if version < 2 {
+        read_uint(iloc, &mut offset, 2)?
+    } else {
+        read_uint(iloc, &mut offset, 4)?
+    };
+
+    for _ in 0..item_count {
+        let item_id = read_uint(iloc, &mut offset, if version < 2 { 2 } else { 4 })? as u32;
+        let construction_method = if version == 1 || version == 2 {
+            read_uint(iloc, &mut offset, 2)? & 0x0f
+        } else {
+            0
+        };
+        let _data_reference_index = read_uint(iloc, &mut offset, 2)?;
+        let base_offset = read_uint(iloc, &mut offset, base_offset_size)?;
+        let extent_count = read_uint(iloc, &mut offset, 2)?;
+
+        let mut first_extent = None;
+        for _ in 0..extent_count {
+            read_uint(iloc, &mut offset, index_size)?;
+            let extent_offset = read_uint(iloc, &mut offset, offset_size)?;
+            let extent_length = read_uint(iloc, &mut offset, length_size)?;
+            first_extent.get_or_insert((extent_offset, extent_length));
+        }
+
+        if item_id == wanted {
+            let (extent_offset, length) = first_extent?;
+            let offset = base_offset.checked_add(extent_offset)?;
+            return match construction_method {
+                0 => Some(ItemLocation::File { offset, length }),
+                1 => Some(ItemLocation::Idat { offset, length }),
+                _ => None,
+            };
+        }
+    }
+
+    None
+}
+
+/// Read the `moov` box of a QuickTime or MP4 file
+pub(crate) fn read_moov(path: &Path) -> io::Result<Option<Vec<u8>>> {
+    let mut file = File::open(path)?;
+    read_top_level_box(&mut file, b"moov")
+}
+
+/// The values of the `mdta` metadata keys in a `meta` box (QuickTime style,
+/// as written by iPhones): (key, value) pairs
+pub(crate) fn quicktime_metadata(moov: &[u8]) -> Vec<(String, Vec<u8>)> {
+    let Some(meta) = find_child(moov, b"meta").or_else(|| {
+        find_child(moov, b"udta").and_then(|udta| find_child(udta, b"meta"))
+    }) else {
+        return Vec::new();
+    };
+
+    // QuickTime's `meta` has no version and flags, MP4's does
+    let children = if find_child(meta, b"keys").is_some() {
+        meta
+    } else {
+        meta.get(4..).unwrap_or_default()
+    };
+
+    let Some(keys) = find_child(children, b"keys") else {
+        return Vec::new();
+    };
+    let Some(ilst) = find_child(children, b"ilst") else {
+        return Vec::new();
+    };
+
+    // keys: version/flags, entry count, then (size, namespace, name) entries numbered from 1
+    let mut names = Vec::new();
+    let mut offset = 8;
+    while let Some(size) = keys
+        .get(offset..offset + 4)
+        .map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]) as usize)
+    {
+        if size < 8 || offset.checked_add(size).is_none_or(|end| end > keys.len()) {
+            break;
+        }
+        names.push(String::from_utf8_lossy(&keys[offset + 8..offset + size]).to_string());
+        offset += size;
+    }
+
+    // ilst: one box per value, whose type is the key's index, holding a `data` box
+    child_boxes(ilst)
+        .into_iter()
+        .filter_map(|(kind, item)| {
+            let index = u32::from_be_bytes(kind) as usize;
+            let name = names.get(index.checked_sub(1)?)?;
+            let data = find_child(item, b"data")?;
+            // data: type indicator and locale before the value
+            Some((name.clone(), data.get(8..)?.to_vec()))
+        })
+        .collect()
+}
+
+fn quicktime_content_identifier(path: &Path) -> io::Result<Option<String>> {
+    let Some(moov) = read_moov(path)? else {
+        return Ok(None);
+    };
+    Ok(quicktime_metadata(&moov)
+        .into_iter()
+        .find(|(key, _)| key == QUICKTIME_CONTENT_IDENTIFIER)
+        .map(|(_, value)| String::from_utf8_lossy(&value).trim_end_matches('\0').to_string())
+        .filter(|id| !id.is_empty()))
+}
+
+/// Apple's metadata keys when present, otherwise the movie header's
+/// creation time (UTC) and the `©xyz` location other phones write
+fn quicktime_media_metadata(moov: &[u8]) -> MediaMetadata {
+    let values = quicktime_metadata(moov);
+    let text = |key: &str| {
+        values
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| String::from_utf8_lossy(value).trim_end_matches('\0').trim().to_string())
+            .filter(|value| !value.is_empty())
+    };
+
+    let captured_at = text(QUICKTIME_CREATION_DATE)
+        .and_then(|date| DateTime::parse_from_str(&date, "%Y-%m-%dT%H:%M:%S%z").ok())
+        .map(CaptureTime::from)
+        .or_else(|| movie_creation_time(moov));
+
+    let location = text(QUICKTIME_LOCATION).or_else(|| {
+        let udta = find_child(moov, b"udta")?;
+        // ©xyz: 2-byte length and 2-byte language before the text
+        let xyz = find_child(udta, b"\xa9xyz")?;
+        Some(String::from_utf8_lossy(xyz.get(4..)?).to_string())
+    });
+
+    MediaMetadata {
+        captured_at,
+        gps: location.as_deref().and_then(parse_iso6709),
+        camera_make: text(QUICKTIME_MAKE),
+        camera_model: text(QUICKTIME_MODEL),
+    }
+}
+
+/// The creation time in the `mvhd` box, which is UTC seconds since 1904
+fn movie_creation_time(moov: &[u8]) -> Option<CaptureTime> {
+    let mvhd = find_child(moov, b"mvhd")?;
+    let mut offset = 4;
+    let seconds = match mvhd.first()? {
+        1 => read_uint(mvhd, &mut offset, 8)?,
+        _ => read_uint(mvhd, &mut offset, 4)?,
+    };
+    if seconds == 0 {
+        return None;
+    }
+    let seconds = i64::try_from(seconds).ok()?.checked_sub(QUICKTIME_EPOCH_OFFSET)?;
+    let time = DateTime::from_timestamp(seconds, 0)?;
+    Some(CaptureTime::from(time.fixed_offset()))
+}
+
+/// Parse an ISO 6709 position such as "+48.8584+002.2945+035.000/"
+fn parse_iso6709(text: &str) -> Option<GpsPosition> {
+    let text = text.trim().trim_end_matches('/');
+    let mut numbers = Vec::new();
+    let mut start = 0;
+    for (index, c) in text.char_indices().skip(1) {
+        if c == '+' || c == '-' {
+            numbers.push(&text[start..index]);
+            start = index;
+        }
+    }
+    numbers.push(&text[start..]);
+
+    let mut numbers = numbers.into_iter().map(|number| number.parse::<f64>().ok());
+    Some(GpsPosition {
+        latitude: numbers.next()??,
+        longitude: numbers.next()??,
+        altitude: numbers.next().flatten(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    /// A box with a 64-bit "largesize"
+    fn large_box(kind: &[u8; 4], size: u64, payload: &[u8]) -> Vec<u8> {
+        let mut data = 1u32.to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(&size.to_be_bytes());
+        data.extend_from_slice(payload);
+        data
+    }
+
+    fn plain_box(kind: &[u8; 4], payload: &[u8]) -> Vec<u8> {
+        let mut data = ((payload.len() + 8) as u32).to_be_bytes().to_vec();
+        data.extend_from_slice(kind);
+        data.extend_from_slice(payload);
+        data
+    }
+
+    #[test]
+    fn child_boxes_reads_packed_boxes() {
+        let mut data = plain_box(b"ftyp", b"heic");
+        data.extend(large_box(b"meta", 20, b"abcd"));
+        let boxes = child_boxes(&data);
+        assert_eq!(boxes, vec![(*b"ftyp", &b"heic"[..]), (*b"meta", &b"abcd"[..])]);
+    }
+
+    #[test]
+    fn child_boxes_stops_at_sizes_past_the_end() {
+        for size in [u64::MAX, u64::MAX - 7, 1 << 40] {
+            let mut data = plain_box(b"ftyp", b"heic");
+            data.extend(large_box(b"meta", size, b"abcd"));
+            assert_eq!(child_boxes(&data), vec![(*b"ftyp", &b"heic"[..])]);
+        }
+    }
+
+    #[test]
+    fn top_level_box_with_overflowing_size_is_ignored() {
+        let mut file = tempfile::tempfile().unwrap();
+        file.write_all(&plain_box(b"ftyp", b"qt  ")).unwrap();
+        file.write_all(&large_box(b"mdat", u64::MAX - 4, b"data")).unwrap();
+        file.write_all(&plain_box(b"moov", b"")).unwrap();
+
+        assert_eq!(read_top_level_box(&mut file, b"moov").unwrap(), None);
+    }
+
+    #[test]
+    fn item_location_with_overflowing_offset_is_ignored() {
+        let mut iloc = vec![0, 0, 0, 0, 0x88, 0x80];
+        iloc.extend(1u16.to_be_bytes()); // item count
+        iloc.extend(1u16.to_be_bytes()); // item id
+        iloc.extend(0u16.to_be_bytes()); // data reference index
+        iloc.extend(u64::MAX.to_be_bytes()); // base offset
+        iloc.extend(1u16.to_be_bytes()); // extent count
+        iloc.extend(1u64.to_be_bytes()); // extent offset
+        iloc.extend(4u64.to_be_bytes()); // extent length
+
+        assert!(item_location(&iloc, 1).is_none());
+    }
+
+    #[test]
+    fn movie_creation_time_out_of_range_is_ignored() {
+        let mut mvhd = vec![1, 0, 0, 0];
+        mvhd.extend(u64::MAX.to_be_bytes());
+        assert!(movie_creation_time(&plain_box(b"mvhd", &mvhd)).is_none());
+    }
+
+    #[test]
+    fn parse_offset_rejects_huge_values() {
+        assert_eq!(parse_offset("+02:00"), FixedOffset::east_opt(7200));
+        assert_eq!(parse_offset("-05:30"), FixedOffset::east_opt(-19800));
+        assert_eq!(parse_offset("+99999999:00"), None);
+        assert_eq!(parse_offset("+00:99999999"), None);
+    }
+}
@@ -0,0 +1,448 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Side of the square grayscale thumbnail every hash is computed from
+const THUMBNAIL_SIZE: usize = 32;
+
+/// Side of the block of low DCT frequencies that make up the pHash
+const PHASH_SIZE: usize = 8;
+
+/// Lowest similarity, in percent, at which two photos are reported as the same
+pub const DEFAULT_MIN_SIMILARITY: &str = "85";
+
+/// Programs that can shrink a photo into a thumbnail, tried in order. sips
+/// ships with macOS and reads everything Photos exports, including HEIC and
+/// RAW; ImageMagick and ffmpeg cover other systems.
+const DECODERS: [Decoder; 4] = [
+    Decoder::Sips,
+    Decoder::Magick("magick"),
+    Decoder::Magick("convert"),
+    Decoder::Ffmpeg,
+];
+
+#[derive(Debug, Clone, Copy)]
+enum Decoder {
+    Sips,
+    Magick(&'static str),
+    Ffmpeg,
+}
+
+/// A difference hash and a DCT hash of a photo, which stay nearly the same
+/// when it is re-encoded, resized or lightly edited
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PerceptualHash {
+    pub dhash: u64,
+    pub phash: u64,
+}
+
+impl PerceptualHash {
+    /// Hash a thumbnail of any size
+    fn of(thumbnail: &Thumbnail) -> PerceptualHash {
+        PerceptualHash {
+            dhash: dhash(thumbnail),
+            phash: phash(thumbnail),
+        }
+    }
+
+    /// How alike two photos look, from 0.0 for opposites to 1.0 for identical
+    /// hashes. Unrelated photos land around 0.5.
+    pub fn similarity(&self, other: &PerceptualHash) -> f64 {
+        let distance =
+            (self.dhash ^ other.dhash).count_ones() + (self.phash ^ other.phash).count_ones();
+        1.0 - distance as f64 / 128.0
+    }
+}
+
+/// A backup photo that has no byte-identical copy in Immich but looks like
+/// one of its photos, such as a HEIC re-exported as JPEG or an edited copy
+#[derive(Debug, Clone)]
+pub struct NearMatch {
+    pub file: PathBuf,
+    pub immich_file: PathBuf,
+    /// Between 0.0 and 1.0
+    pub similarity: f64,
+}
+
+impl fmt::Display for NearMatch {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} looks like {} ({:.0}% similar)",
+            self.file.display(),
+            self.immich_file.display(),
+            self.similarity * 100.0
+        )
+    }
+}
+
+/// A grayscale image with luma values from 0 to 255, row by row
+#[derive(Debug, Clone)]
+struct Thumbnail {
+    width: usize,
+    height: usize,
+    pixels: Vec<f64>,
+}
+
+impl Thumbnail {
+    fn at(&self, x: usize, y: usize) -> f64 {
+        self.pixels[y * self.width + x]
+    }
+
+    /// Scale to exactly `width` x `height`, averaging the source pixels that
+    /// fall into each target pixel
+    fn resize(&self, width: usize, height: usize) -> Thumbnail {
+        let mut pixels = Vec::with_capacity(width * height);
+        for y in 0..height {
+            let (y0, y1) = span(y, height, self.height);
+            for x in 0..width {
+                let (x0, x1) = span(x, width, self.width);
+                let mut sum = 0.0;
+                for sy in y0..y1 {
+                    for sx in x0..x1 {
+                        sum += self.at(sx, sy);
+                    }
+                }
+                pixels.push(sum / ((y1 - y0) * (x1 - x0)) as f64);
+            }
+        }
+        Thumbnail {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    /// Turn a quarter turn clockwise
+    fn rotate(&self) -> Thumbnail {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for y in 0..self.width {
+            for x in 0..self.height {
+                pixels.push(self.at(y, self.height - 1 - x));
+            }
+        }
+        Thumbnail {
+            width: self.height,
+            height: self.width,
+            pixels,
+        }
+    }
+}
+
+/// The source pixels covered by target pixel `index`, never empty
+fn span(index: usize, target: usize, source: usize) -> (usize, usize) {
+    let start = index * source / target;
+    let end = ((index + 1) * source / target).max(start + 1).min(source);
+    (start.min(source - 1), end)
+}
+
+/// Each bit says whether a pixel of a 9x8 thumbnail is darker than its right neighbour
+fn dhash(thumbnail: &Thumbnail) -> u64 {
+    let small = thumbnail.resize(9, 8);
+    let mut hash = 0;
+    for y in 0..8 {
+        for x in 0..8 {
+            hash = hash << 1 | (small.at(x, y) < small.at(x + 1, y)) as u64;
+        }
+    }
+    hash
+}
+
+/// Each bit says whether one of the 8x8 lowest frequencies of the 32x32
+/// thumbnail's DCT is above their median, leaving the average out of it
+fn phash(thumbnail: &Thumbnail) -> u64 {
+    let image = thumbnail.resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE);
+    let n = THUMBNAIL_SIZE;
+    let cosines: Vec<Vec<f64>> = (0..PHASH_SIZE)
+        .map(|u| {
+            (0..n)
+                .map(|x| {
+                    ((2 * x + 1) as f64 * u as f64 * std::f64::consts::PI / (2 * n) as f64).cos()
+                })
+                .collect()
+        })
+        .collect();
+
+    // Rows first, then columns, only for the frequencies we keep
+    let mut rows = vec![[0.0; PHASH_SIZE]; n];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, value) in row.iter_mut().enumerate() {
+            *value = (0..n).map(|x| image.at(x, y) * cosines[u][x]).sum();
+        }
+    }
+    let mut coefficients = Vec::with_capacity(PHASH_SIZE * PHASH_SIZE);
+    for cosine in &cosines {
+        for u in 0..PHASH_SIZE {
+            coefficients.push(
+                rows.iter()
+                    .zip(cosine)
+                    .map(|(row, c)| row[u] * c)
+                    .sum::<f64>(),
+            );
+        }
+    }
+
+    let mut ac = coefficients[1..].to_vec();
+    ac.sort_by(f64::total_cmp);
+    let median = ac[ac.len() / 2];
+    coefficients
+        .iter()
+        .fold(0, |hash, &value| hash << 1 | (value > median) as u64)
+}
+
+/// Perceptual hash of a photo as it is stored
+pub fn hash_file(path: &Path) -> Result<PerceptualHash, String> {
+    Ok(PerceptualHash::of(&thumbnail(path)?))
+}
+
+/// Perceptual hashes of a photo in all four quarter turns, so a photo still
+/// matches a copy that was rotated or had its orientation tag applied
+pub fn hash_file_rotations(path: &Path) -> Result<[PerceptualHash; 4], String> {
+    let mut thumbnail = thumbnail(path)?;
+    let mut hashes = [PerceptualHash { dhash: 0, phash: 0 }; 4];
+    for hash in &mut hashes {
+        *hash = PerceptualHash::of(&thumbnail);
+        thumbnail = thumbnail.rotate();
+    }
+    Ok(hashes)
+}
+
+/// For every photo, the most similar candidate at or above `min_similarity`
+pub fn best_matches(
+    photos: &[(PathBuf, [PerceptualHash; 4])],
+    candidates: &[(PathBuf, PerceptualHash)],
+    min_similarity: f64,
+) -> Vec<NearMatch> {
+    photos
+        .par_iter()
+        .filter_map(|(file, rotations)| {
+            candidates
+                .iter()
+                .map(|(candidate, hash)| {
+                    let similarity = rotations
+                        .iter()
+                        .map(|rotation| rotation.similarity(hash))
+                        .fold(0.0, f64::max);
+                    (candidate, similarity)
+                })
+                .filter(|(_, similarity)| *similarity >= min_similarity)
+                .max_by(|a, b| a.1.total_cmp(&b.1))
+                .map(|(candidate, similarity)| NearMatch {
+                    file: file.clone(),
+                    immich_file: candidate.clone(),
+                    similarity,
+                })
+        })
+        .collect()
+}
+
+/// Decode a photo into a 32x32 grayscale thumbnail with the first decoder
+/// that is installed
+fn thumbnail(path: &Path) -> Result<Thumbnail, String> {
+    let mut installed = false;
+    let mut last_error = String::new();
+    for decoder in DECODERS {
+        match decode(decoder, path) {
+            Ok(image) => {
+                return parse_image(&image)
+                    .map(|thumbnail| thumbnail.resize(THUMBNAIL_SIZE, THUMBNAIL_SIZE))
+                    .ok_or_else(|| {
+                        format!("{:?} returned an image that couldn't be read", decoder)
+                    });
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => continue,
+            Err(e) => {
+                installed = true;
+                last_error = e.to_string();
+            }
+        }
+    }
+    if installed {
+        Err(last_error)
+    } else {
+        Err("no image decoder found, install ImageMagick or ffmpeg".to_string())
+    }
+}
+
+/// Run a decoder and return the small image it wrote, as PGM or BMP data
+fn decode(decoder: Decoder, path: &Path) -> io::Result<Vec<u8>> {
+    let size = THUMBNAIL_SIZE.to_string();
+    match decoder {
+        Decoder::Sips => {
+            // sips can only write to a file
+            static COUNTER: AtomicUsize = AtomicUsize::new(0);
+            let temp = std::env::temp_dir().join(format!(
+                "backup-photos-{}-{}.bmp",
+                std::process::id(),
+                COUNTER.fetch_add(1, Ordering::Relaxed)
+            ));
+            let result = run(Command::new("sips")
+                .args(["-s", "format", "bmp", "-z", &size, &size])
+                .arg(path)
+                .arg("--out")
+                .arg(&temp))
+            .and_then(|_| fs::read(&temp));
+            let _ = fs::remove_file(&temp);
+            result
+        }
+        Decoder::Magick(program) => {
+            let mut source = path.as_os_str().to_os_string();
+            source.push("[0]");
+            run(Command::new(program)
+                .arg(source)
+                .args(["-auto-orient", "-resize"])
+                .arg(format!("{}x{}!", size, size))
+                .args(["-colorspace", "Gray", "-depth", "8", "pgm:-"]))
+        }
+        Decoder::Ffmpeg => run(Command::new("ffmpeg")
+            .args(["-v", "error", "-i"])
+            .arg(path)
+            .args(["-frames:v", "1", "-vf"])
+            .arg(format!("scale={}:{},format=gray", size, size))
+            .args(["-f", "image2pipe", "-c:v", "pgm", "-"])),
+    }
+}
+
+/// Run a command and return its standard output, failing with its error
+/// output if it exits unsuccessfully
+fn run(command: &mut Command) -> io::Result<Vec<u8>> {
+    let output = command.stdin(Stdio::null()).output()?;
+    if !output.status.success() {
+        return Err(io::Error::other(format!(
+            "{} failed: {}",
+            command.get_program().to_string_lossy(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+    Ok(output.stdout)
+}
+
+fn parse_image(data: &[u8]) -> Option<Thumbnail> {
+    if data.starts_with(b"P5") {
+        parse_pgm(data)
+    } else if data.starts_with(b"BM") {
+        parse_bmp(data)
+    } else {
+        None
+    }
+}
+
+/// Binary PGM with 8 or 16 bits per pixel
+fn parse_pgm(data: &[u8]) -> Option<Thumbnail> {
+    let mut fields = Vec::new();
+    let mut pos = 2;
+    while fields.len() < 3 {
+        match data.get(pos)? {
+            b'#' => {
+                while *data.get(pos)? != b'\n' {
+                    pos += 1;
+                }
+            }
+            c if c.is_ascii_whitespace() => pos += 1,
+            _ => {
+                let start = pos;
+                while data.get(pos)?.is_ascii_digit() {
+                    pos += 1;
+                }
+                fields.push(
+                    std::str::from_utf8(&data[start..pos])
+                        .ok()?
+                        .parse::<usize>()
+                        .ok()?,
+                );
+            }
+        }
+    }
+    // A single whitespace byte separates the header from the pixels
+    pos += 1;
+
+    let (width, height, max) = (fields[0], fields[1], fields[2]);
+    if width == 0 || height == 0 || max == 0 {
+        return None;
+    }
+    let bytes_per_pixel = if max > 255 { 2 } else { 1 };
+    let raw = data.get(pos..pos + width * height * bytes_per_pixel)?;
+    let pixels = raw
+        .chunks(bytes_per_pixel)
+        .map(|value| {
+            let value = value.iter().fold(0usize, |acc, &b| acc << 8 | b as usize);
+            value as f64 * 255.0 / max as f64
+        })
+        .collect();
+    Some(Thumbnail {
+        width,
+        height,
+        pixels,
+    })
+}
+
+/// Uncompressed BMP with 8-bit palette, 24-bit or 32-bit pixels
+fn parse_bmp(data: &[u8]) -> Option<Thumbnail> {
+    let u16_at = |offset: usize| {
+        Some(u16::from_le_bytes(
+            data.get(offset..offset + 2)?.try_into().ok()?,
+        ))
+    };
+    let u32_at = |offset: usize| {
+        Some(u32::from_le_bytes(
+            data.get(offset..offset + 4)?.try_into().ok()?,
+        ))
+    };
+
+    let pixel_offset = u32_at(10)? as usize;
+    let header_size = u32_at(14)? as usize;
+    let width = u32_at(18)? as i32;
+    let height = u32_at(22)? as i32;
+    let bits = u16_at(28)? as usize;
+    let compression = u32_at(30)?;
+    // BI_BITFIELDS is only accepted for 32-bit pixels, which are always BGRA in practice
+    if width <= 0 || height == 0 || !(compression == 0 || (compression == 3 && bits == 32)) {
+        return None;
+    }
+    let (width, rows) = (width as usize, height.unsigned_abs() as usize);
+    let stride = (width * bits).div_ceil(32) * 4;
+
+    let luma = |b: u8, g: u8, r: u8| 0.299 * r as f64 + 0.587 * g as f64 + 0.114 * b as f64;
+    let palette: Vec<f64> = if bits == 8 {
+        let start = 14 + header_size;
+        let colors = match u32_at(46)? {
+            0 => 256,
+            n => n as usize,
+        };
+        data.get(start..start + colors * 4)?
+            .chunks(4)
+            .map(|c| luma(c[0], c[1], c[2]))
+            .collect()
+    } else {
+        Vec::new()
+    };
+
+    let mut pixels = Vec::with_capacity(width * rows);
+    for y in 0..rows {
+        // Rows are stored bottom-up unless the height is negative
+        let row = if height > 0 { rows - 1 - y } else { y };
+        let start = pixel_offset + row * stride;
+        let line = data.get(start..start + stride)?;
+        for x in 0..width {
+            let value = match bits {
+                8 => *palette.get(line[x] as usize)?,
+                24 | 32 => {
+                    let p = &line[x * bits / 8..];
+                    luma(p[0], p[1], p[2])
+                }
+                _ => return None,
+            };
+            pixels.push(value);
+        }
+    }
+    Some(Thumbnail {
+        width,
+        height: rows,
+        pixels,
+    })
+}
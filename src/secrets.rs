@@ -0,0 +1,184 @@
+use crate::BackupError;
+use log::debug;
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+/// An Immich API key. Debug output is redacted so the key never ends up in logs.
+#[derive(Clone)]
+pub struct ApiKey(String);
+
+impl ApiKey {
+    /// The key itself, for handing to the server or a child process
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Debug for ApiKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "ApiKey(<redacted>)")
+    }
+}
+
+/// Where to read the Immich API key from
+#[derive(Clone)]
+pub enum ApiKeySource {
+    /// The key itself, from IMMICH_API_KEY or from the config file at the
+    /// path, which must then not be readable by group or others either
+    Value(String, Option<PathBuf>),
+    /// A file containing only the key, which must not be readable by group or others
+    File(PathBuf),
+    /// A shell command that prints the key, e.g. `pass show immich`
+    Command(String),
+}
+
+impl fmt::Debug for ApiKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeySource::Value(_, file) => write!(f, "Value(<redacted>, {:?})", file),
+            ApiKeySource::File(path) => f.debug_tuple("File").field(path).finish(),
+            ApiKeySource::Command(command) => f.debug_tuple("Command").field(command).finish(),
+        }
+    }
+}
+
+impl fmt::Display for ApiKeySource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ApiKeySource::Value(..) => write!(f, "configured value"),
+            ApiKeySource::File(path) => write!(f, "file {}", path.display()),
+            ApiKeySource::Command(command) => write!(f, "command `{}`", command),
+        }
+    }
+}
+
+impl ApiKeySource {
+    /// Read the key, checking file permissions or running the command as needed
+    pub fn load(&self) -> Result<ApiKey, BackupError> {
+        let key = match self {
+            ApiKeySource::Value(value, file) => {
+                if let Some(path) = file {
+                    check_private_permissions(path, "Config file with immich_api_key")?;
+                }
+                value.clone()
+            }
+            ApiKeySource::File(path) => {
+                check_private_permissions(path, "API key file")?;
+                fs::read_to_string(path).map_err(|e| {
+                    BackupError::ApiKeyError(format!(
+                        "Failed to read API key file {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?
+            }
+            ApiKeySource::Command(command) => run_key_command(command)?,
+        };
+
+        let key = key.trim();
+        if key.is_empty() {
+            return Err(BackupError::ApiKeyError(format!("API key from {} is empty", self)));
+        }
+
+        debug!("Loaded Immich API key from {}", self);
+        Ok(ApiKey(key.to_string()))
+    }
+}
+
+/// Refuse files holding a key that other users could read, like ssh does for
+/// private keys. `what` names the file in the error.
+#[cfg(unix)]
+fn check_private_permissions(
+    path: &std::path::Path,
+    what: &str,
+) -> Result<(), BackupError> {
+    use std::os::unix::fs::PermissionsExt;
+
+    let mode = fs::metadata(path)
+        .map_err(|e| {
+            BackupError::ApiKeyError(format!("Failed to read {} {}: {}", what, path.display(), e))
+        })?
+        .permissions()
+        .mode();
+
+    if mode & 0o077 != 0 {
+        return Err(BackupError::ApiKeyError(format!(
+            "{} {} has permissions {:o}, which lets other users read it. Run: chmod 600 {}",
+            what,
+            path.display(),
+            mode & 0o777,
+            path.display()
+        )));
+    }
+
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn check_private_permissions(
+    _path: &std::path::Path,
+    _what: &str,
+) -> Result<(), BackupError> {
+    Ok(())
+}
+
+/// Run the command through the shell and take the first line of its stdout as
+/// the key, so tools like `pass` that print more after it still work.
+/// stderr and stdin stay attached so password managers can prompt.
+fn run_key_command(command: &str) -> Result<String, BackupError> {
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .stdin(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .output()
+        .map_err(|e| {
+            BackupError::ApiKeyError(format!("Failed to run `{}`: {}", command, e))
+        })?;
+
+    if !output.status.success() {
+        return Err(BackupError::ApiKeyError(format!(
+            "`{}` exited with status: {}",
+            command, output.status
+        )));
+    }
+
+    let stdout = String::from_utf8(output.stdout).map_err(|_| {
+        BackupError::ApiKeyError(format!("`{}` printed a key that is not valid UTF-8", command))
+    })?;
+    Ok(stdout.lines().next().unwrap_or_default().to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_key_is_the_first_line() {
+        let source = ApiKeySource::Command("printf ' abc123 \\nlogin: me\\n'".to_string());
+        assert_eq!(source.load().unwrap().expose(), "abc123");
+
+        let source = ApiKeySource::Command("printf '\\nabc123\\n'".to_string());
+        assert!(source.load().is_err());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn key_file_must_be_private() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("key");
+        fs::write(&path, "abc123\n").unwrap();
+        let source = ApiKeySource::File(path.clone());
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+        let error = source.load().unwrap_err().to_string();
+        assert!(error.contains("API key file"), "{}", error);
+
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o600)).unwrap();
+        assert_eq!(source.load().unwrap().expose(), "abc123");
+    }
+}
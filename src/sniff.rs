@@ -0,0 +1,499 @@
+use crate::media::MediaKind;
+use indicatif::ProgressBar;
+use rayon::prelude::*;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+
+/// Bytes read from the start of a file to recognise its format
+const HEADER_SIZE: usize = 256;
+
+/// ISO base media brands of HEIF stills
+const HEIF_BRANDS: [&[u8; 4]; 8] = [
+    b"heic", b"heix", b"heim", b"heis", b"hevc", b"hevx", b"mif1", b"msf1",
+];
+const AVIF_BRANDS: [&[u8; 4]; 2] = [b"avif", b"avis"];
+
+/// A file format recognised from the first bytes of a file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Jpeg,
+    Heif,
+    Avif,
+    Png,
+    Gif,
+    Webp,
+    /// TIFF and the RAW formats built on it (DNG, NEF, CR2, ARW, ORF, RW2)
+    Tiff,
+    Raf,
+    Cr3,
+    Mp4,
+    QuickTime,
+    Matroska,
+    Avi,
+    MpegProgram,
+    MpegTransport,
+    Flv,
+    Asf,
+    Xmp,
+}
+
+const FORMATS: [Format; 18] = [
+    Format::Jpeg,
+    Format::Heif,
+    Format::Avif,
+    Format::Png,
+    Format::Gif,
+    Format::Webp,
+    Format::Tiff,
+    Format::Raf,
+    Format::Cr3,
+    Format::Mp4,
+    Format::QuickTime,
+    Format::Matroska,
+    Format::Avi,
+    Format::MpegProgram,
+    Format::MpegTransport,
+    Format::Flv,
+    Format::Asf,
+    Format::Xmp,
+];
+
+impl Format {
+    pub fn kind(self) -> MediaKind {
+        match self {
+            Format::Jpeg
+            | Format::Heif
+            | Format::Avif
+            | Format::Png
+            | Format::Gif
+            | Format::Webp
+            | Format::Tiff
+            | Format::Raf
+            | Format::Cr3 => MediaKind::Photo,
+            Format::Xmp => MediaKind::Sidecar,
+            _ => MediaKind::Video,
+        }
+    }
+
+    /// Extensions a file in this format may have. MP4 and QuickTime share
+    /// theirs because cameras and phones write both under either name.
+    fn extensions(self) -> &'static [&'static str] {
+        match self {
+            Format::Jpeg => &["jpg", "jpeg"],
+            Format::Heif => &["heic", "heif"],
+            Format::Avif => &["avif"],
+            Format::Png => &["png"],
+            Format::Gif => &["gif"],
+            Format::Webp => &["webp"],
+            Format::Tiff => &["tif", "tiff", "dng", "nef", "cr2", "arw", "orf", "rw2", "raw"],
+            Format::Raf => &["raf", "raw"],
+            Format::Cr3 => &["cr3"],
+            Format::Mp4 | Format::QuickTime => &["mp4", "m4v", "mov", "3gp"],
+            Format::Matroska => &["mkv", "webm"],
+            Format::Avi => &["avi"],
+            Format::MpegProgram => &["mpg", "mpeg"],
+            Format::MpegTransport => &["mts", "m2ts"],
+            Format::Flv => &["flv"],
+            Format::Asf => &["wmv"],
+            Format::Xmp => &["xmp"],
+        }
+    }
+
+    /// Whether a file with this extension may hold data in this format
+    pub fn matches_extension(self, extension: &str) -> bool {
+        self.extensions()
+            .contains(&extension.to_lowercase().as_str())
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Format::Jpeg => "JPEG",
+            Format::Heif => "HEIF",
+            Format::Avif => "AVIF",
+            Format::Png => "PNG",
+            Format::Gif => "GIF",
+            Format::Webp => "WebP",
+            Format::Tiff => "TIFF",
+            Format::Raf => "Fujifilm RAF",
+            Format::Cr3 => "Canon CR3",
+            Format::Mp4 => "MP4",
+            Format::QuickTime => "QuickTime",
+            Format::Matroska => "Matroska",
+            Format::Avi => "AVI",
+            Format::MpegProgram => "MPEG",
+            Format::MpegTransport => "MPEG transport stream",
+            Format::Flv => "Flash video",
+            Format::Asf => "Windows Media",
+            Format::Xmp => "XMP",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Read the start of a file and recognise its format
+pub fn detect(path: &Path) -> io::Result<Option<Format>> {
+    let mut header = Vec::with_capacity(HEADER_SIZE);
+    File::open(path)?
+        .take(HEADER_SIZE as u64)
+        .read_to_end(&mut header)?;
+    Ok(format_from_header(&header))
+}
+
+/// Recognise a format from the first bytes of a file
+pub fn format_from_header(header: &[u8]) -> Option<Format> {
+    let starts = |magic: &[u8]| header.starts_with(magic);
+    let at = |offset: usize, magic: &[u8]| header.get(offset..offset + magic.len()) == Some(magic);
+
+    if starts(&[0xff, 0xd8, 0xff]) {
+        return Some(Format::Jpeg);
+    }
+    if starts(b"\x89PNG\r\n\x1a\n") {
+        return Some(Format::Png);
+    }
+    if starts(b"GIF87a") || starts(b"GIF89a") {
+        return Some(Format::Gif);
+    }
+    if starts(b"RIFF") && at(8, b"WEBP") {
+        return Some(Format::Webp);
+    }
+    if starts(b"RIFF") && at(8, b"AVI ") {
+        return Some(Format::Avi);
+    }
+    // ORF ("IIRO", "IIRS", "MMOR") and RW2 ("IIU\0") are TIFF with a different magic number
+    if starts(b"II*\0")
+        || starts(b"MM\0*")
+        || starts(b"IIRO")
+        || starts(b"IIRS")
+        || starts(b"MMOR")
+        || starts(b"IIU\0")
+    {
+        return Some(Format::Tiff);
+    }
+    if starts(b"FUJIFILMCCD-RAW") {
+        return Some(Format::Raf);
+    }
+
+    if at(4, b"ftyp") {
+        let brand = header.get(8..12)?;
+        let is = |brands: &[&[u8; 4]]| brands.iter().any(|b| b.as_slice() == brand);
+        return Some(if is(&HEIF_BRANDS) {
+            Format::Heif
+        } else if is(&AVIF_BRANDS) {
+            Format::Avif
+        } else if brand == b"crx " {
+            Format::Cr3
+        } else if brand == b"qt  " {
+            Format::QuickTime
+        } else {
+            Format::Mp4
+        });
+    }
+    // Older QuickTime files start straight with a movie, data or padding atom
+    if at(4, b"moov") || at(4, b"mdat") || at(4, b"wide") {
+        return Some(Format::QuickTime);
+    }
+
+    if starts(&[0x1a, 0x45, 0xdf, 0xa3]) {
+        return Some(Format::Matroska);
+    }
+    if starts(&[0x00, 0x00, 0x01, 0xba]) || starts(&[0x00, 0x00, 0x01, 0xb3]) {
+        return Some(Format::MpegProgram);
+    }
+    // Transport stream packets are 188 bytes, or 192 with the timecode prefix of M2TS
+    if (at(0, &[0x47]) && at(188, &[0x47])) || (at(4, &[0x47]) && at(196, &[0x47])) {
+        return Some(Format::MpegTransport);
+    }
+    if starts(b"FLV\x01") {
+        return Some(Format::Flv);
+    }
+    if starts(&[0x30, 0x26, 0xb2, 0x75, 0x8e, 0x66, 0xcf, 0x11]) {
+        return Some(Format::Asf);
+    }
+
+    let text = String::from_utf8_lossy(header);
+    let text = text.trim_start_matches('\u{feff}').trim_start();
+    if text.starts_with("<?xpacket")
+        || text.starts_with("<x:xmpmeta")
+        || (text.starts_with("<?xml") && text.contains("<x:xmpmeta"))
+    {
+        return Some(Format::Xmp);
+    }
+
+    None
+}
+
+/// Why a media file looks wrong
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Problem {
+    /// The file is zero bytes long
+    Empty,
+    /// The file couldn't be opened or read
+    Unreadable(String),
+    /// The contents are in a format that doesn't match the extension
+    Mismatch { extension: String, actual: Format },
+    /// The contents aren't in any format the extension allows, which usually
+    /// means the file is truncated or corrupted
+    Unrecognized { extension: String },
+}
+
+impl Problem {
+    /// Headings for reports, in the order problems should be listed
+    pub const CATEGORIES: [&'static str; 4] = [
+        "Empty files",
+        "Unreadable files",
+        "Contents don't match the extension",
+        "Truncated or corrupted files",
+    ];
+
+    pub fn category(&self) -> &'static str {
+        let index = match self {
+            Problem::Empty => 0,
+            Problem::Unreadable(_) => 1,
+            Problem::Mismatch { .. } => 2,
+            Problem::Unrecognized { .. } => 3,
+        };
+        Problem::CATEGORIES[index]
+    }
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Problem::Empty => write!(f, "file is empty"),
+            Problem::Unreadable(e) => write!(f, "could not be read: {}", e),
+            Problem::Mismatch { extension, actual } => {
+                write!(f, "contains {} data but is named .{}", actual, extension)
+            }
+            Problem::Unrecognized { extension } => {
+                write!(f, "contents are not a valid .{} file", extension)
+            }
+        }
+    }
+}
+
+/// A file whose contents don't look like what its name says
+#[derive(Debug, Clone)]
+pub struct Suspect {
+    pub path: PathBuf,
+    pub problem: Problem,
+}
+
+/// Check that a file is readable, not empty, and that its contents match its
+/// extension. Extensions no known format uses, such as ones added in the
+/// config, are only checked for being readable and non-empty.
+pub fn check(path: &Path) -> Option<Problem> {
+    let size = match path.metadata() {
+        Ok(metadata) => metadata.len(),
+        Err(e) => return Some(Problem::Unreadable(e.to_string())),
+    };
+    if size == 0 {
+        return Some(Problem::Empty);
+    }
+
+    let format = match detect(path) {
+        Ok(format) => format,
+        Err(e) => return Some(Problem::Unreadable(e.to_string())),
+    };
+
+    let extension = path.extension()?.to_string_lossy().to_lowercase();
+    let checkable = FORMATS.iter().any(|f| f.matches_extension(&extension));
+    match format {
+        Some(format) if !checkable || format.matches_extension(&extension) => None,
+        Some(actual) => Some(Problem::Mismatch { extension, actual }),
+        None if checkable => Some(Problem::Unrecognized { extension }),
+        None => None,
+    }
+}
+
+/// Check many files in parallel, returning the suspicious ones in input order
+pub fn check_all(files: &[PathBuf], progress: &ProgressBar) -> Vec<Suspect> {
+    let problems: Vec<Option<Problem>> = files
+        .par_iter()
+        .map(|file| {
+            let problem = check(file);
+            progress.inc(1);
+            problem
+        })
+        .collect();
+
+    files
+        .iter()
+        .zip(problems)
+        .filter_map(|(path, problem)| {
+            problem.map(|problem| Suspect {
+                path: path.clone(),
+                problem,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const JPEG: &[u8] = &[
+        0xff, 0xd8, 0xff, 0xe1, 0x00, 0x18, b'E', b'x', b'i', b'f', 0, 0,
+    ];
+    const PNG: &[u8] = b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR";
+    const TIFF_LE: &[u8] = b"II*\0\x08\0\0\0";
+    const TIFF_BE: &[u8] = b"MM\0*\0\0\0\x08";
+
+    /// An ISO base media file starting with an ftyp box of `brand`
+    fn ftyp(brand: &[u8; 4]) -> Vec<u8> {
+        let mut header = vec![0, 0, 0, 0x18];
+        header.extend_from_slice(b"ftyp");
+        header.extend_from_slice(brand);
+        header.extend_from_slice(&[0, 0, 0, 0]);
+        header.extend_from_slice(b"mif1heic");
+        header
+    }
+
+    fn write(dir: &Path, name: &str, contents: &[u8]) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn photo_formats_are_recognised() {
+        assert_eq!(format_from_header(JPEG), Some(Format::Jpeg));
+        assert_eq!(format_from_header(PNG), Some(Format::Png));
+        assert_eq!(format_from_header(TIFF_LE), Some(Format::Tiff));
+        assert_eq!(format_from_header(TIFF_BE), Some(Format::Tiff));
+        // Olympus ORF and Panasonic RW2 use their own TIFF magic numbers
+        assert_eq!(format_from_header(b"IIRO\x08\0\0\0"), Some(Format::Tiff));
+        assert_eq!(format_from_header(b"IIU\0\x08\0\0\0"), Some(Format::Tiff));
+        assert_eq!(
+            format_from_header(b"FUJIFILMCCD-RAW 0201"),
+            Some(Format::Raf)
+        );
+    }
+
+    #[test]
+    fn ftyp_brands_are_told_apart() {
+        for brand in [b"heic", b"heix", b"mif1", b"msf1"] {
+            assert_eq!(
+                format_from_header(&ftyp(brand)),
+                Some(Format::Heif),
+                "{:?}",
+                brand
+            );
+        }
+        assert_eq!(format_from_header(&ftyp(b"avif")), Some(Format::Avif));
+        assert_eq!(format_from_header(&ftyp(b"crx ")), Some(Format::Cr3));
+        assert_eq!(format_from_header(&ftyp(b"qt  ")), Some(Format::QuickTime));
+        assert_eq!(format_from_header(&ftyp(b"isom")), Some(Format::Mp4));
+        assert_eq!(format_from_header(&ftyp(b"mp42")), Some(Format::Mp4));
+        // A header cut off before the brand isn't anything
+        assert_eq!(format_from_header(b"\0\0\0\x18ftyp"), None);
+    }
+
+    #[test]
+    fn quicktime_without_ftyp_is_recognised() {
+        assert_eq!(
+            format_from_header(b"\0\0\0\x08wide\0\0\0\0mdat"),
+            Some(Format::QuickTime)
+        );
+        assert_eq!(
+            format_from_header(b"\0\0\x01\0moov"),
+            Some(Format::QuickTime)
+        );
+    }
+
+    #[test]
+    fn unknown_or_short_headers_are_not_recognised() {
+        assert_eq!(format_from_header(b""), None);
+        assert_eq!(format_from_header(&[0xff, 0xd8]), None);
+        assert_eq!(format_from_header(b"hello, world"), None);
+        assert_eq!(format_from_header(b"<?xml version=\"1.0\"?><html/>"), None);
+        assert_eq!(
+            format_from_header(b"\xef\xbb\xbf<x:xmpmeta xmlns:x=\"adobe:ns:meta/\">"),
+            Some(Format::Xmp)
+        );
+    }
+
+    #[test]
+    fn check_accepts_matching_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        assert_eq!(check(&write(dir, "a.jpg", JPEG)), None);
+        assert_eq!(check(&write(dir, "b.JPEG", JPEG)), None);
+        assert_eq!(check(&write(dir, "c.png", PNG)), None);
+        assert_eq!(check(&write(dir, "d.HEIC", &ftyp(b"heic"))), None);
+        assert_eq!(check(&write(dir, "e.dng", TIFF_LE)), None);
+        assert_eq!(check(&write(dir, "f.nef", TIFF_BE)), None);
+        // Phones write QuickTime data under .mp4 and MP4 data under .mov
+        assert_eq!(check(&write(dir, "g.mp4", &ftyp(b"qt  "))), None);
+        assert_eq!(check(&write(dir, "h.mov", &ftyp(b"isom"))), None);
+        // An extension no known format uses is only checked for being non-empty
+        assert_eq!(check(&write(dir, "i.psd", b"8BPS")), None);
+    }
+
+    #[test]
+    fn check_reports_what_doctor_looks_for() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+
+        assert_eq!(check(&write(dir, "empty.jpg", b"")), Some(Problem::Empty));
+        assert_eq!(check(&write(dir, "empty.psd", b"")), Some(Problem::Empty));
+        assert_eq!(
+            check(&write(dir, "truncated.jpg", &JPEG[..2])),
+            Some(Problem::Unrecognized {
+                extension: "jpg".to_string()
+            })
+        );
+        assert_eq!(
+            check(&write(dir, "misnamed.JPG", &ftyp(b"heic"))),
+            Some(Problem::Mismatch {
+                extension: "jpg".to_string(),
+                actual: Format::Heif
+            })
+        );
+        assert_eq!(
+            check(&write(dir, "misnamed.heic", JPEG)),
+            Some(Problem::Mismatch {
+                extension: "heic".to_string(),
+                actual: Format::Jpeg
+            })
+        );
+        assert!(matches!(
+            check(&dir.join("missing.jpg")),
+            Some(Problem::Unreadable(_))
+        ));
+        assert!(matches!(check(dir), Some(Problem::Unreadable(_))));
+    }
+
+    #[test]
+    fn check_all_keeps_input_order() {
+        let dir = tempfile::tempdir().unwrap();
+        let dir = dir.path();
+        let files = vec![
+            write(dir, "1.jpg", b""),
+            write(dir, "2.jpg", JPEG),
+            write(dir, "3.png", JPEG),
+            write(dir, "4.mov", &ftyp(b"qt  ")),
+            write(dir, "5.cr2", b"garbage"),
+        ];
+
+        let suspects = check_all(&files, &ProgressBar::hidden());
+        let found: Vec<(&Path, &str)> = suspects
+            .iter()
+            .map(|s| (s.path.as_path(), s.problem.category()))
+            .collect();
+        assert_eq!(
+            found,
+            vec![
+                (files[0].as_path(), "Empty files"),
+                (files[2].as_path(), "Contents don't match the extension"),
+                (files[4].as_path(), "Truncated or corrupted files"),
+            ]
+        );
+        assert!(check_all(&[], &ProgressBar::hidden()).is_empty());
+    }
+}
@@ -0,0 +1,469 @@
+use crate::asset::Asset;
+use crate::hash_cache::HashCache;
+use crate::metadata;
+use crate::BackupError;
+use crate::MediaKind;
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Timelike, Utc};
+use indicatif::ProgressBar;
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+/// Version of the plan file format
+pub const PLAN_VERSION: u32 = 1;
+
+/// What a plan proposes to do with an asset
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PlanAction {
+    Trash,
+    Keep,
+}
+
+/// Rules choosing which assets a plan proposes to trash. An asset is trashed
+/// when it matches every rule given; everything else is kept, and with no
+/// rules at all every asset is kept.
+#[derive(Debug, Clone, Default, clap::Args, Serialize, Deserialize)]
+pub struct PlanRules {
+    /// Only trash assets of this type
+    #[arg(long = "type", value_enum, value_name = "TYPE")]
+    #[serde(rename = "type", default, skip_serializing_if = "Option::is_none")]
+    pub kind: Option<MediaKind>,
+
+    /// Only trash assets whose path in the backup matches one of these glob
+    /// patterns, e.g. "*.png" or "Screenshots/**" (case-insensitive; a pattern
+    /// without a / is matched against the file name)
+    #[arg(long = "glob", value_name = "PATTERN")]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub globs: Vec<String>,
+
+    /// Only trash assets taken on or after this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_after: Option<NaiveDate>,
+
+    /// Only trash assets taken before this date (YYYY-MM-DD)
+    #[arg(long, value_name = "DATE")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub taken_before: Option<NaiveDate>,
+
+    /// Only trash assets at least this big, e.g. 500K, 20M or 2G
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_size: Option<u64>,
+
+    /// Only trash assets smaller than this
+    #[arg(long, value_name = "SIZE", value_parser = parse_size)]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size: Option<u64>,
+}
+
+impl PlanRules {
+    pub fn is_empty(&self) -> bool {
+        self.kind.is_none()
+            && self.globs.is_empty()
+            && self.taken_after.is_none()
+            && self.taken_before.is_none()
+            && self.min_size.is_none()
+            && self.max_size.is_none()
+    }
+
+    /// Why the asset should be kept, or None if it matches every rule
+    fn keep_reason(&self, asset: &PlannedAsset) -> Option<String> {
+        if self.is_empty() {
+            return Some("no rules given".to_string());
+        }
+        if let Some(kind) = self.kind {
+            if asset.kind != Some(kind) {
+                return Some(format!("not a {}", kind));
+            }
+        }
+        if !self.globs.is_empty()
+            && !self
+                .globs
+                .iter()
+                .any(|glob| glob_matches(glob, &asset.path))
+        {
+            return Some("matches none of the glob patterns".to_string());
+        }
+        if self.taken_after.is_some() || self.taken_before.is_some() {
+            let Some(taken) = asset.taken else {
+                return Some("capture date unknown".to_string());
+            };
+            if let Some(after) = self.taken_after {
+                if taken.date() < after {
+                    return Some(format!("taken before {}", after));
+                }
+            }
+            if let Some(before) = self.taken_before {
+                if taken.date() >= before {
+                    return Some(format!("taken on or after {}", before));
+                }
+            }
+        }
+        if let Some(min) = self.min_size {
+            if asset.size < min {
+                return Some(format!("smaller than {} bytes", min));
+            }
+        }
+        if let Some(max) = self.max_size {
+            if asset.size >= max {
+                return Some(format!("at least {} bytes", max));
+            }
+        }
+        None
+    }
+}
+
+/// The proposed actions for every asset that is in the backup but not in
+/// Immich, written by `sync plan` for review and carried out by `sync apply`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SyncPlan {
+    pub version: u32,
+    pub created: DateTime<Utc>,
+    pub backup_dir: PathBuf,
+    pub rules: PlanRules,
+    pub assets: Vec<PlannedAsset>,
+}
+
+/// One asset of a plan. Paths are relative to the backup root.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedAsset {
+    pub action: PlanAction,
+    /// Why the rules chose the action
+    pub reason: String,
+    pub path: PathBuf,
+    pub kind: Option<MediaKind>,
+    /// Capture date from the file's metadata, or its modification time
+    pub taken: Option<NaiveDateTime>,
+    /// Size of all the asset's files together
+    pub size: u64,
+    /// Every file of the asset, primary first, with the hash it had when
+    /// the plan was made
+    pub files: Vec<PlannedFile>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct PlannedFile {
+    pub path: PathBuf,
+    pub sha256: String,
+    pub size: u64,
+}
+
+/// What applying a plan did
+#[derive(Debug, Default)]
+pub struct ApplySummary {
+    pub trashed: Vec<PathBuf>,
+    pub kept: usize,
+    /// Assets that were not trashed, with the reason
+    pub failed: Vec<(PathBuf, String)>,
+    /// Session ID of the journal recording the trash moves
+    pub session: Option<String>,
+}
+
+/// Work out the action for each asset, hashing all of their files. An asset
+/// with a file that can't be read is kept, with the error as the reason.
+pub fn plan(
+    backup_dir: &Path,
+    assets: &[Asset],
+    rules: &PlanRules,
+    cache: &mut HashCache,
+    progress: &ProgressBar,
+) -> Result<SyncPlan, BackupError> {
+    let files: Vec<PathBuf> = assets
+        .iter()
+        .flat_map(|asset| asset.files())
+        .map(Path::to_path_buf)
+        .collect();
+    progress.set_length(files.len() as u64);
+    let mut hashes = cache.hash_all(&files, progress).into_iter();
+
+    let relative = |path: &Path| path.strip_prefix(backup_dir).unwrap_or(path).to_path_buf();
+    let mut planned = Vec::new();
+    for asset in assets {
+        let mut planned_files = Vec::new();
+        let mut unreadable = None;
+        for file in asset.files() {
+            let read = hashes
+                .next()
+                .unwrap_or_else(|| Err(BackupError::IoError(std::io::Error::other("missing hash"))))
+                .and_then(|sha256| Ok((sha256, fs::metadata(file)?.len())));
+            // An unreadable file gets no hash, so applying the plan refuses to
+            // trash its asset even if the action is edited to trash
+            let (sha256, size) = read.unwrap_or_else(|e| {
+                unreadable.get_or_insert_with(|| {
+                    format!("{} could not be read: {}", relative(file).display(), e)
+                });
+                (String::new(), 0)
+            });
+            planned_files.push(PlannedFile {
+                path: relative(file),
+                sha256,
+                size,
+            });
+        }
+
+        let mut entry = PlannedAsset {
+            action: PlanAction::Keep,
+            reason: String::new(),
+            path: relative(&asset.primary),
+            kind: MediaKind::of(&asset.primary),
+            taken: taken(asset),
+            size: planned_files.iter().map(|file| file.size).sum(),
+            files: planned_files,
+        };
+        match unreadable.or_else(|| rules.keep_reason(&entry)) {
+            Some(reason) => entry.reason = reason,
+            None => {
+                entry.action = PlanAction::Trash;
+                entry.reason = "matches every rule".to_string();
+            }
+        }
+        planned.push(entry);
+    }
+
+    Ok(SyncPlan {
+        version: PLAN_VERSION,
+        created: Utc::now(),
+        backup_dir: backup_dir.to_path_buf(),
+        rules: rules.clone(),
+        assets: planned,
+    })
+}
+
+/// Write a plan as pretty-printed JSON, so it reads well in review
+pub fn save(plan: &SyncPlan, path: &Path) -> Result<(), BackupError> {
+    let json = serde_json::to_string_pretty(plan)
+        .map_err(|e| BackupError::IoError(std::io::Error::other(e)))?;
+    fs::write(path, json + "\n")?;
+    Ok(())
+}
+
+/// Read a plan and check it was made for `backup_dir` and only names files
+/// inside it
+pub fn load(path: &Path, backup_dir: &Path) -> Result<SyncPlan, BackupError> {
+    let text = fs::read_to_string(path)?;
+    let plan: SyncPlan = serde_json::from_str(&text)
+        .map_err(|e| BackupError::InvalidPlan(format!("{}: {}", path.display(), e)))?;
+
+    if plan.version != PLAN_VERSION {
+        return Err(BackupError::InvalidPlan(format!(
+            "{} has version {}, expected {}",
+            path.display(),
+            plan.version,
+            PLAN_VERSION
+        )));
+    }
+    let same_dir = match (
+        fs::canonicalize(&plan.backup_dir),
+        fs::canonicalize(backup_dir),
+    ) {
+        (Ok(planned), Ok(current)) => planned == current,
+        _ => plan.backup_dir == backup_dir,
+    };
+    if !same_dir {
+        return Err(BackupError::InvalidPlan(format!(
+            "{} was made for {}, not {}",
+            path.display(),
+            plan.backup_dir.display(),
+            backup_dir.display()
+        )));
+    }
+    let escapes = |path: &Path| {
+        path.components()
+            .any(|component| !matches!(component, Component::Normal(_) | Component::CurDir))
+    };
+    for asset in &plan.assets {
+        if let Some(file) = asset.files.iter().find(|file| escapes(&file.path)) {
+            return Err(BackupError::InvalidPlan(format!(
+                "{} is not a path inside the backup directory",
+                file.path.display()
+            )));
+        }
+    }
+    Ok(plan)
+}
+
+/// Make sure every file of a planned asset still has the hash it was
+/// planned with
+pub fn check_unchanged(backup_dir: &Path, asset: &PlannedAsset) -> Result<(), String> {
+    for file in &asset.files {
+        let path = backup_dir.join(&file.path);
+        let hash = crate::calculate_file_hash(&path)
+            .map_err(|e| format!("{} can't be read: {}", path.display(), e))?;
+        if hash != file.sha256 {
+            return Err(format!(
+                "{} changed since the plan was made",
+                path.display()
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// When the asset was taken: the first capture time in its photos and videos,
+/// falling back to the primary file's modification time
+fn taken(asset: &Asset) -> Option<NaiveDateTime> {
+    asset
+        .media_files()
+        .into_iter()
+        .filter_map(metadata::capture_time)
+        .map(|time| time.local)
+        .next()
+        .or_else(|| {
+            let modified = fs::metadata(&asset.primary).ok()?.modified().ok()?;
+            let modified = DateTime::<Local>::from(modified).naive_local();
+            modified.with_nanosecond(0)
+        })
+}
+
+/// Parse a size such as `2048`, `500K`, `20MB` or `1.5G`, where K, M, G and T
+/// are powers of 1024
+pub fn parse_size(text: &str) -> Result<u64, String> {
+    let text = text.trim();
+    let split = text
+        .find(|c: char| !(c.is_ascii_digit() || c == '.'))
+        .unwrap_or(text.len());
+    let (number, unit) = text.split_at(split);
+    let invalid = || format!("'{}' is not a size, e.g. 500K, 20M or 2G", text);
+
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => return Err(invalid()),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Whether a relative path matches a glob pattern. `*` and `?` stay within a
+/// path component, `**` spans any number of them, and `[...]` matches a set
+/// or range of characters, negated with `!` or `^`. Patterns without a `/`
+/// are matched against the file name alone. Case is ignored.
+pub fn glob_matches(pattern: &str, path: &Path) -> bool {
+    let path = path.to_string_lossy().replace('\\', "/");
+    let text = if pattern.contains('/') {
+        path.as_str()
+    } else {
+        path.rsplit('/').next().unwrap_or_default()
+    };
+    let pattern: Vec<char> = pattern.to_lowercase().chars().collect();
+    let text: Vec<char> = text.to_lowercase().chars().collect();
+    glob_match(&pattern, &text)
+}
+
+fn glob_match(pattern: &[char], text: &[char]) -> bool {
+    match pattern.first() {
+        None => text.is_empty(),
+        Some('*') if pattern.get(1) == Some(&'*') => {
+            let rest = &pattern[2..];
+            if rest.is_empty() {
+                return true;
+            }
+            match rest.strip_prefix(&['/']) {
+                // "**/" matches whole directories, including none at all
+                Some(rest) => (0..=text.len())
+                    .filter(|&i| i == 0 || text[i - 1] == '/')
+                    .any(|i| glob_match(rest, &text[i..])),
+                None => (0..=text.len()).any(|i| glob_match(rest, &text[i..])),
+            }
+        }
+        Some('*') => (0..=text.len())
+            .take_while(|&i| i == 0 || text[i - 1] != '/')
+            .any(|i| glob_match(&pattern[1..], &text[i..])),
+        Some('?') => {
+            text.first().is_some_and(|&c| c != '/') && glob_match(&pattern[1..], &text[1..])
+        }
+        Some('[') => match pattern.iter().skip(2).position(|&c| c == ']') {
+            Some(end) => {
+                let class = &pattern[1..end + 2];
+                text.first()
+                    .is_some_and(|&c| c != '/' && class_matches(class, c))
+                    && glob_match(&pattern[end + 3..], &text[1..])
+            }
+            None => text.first() == Some(&'[') && glob_match(&pattern[1..], &text[1..]),
+        },
+        Some(&c) => text.first() == Some(&c) && glob_match(&pattern[1..], &text[1..]),
+    }
+}
+
+/// Whether `c` is in a `[...]` class, given without the brackets
+fn class_matches(class: &[char], c: char) -> bool {
+    let (negated, class) = match class.first() {
+        Some('!') | Some('^') => (true, &class[1..]),
+        _ => (false, class),
+    };
+    let mut found = false;
+    let mut i = 0;
+    while i < class.len() {
+        if i + 2 < class.len() && class[i + 1] == '-' {
+            found |= class[i] <= c && c <= class[i + 2];
+            i += 3;
+        } else {
+            found |= class[i] == c;
+            i += 1;
+        }
+    }
+    found != negated
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn plan_for(backup_dir: &Path, assets: &[Asset]) -> SyncPlan {
+        let rules = PlanRules {
+            globs: vec!["*".to_string()],
+            ..PlanRules::default()
+        };
+        let mut cache = HashCache::load(&backup_dir.join("cache.json")).unwrap();
+        plan(
+            backup_dir,
+            assets,
+            &rules,
+            &mut cache,
+            &ProgressBar::hidden(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn unreadable_asset_is_kept_with_the_error() {
+        let dir = tempfile::tempdir().unwrap();
+        let readable = Asset::new(dir.path().join("IMG_0001.jpg"));
+        fs::write(&readable.primary, b"jpeg").unwrap();
+        // A directory can't be hashed, like a file without read permission
+        let unreadable = Asset::new(dir.path().join("IMG_0002.jpg"));
+        fs::create_dir(&unreadable.primary).unwrap();
+
+        let plan = plan_for(dir.path(), &[readable, unreadable]);
+
+        assert_eq!(plan.assets[0].action, PlanAction::Trash);
+        assert_eq!(plan.assets[1].action, PlanAction::Keep);
+        assert!(plan.assets[1]
+            .reason
+            .starts_with("IMG_0002.jpg could not be read"));
+        assert!(plan.assets[1].files[0].sha256.is_empty());
+        assert!(check_unchanged(dir.path(), &plan.assets[1]).is_err());
+    }
+
+    #[test]
+    fn load_accepts_another_spelling_of_the_backup_dir() {
+        let dir = tempfile::tempdir().unwrap();
+        let backup_dir = dir.path().join("backup");
+        fs::create_dir(&backup_dir).unwrap();
+        let plan = plan_for(&backup_dir, &[]);
+        let file = dir.path().join("plan.json");
+        save(&plan, &file).unwrap();
+
+        assert!(load(&file, &dir.path().join("backup/")).is_ok());
+        assert!(load(&file, &dir.path().join("backup/../backup")).is_ok());
+        assert!(matches!(
+            load(&file, dir.path()),
+            Err(BackupError::InvalidPlan(_))
+        ));
+    }
+}
@@ -0,0 +1,452 @@
+use crate::copy;
+use crate::BackupError;
+use indicatif::ProgressBar;
+use log::{debug, warn};
+use std::env;
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+/// Mode bit a shared `.Trash` at the top of a volume must have
+#[cfg(unix)]
+const STICKY_BIT: u32 = 0o1000;
+
+/// Which convention a trash follows
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    /// The freedesktop.org Trash specification used by Linux desktops: every
+    /// item goes in `files` with a `.trashinfo` in `info` recording its
+    /// original path and deletion date, and files on other volumes go to a
+    /// trash at the top of that volume
+    Freedesktop,
+    /// `~/.Trash` on macOS
+    MacOs,
+}
+
+/// The current user's trash, where the file manager can show and restore
+/// deleted files
+#[derive(Debug, Clone)]
+pub struct Trash {
+    backend: Backend,
+    home: PathBuf,
+}
+
+/// A file that was moved to the trash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashedFile {
+    pub original: PathBuf,
+    pub trashed: PathBuf,
+    /// The `.trashinfo` file describing it, with the freedesktop backend
+    pub info: Option<PathBuf>,
+}
+
+impl Trash {
+    /// The trash of this platform: `~/.Trash` on macOS, and the freedesktop
+    /// home trash, `$XDG_DATA_HOME/Trash`, everywhere else
+    pub fn for_platform() -> Result<Trash, BackupError> {
+        if cfg!(target_os = "macos") {
+            return Ok(Trash::new(Backend::MacOs, home_dir()?.join(".Trash")));
+        }
+
+        let data_home = match env::var_os("XDG_DATA_HOME").filter(|v| !v.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => home_dir()?.join(".local").join("share"),
+        };
+        Ok(Trash::new(Backend::Freedesktop, data_home.join("Trash")))
+    }
+
+    pub fn new(backend: Backend, home: PathBuf) -> Trash {
+        Trash { backend, home }
+    }
+
+    pub fn backend(&self) -> Backend {
+        self.backend
+    }
+
+    /// The trash directory in the user's home
+    pub fn home(&self) -> &Path {
+        &self.home
+    }
+
+    /// Move a file to the trash under a name nothing else there has. The
+    /// file is renamed when the trash is on the same filesystem and copied
+    /// then deleted otherwise. With `dry_run`, only works out where it would go.
+    pub fn put(&self, file: &Path, dry_run: bool) -> Result<TrashedFile, BackupError> {
+        let file = absolute(file)?;
+        match self.backend {
+            Backend::MacOs => self.put_macos(&file, dry_run),
+            Backend::Freedesktop => self.put_freedesktop(&file, dry_run),
+        }
+    }
+
+    fn put_macos(&self, file: &Path, dry_run: bool) -> Result<TrashedFile, BackupError> {
+        if !dry_run {
+            fs::create_dir_all(&self.home)?;
+        }
+
+        // Finder's style of naming a second "IMG_1.HEIC" is "IMG_1 2.HEIC"
+        let name = file_name(file);
+        let trashed = (1..)
+            .map(|n| self.home.join(numbered(&name, n, " ")))
+            .find(|candidate| fs::symlink_metadata(candidate).is_err())
+            .unwrap_or_default();
+
+        if !dry_run {
+            move_file(file, &trashed)?;
+        }
+        Ok(TrashedFile {
+            original: file.to_path_buf(),
+            trashed,
+            info: None,
+        })
+    }
+
+    fn put_freedesktop(&self, file: &Path, dry_run: bool) -> Result<TrashedFile, BackupError> {
+        let (trash_dir, top_dir) = self.trash_dir_for(file, dry_run)?;
+        let files_dir = trash_dir.join("files");
+        let info_dir = trash_dir.join("info");
+        if !dry_run {
+            create_private_dir(&files_dir)?;
+            create_private_dir(&info_dir)?;
+        }
+
+        // Paths in a volume's trash are relative to the top of the volume
+        let original = match &top_dir {
+            Some(top) => file.strip_prefix(top).unwrap_or(file),
+            None => file,
+        };
+        let info_text = format!(
+            "[Trash Info]\nPath={}\nDeletionDate={}\n",
+            encode_path(original),
+            chrono::Local::now().format("%Y-%m-%dT%H:%M:%S")
+        );
+
+        // Claiming the .trashinfo name first, atomically, reserves the name in files/
+        let name = file_name(file);
+        let mut n = 1;
+        let (trashed, info) = loop {
+            let candidate = numbered(&name, n, ".");
+            n += 1;
+            let trashed = files_dir.join(&candidate);
+            let info = info_dir.join(format!("{}.trashinfo", candidate));
+            if fs::symlink_metadata(&trashed).is_ok() {
+                continue;
+            }
+            if dry_run {
+                if fs::symlink_metadata(&info).is_ok() {
+                    continue;
+                }
+                break (trashed, info);
+            }
+            match OpenOptions::new().write(true).create_new(true).open(&info) {
+                Ok(mut handle) => {
+                    if let Err(e) = handle.write_all(info_text.as_bytes()) {
+                        let _ = fs::remove_file(&info);
+                        return Err(BackupError::IoError(e));
+                    }
+                    break (trashed, info);
+                }
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+                Err(e) => return Err(BackupError::IoError(e)),
+            }
+        };
+
+        if !dry_run {
+            if let Err(e) = move_file(file, &trashed) {
+                let _ = fs::remove_file(&info);
+                return Err(e);
+            }
+        }
+        Ok(TrashedFile {
+            original: file.to_path_buf(),
+            trashed,
+            info: Some(info),
+        })
+    }
+
+    /// The trash a file goes to, and the top of its volume when that isn't
+    /// the home trash: the home trash for files on its filesystem, otherwise
+    /// `$topdir/.Trash/$uid` if the volume has a shared sticky `.Trash`, or
+    /// else `$topdir/.Trash-$uid`. Falls back to the home trash, with a copy,
+    /// if the volume can't have a trash.
+    #[cfg(unix)]
+    fn trash_dir_for(
+        &self,
+        file: &Path,
+        dry_run: bool,
+    ) -> Result<(PathBuf, Option<PathBuf>), BackupError> {
+        use std::os::unix::fs::MetadataExt;
+
+        let device = fs::symlink_metadata(file)?.dev();
+        let home_device = self
+            .home
+            .ancestors()
+            .find_map(|dir| fs::metadata(dir).ok())
+            .map(|metadata| metadata.dev());
+        if home_device == Some(device) {
+            return Ok((self.home.clone(), None));
+        }
+
+        let top_dir = file
+            .ancestors()
+            .skip(1)
+            .take_while(|dir| fs::metadata(dir).is_ok_and(|metadata| metadata.dev() == device))
+            .last()
+            .unwrap_or(Path::new("/"))
+            .to_path_buf();
+        let uid = unsafe { libc::getuid() }.to_string();
+
+        let shared = top_dir.join(".Trash");
+        match fs::symlink_metadata(&shared) {
+            Ok(metadata) if metadata.is_dir() && metadata.mode() & STICKY_BIT != 0 => {
+                return Ok((shared.join(uid), Some(top_dir)));
+            }
+            Ok(_) => warn!(
+                "Ignoring {}: it must be a directory with the sticky bit set and not a symlink",
+                shared.display()
+            ),
+            Err(_) => {}
+        }
+
+        let own = top_dir.join(format!(".Trash-{}", uid));
+        let usable = match fs::symlink_metadata(&own) {
+            Ok(metadata) => metadata.is_dir() && metadata.uid().to_string() == uid,
+            Err(_) if dry_run => true,
+            Err(_) => create_private_dir(&own).is_ok(),
+        };
+        if usable {
+            return Ok((own, Some(top_dir)));
+        }
+
+        warn!(
+            "Can't use a trash on the volume at {}, copying to {} instead",
+            top_dir.display(),
+            self.home.display()
+        );
+        Ok((self.home.clone(), None))
+    }
+
+    #[cfg(not(unix))]
+    fn trash_dir_for(
+        &self,
+        _file: &Path,
+        _dry_run: bool,
+    ) -> Result<(PathBuf, Option<PathBuf>), BackupError> {
+        Ok((self.home.clone(), None))
+    }
+}
+
+/// Move a trashed file back to `original`, which must not exist, and drop
+/// its `.trashinfo`
+pub fn restore(trashed: &Path, info: Option<&Path>, original: &Path) -> Result<(), BackupError> {
+    if fs::symlink_metadata(original).is_ok() {
+        return Err(BackupError::IoError(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{} already exists", original.display()),
+        )));
+    }
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    move_file(trashed, original)?;
+    if let Some(info) = info {
+        if let Err(e) = fs::remove_file(info) {
+            warn!("Could not remove {}: {}", info.display(), e);
+        }
+    }
+    Ok(())
+}
+
+fn home_dir() -> Result<PathBuf, BackupError> {
+    dirs::home_dir().ok_or_else(|| {
+        BackupError::DirectoryNotAccessible("Could not determine home directory".to_string())
+    })
+}
+
+/// The file's path with its directory resolved, without following the file
+/// itself if it is a symlink
+fn absolute(file: &Path) -> Result<PathBuf, BackupError> {
+    let parent = match file.parent() {
+        Some(parent) if !parent.as_os_str().is_empty() => fs::canonicalize(parent)?,
+        _ => env::current_dir()?,
+    };
+    Ok(parent.join(file.file_name().unwrap_or_default()))
+}
+
+fn file_name(file: &Path) -> String {
+    file.file_name()
+        .map(|name| name.to_string_lossy().to_string())
+        .unwrap_or_default()
+}
+
+/// The name itself for `n` 1, otherwise `n` inserted before the extension
+/// after `separator`: `IMG_1.2.HEIC` or `IMG_1 2.HEIC`
+fn numbered(name: &str, n: usize, separator: &str) -> String {
+    if n == 1 {
+        return name.to_string();
+    }
+    match name.rsplit_once('.') {
+        Some((stem, ext)) if !stem.is_empty() => format!("{}{}{}.{}", stem, separator, n, ext),
+        _ => format!("{}{}{}", name, separator, n),
+    }
+}
+
+/// Percent-encode a path for a `.trashinfo` file, keeping `/` and the
+/// characters URLs don't need escaped
+fn encode_path(path: &Path) -> String {
+    #[cfg(unix)]
+    let bytes = std::os::unix::ffi::OsStrExt::as_bytes(path.as_os_str()).to_vec();
+    #[cfg(not(unix))]
+    let bytes = path.to_string_lossy().into_owned().into_bytes();
+
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' | b'/' => {
+                (b as char).to_string()
+            }
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// Create a directory, and its parents, readable only by the user
+fn create_private_dir(dir: &Path) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+    #[cfg(unix)]
+    std::os::unix::fs::DirBuilderExt::mode(&mut builder, 0o700);
+    builder.create(dir)
+}
+
+/// Rename a file, or copy it and delete the original when the destination
+/// is on another filesystem. If the original can't be deleted, the copy is
+/// removed again.
+fn move_file(from: &Path, to: &Path) -> Result<(), BackupError> {
+    match fs::rename(from, to) {
+        Ok(()) => {
+            debug!("Renamed {} to {}", from.display(), to.display());
+            Ok(())
+        }
+        Err(e) if e.kind() == io::ErrorKind::CrossesDevices => {
+            debug!(
+                "Copying {} to {} on another filesystem",
+                from.display(),
+                to.display()
+            );
+            copy::copy_file_atomic(from, to, &ProgressBar::hidden())?;
+            if let Err(e) = fs::remove_file(from) {
+                // Leave the file where it was rather than in two places
+                warn!(
+                    "{} was copied to {} but could not be deleted, removing the copy: {}",
+                    from.display(),
+                    to.display(),
+                    e
+                );
+                if let Err(e) = fs::remove_file(to) {
+                    warn!("Could not remove {}: {}", to.display(), e);
+                }
+                return Err(BackupError::IoError(e));
+            }
+            Ok(())
+        }
+        Err(e) => Err(BackupError::IoError(e)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDateTime;
+
+    fn trash_in(dir: &Path) -> Trash {
+        Trash::new(Backend::Freedesktop, dir.join("Trash"))
+    }
+
+    fn write(path: &Path, content: &str) {
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(path, content).unwrap();
+    }
+
+    #[cfg(not(target_os = "macos"))]
+    #[test]
+    fn home_trash_follows_xdg_data_home() {
+        let _env = crate::tests::ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+        let dir = tempfile::tempdir().unwrap();
+        env::set_var("XDG_DATA_HOME", dir.path());
+        let trash = Trash::for_platform().unwrap();
+        assert_eq!(trash.backend(), Backend::Freedesktop);
+        assert_eq!(trash.home(), dir.path().join("Trash"));
+    }
+
+    #[test]
+    fn same_names_are_numbered() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = trash_in(dir.path());
+        let (first, second) = (
+            dir.path().join("a/IMG_1.HEIC"),
+            dir.path().join("b/IMG_1.HEIC"),
+        );
+        write(&first, "first");
+        write(&second, "second");
+
+        let planned = trash.put(&first, true).unwrap();
+        assert!(first.exists());
+        let first = trash.put(&first, false).unwrap();
+        let second = trash.put(&second, false).unwrap();
+
+        let files = dir.path().join("Trash/files");
+        assert_eq!(planned.trashed, first.trashed);
+        assert_eq!(first.trashed, files.join("IMG_1.HEIC"));
+        assert_eq!(second.trashed, files.join("IMG_1.2.HEIC"));
+        assert_eq!(
+            second.info.unwrap(),
+            dir.path().join("Trash/info/IMG_1.2.HEIC.trashinfo")
+        );
+        assert_eq!(fs::read_to_string(&second.trashed).unwrap(), "second");
+    }
+
+    #[test]
+    fn trashinfo_has_the_encoded_path_and_date() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = trash_in(dir.path());
+        let file = dir.path().join("My Photos/Zoë #1.jpg");
+        write(&file, "jpeg");
+
+        let trashed = trash.put(&file, false).unwrap();
+        let info = fs::read_to_string(trashed.info.unwrap()).unwrap();
+        let lines: Vec<&str> = info.lines().collect();
+
+        let original = fs::canonicalize(dir.path())
+            .unwrap()
+            .join("My Photos/Zoë #1.jpg");
+        assert_eq!(original, trashed.original);
+        assert_eq!(lines[0], "[Trash Info]");
+        assert_eq!(lines[1], format!("Path={}", encode_path(&original)));
+        assert!(lines[1].ends_with("/My%20Photos/Zo%C3%AB%20%231.jpg"));
+        let date = lines[2].strip_prefix("DeletionDate=").unwrap();
+        assert!(NaiveDateTime::parse_from_str(date, "%Y-%m-%dT%H:%M:%S").is_ok());
+    }
+
+    #[test]
+    fn restore_puts_the_file_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let trash = trash_in(dir.path());
+        let file = dir.path().join("photos/IMG_1.HEIC");
+        write(&file, "heic");
+        let trashed = trash.put(&file, false).unwrap();
+        assert!(!file.exists());
+
+        // Nothing is overwritten by a restore
+        write(&file, "new");
+        assert!(restore(&trashed.trashed, trashed.info.as_deref(), &file).is_err());
+        assert!(trashed.trashed.exists());
+        fs::remove_file(&file).unwrap();
+
+        restore(&trashed.trashed, trashed.info.as_deref(), &trashed.original).unwrap();
+        assert_eq!(fs::read_to_string(&file).unwrap(), "heic");
+        assert!(!trashed.trashed.exists());
+        assert!(!trashed.info.unwrap().exists());
+    }
+}
@@ -0,0 +1,988 @@
+use crate::asset::Asset;
+use crate::metadata::{self, MediaMetadata};
+use crate::perceptual::NearMatch;
+use crate::sync_plan;
+use crate::{BackupError, MediaKind};
+use chrono::{DateTime, Local, NaiveDate, NaiveDateTime, Timelike};
+use clap::ValueEnum;
+use ratatui::backend::{Backend, TestBackend};
+use ratatui::buffer::Buffer;
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Modifier, Style};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Cell, HighlightSpacing, Paragraph, Row, Table, TableState, Wrap};
+use ratatui::{Frame, Terminal};
+use rayon::prelude::*;
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// Size of the off-screen terminal a key script is played on
+const SCRIPTED_WIDTH: u16 = 120;
+const SCRIPTED_HEIGHT: u16 = 40;
+
+/// Rows moved by Page Up and Page Down
+const PAGE: usize = 10;
+
+/// What has happened to an asset during the review
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Status {
+    Pending,
+    Kept,
+    Trashed,
+    /// Uploaded, and Immich was seen to have it afterwards
+    Uploaded,
+    Failed(String),
+}
+
+impl Status {
+    /// Whether the asset can still be trashed, kept or uploaded
+    fn is_open(&self) -> bool {
+        !matches!(self, Status::Trashed | Status::Uploaded)
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            Status::Pending => "",
+            Status::Kept => "kept",
+            Status::Trashed => "trashed",
+            Status::Uploaded => "uploaded",
+            Status::Failed(_) => "failed",
+        }
+    }
+
+    fn style(&self) -> Style {
+        match self {
+            Status::Pending => Style::default(),
+            Status::Kept => Style::default().fg(Color::Blue),
+            Status::Trashed => Style::default().fg(Color::Red),
+            Status::Uploaded => Style::default().fg(Color::Green),
+            Status::Failed(_) => Style::default().fg(Color::Yellow),
+        }
+    }
+}
+
+/// Carries out the actions chosen on the review screen
+pub trait SyncActions {
+    fn trash(&mut self, asset: &Asset) -> Result<(), BackupError>;
+    /// Upload an asset and succeed only once Immich has it
+    fn upload(&mut self, asset: &Asset) -> Result<(), BackupError>;
+}
+
+/// Where the review screen reads its keys from
+pub trait KeySource {
+    /// The next key pressed, or None once there are no more
+    fn next_key(&mut self) -> io::Result<Option<KeyEvent>>;
+}
+
+/// Keys pressed at the terminal
+pub struct TerminalKeys;
+
+impl KeySource for TerminalKeys {
+    fn next_key(&mut self) -> io::Result<Option<KeyEvent>> {
+        loop {
+            match event::read()? {
+                Event::Key(key) if key.kind != KeyEventKind::Release => return Ok(Some(key)),
+                // A key that does nothing, so the screen is redrawn at the new size
+                Event::Resize(..) => return Ok(Some(KeyEvent::from(KeyCode::Null))),
+                _ => {}
+            }
+        }
+    }
+}
+
+/// A fixed sequence of keys, to drive the review screen from a script or a test
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ScriptedKeys {
+    keys: VecDeque<KeyEvent>,
+}
+
+impl ScriptedKeys {
+    /// Parse keys written as plain characters, with special keys in angle
+    /// brackets: `<up>`, `<down>`, `<pgup>`, `<pgdn>`, `<home>`, `<end>`,
+    /// `<enter>`, `<esc>`, `<space>`, `<backspace>`, `<tab>`, `<c-c>` and
+    /// `<lt>` for a literal `<`. Whitespace is ignored, so keys can be spaced
+    /// out for reading, e.g. `"<down> <space> t y q"`.
+    pub fn parse(script: &str) -> Result<ScriptedKeys, String> {
+        let mut keys = VecDeque::new();
+        let mut chars = script.chars();
+        while let Some(c) = chars.next() {
+            if c.is_whitespace() {
+                continue;
+            }
+            if c != '<' {
+                keys.push_back(KeyEvent::from(KeyCode::Char(c)));
+                continue;
+            }
+
+            let name: String = chars.by_ref().take_while(|&c| c != '>').collect();
+            let key = match name.to_lowercase().as_str() {
+                "up" => KeyEvent::from(KeyCode::Up),
+                "down" => KeyEvent::from(KeyCode::Down),
+                "pgup" => KeyEvent::from(KeyCode::PageUp),
+                "pgdn" => KeyEvent::from(KeyCode::PageDown),
+                "home" => KeyEvent::from(KeyCode::Home),
+                "end" => KeyEvent::from(KeyCode::End),
+                "enter" => KeyEvent::from(KeyCode::Enter),
+                "esc" => KeyEvent::from(KeyCode::Esc),
+                "space" => KeyEvent::from(KeyCode::Char(' ')),
+                "backspace" => KeyEvent::from(KeyCode::Backspace),
+                "tab" => KeyEvent::from(KeyCode::Tab),
+                "c-c" => KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL),
+                "lt" => KeyEvent::from(KeyCode::Char('<')),
+                _ => return Err(format!("unknown key <{}>", name)),
+            };
+            keys.push_back(key);
+        }
+        Ok(ScriptedKeys { keys })
+    }
+}
+
+impl KeySource for ScriptedKeys {
+    fn next_key(&mut self) -> io::Result<Option<KeyEvent>> {
+        Ok(self.keys.pop_front())
+    }
+}
+
+/// What the review screen knows about an asset, read once up front
+struct Item {
+    /// Path relative to the backup directory
+    name: String,
+    kind: Option<MediaKind>,
+    /// Size of all the asset's files together
+    size: u64,
+    metadata: MediaMetadata,
+    modified: Option<NaiveDateTime>,
+}
+
+impl Item {
+    fn load(asset: &Asset, root: &Path) -> Item {
+        let metadata = asset
+            .media_files()
+            .into_iter()
+            .filter_map(|file| metadata::read_metadata(file).ok())
+            .find(|metadata| metadata.captured_at.is_some())
+            .unwrap_or_default();
+        let modified = fs::metadata(&asset.primary)
+            .and_then(|metadata| metadata.modified())
+            .ok()
+            .and_then(|time| {
+                DateTime::<Local>::from(time)
+                    .naive_local()
+                    .with_nanosecond(0)
+            });
+
+        Item {
+            name: asset
+                .primary
+                .strip_prefix(root)
+                .unwrap_or(&asset.primary)
+                .display()
+                .to_string(),
+            kind: MediaKind::of(&asset.primary),
+            size: asset
+                .files()
+                .iter()
+                .filter_map(|file| fs::metadata(file).ok())
+                .map(|metadata| metadata.len())
+                .sum(),
+            metadata,
+            modified,
+        }
+    }
+
+    /// When the asset was taken, or else last modified
+    fn taken(&self) -> Option<NaiveDateTime> {
+        self.metadata
+            .captured_at
+            .map(|time| time.local)
+            .or(self.modified)
+    }
+}
+
+/// A live filter typed after `/`: `type:photo`, `after:2020-01-01` and
+/// `before:2021-01-01` words, and anything else as a pattern the path must
+/// contain, or match if it has glob characters
+#[derive(Debug, Default)]
+struct Filter {
+    kind: Option<MediaKind>,
+    after: Option<NaiveDate>,
+    before: Option<NaiveDate>,
+    patterns: Vec<String>,
+}
+
+impl Filter {
+    fn parse(text: &str) -> Result<Filter, String> {
+        let date = |value: &str| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .map_err(|_| format!("'{}' is not a YYYY-MM-DD date", value))
+        };
+
+        let mut filter = Filter::default();
+        for word in text.split_whitespace() {
+            match word.split_once(':') {
+                Some(("type", kind)) => {
+                    filter.kind = Some(
+                        MediaKind::from_str(kind, true)
+                            .map_err(|_| format!("'{}' is not photo, video or sidecar", kind))?,
+                    )
+                }
+                Some(("after", value)) => filter.after = Some(date(value)?),
+                Some(("before", value)) => filter.before = Some(date(value)?),
+                _ => filter.patterns.push(word.to_lowercase()),
+            }
+        }
+        Ok(filter)
+    }
+
+    fn matches(&self, item: &Item) -> bool {
+        if self.kind.is_some() && item.kind != self.kind {
+            return false;
+        }
+        if self.after.is_some() || self.before.is_some() {
+            let Some(taken) = item.taken() else {
+                return false;
+            };
+            if self.after.is_some_and(|after| taken.date() < after)
+                || self.before.is_some_and(|before| taken.date() >= before)
+            {
+                return false;
+            }
+        }
+        let name = item.name.to_lowercase();
+        self.patterns.iter().all(|pattern| {
+            if pattern.contains(['*', '?', '[']) {
+                sync_plan::glob_matches(pattern, Path::new(&name))
+            } else {
+                name.contains(pattern.as_str())
+            }
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Mode {
+    Normal,
+    /// Typing a filter, with the text to go back to on Esc
+    Filter {
+        previous: String,
+    },
+    /// Waiting for y to trash these assets
+    ConfirmTrash(Vec<usize>),
+}
+
+/// Work the screen hands to `SyncActions` after showing that it started
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Job {
+    Trash(Vec<usize>),
+    Upload(Vec<usize>),
+}
+
+/// State of the review screen
+struct App<'a> {
+    assets: &'a [Asset],
+    near_matches: &'a HashMap<PathBuf, NearMatch>,
+    items: Vec<Item>,
+    status: Vec<Status>,
+    /// Indices of the assets the filter lets through, in order
+    visible: Vec<usize>,
+    /// Position of the highlighted row in `visible`
+    cursor: usize,
+    selected: BTreeSet<usize>,
+    filter_text: String,
+    filter: Filter,
+    mode: Mode,
+    message: String,
+    table: TableState,
+    dry_run: bool,
+    quit: bool,
+}
+
+impl<'a> App<'a> {
+    fn new(
+        root: &Path,
+        assets: &'a [Asset],
+        near_matches: &'a HashMap<PathBuf, NearMatch>,
+        dry_run: bool,
+    ) -> App<'a> {
+        let items = assets
+            .par_iter()
+            .map(|asset| Item::load(asset, root))
+            .collect();
+        App {
+            assets,
+            near_matches,
+            items,
+            status: vec![Status::Pending; assets.len()],
+            visible: (0..assets.len()).collect(),
+            cursor: 0,
+            selected: BTreeSet::new(),
+            filter_text: String::new(),
+            filter: Filter::default(),
+            mode: Mode::Normal,
+            message: String::new(),
+            table: TableState::default(),
+            dry_run,
+            quit: false,
+        }
+    }
+
+    fn current(&self) -> Option<usize> {
+        self.visible.get(self.cursor).copied()
+    }
+
+    /// The selected assets, or the highlighted one if none are selected,
+    /// leaving out those already trashed or uploaded
+    fn targets(&self) -> Vec<usize> {
+        let targets: Vec<usize> = if self.selected.is_empty() {
+            self.current().into_iter().collect()
+        } else {
+            self.selected.iter().copied().collect()
+        };
+        targets
+            .into_iter()
+            .filter(|&index| self.status[index].is_open())
+            .collect()
+    }
+
+    fn move_cursor(&mut self, to: usize) {
+        self.cursor = to.min(self.visible.len().saturating_sub(1));
+    }
+
+    /// Apply the filter text, keeping the highlighted asset if it still shows
+    /// and dropping selected assets that no longer do
+    fn refilter(&mut self) -> Result<(), String> {
+        self.filter = Filter::parse(&self.filter_text)?;
+        let current = self.current();
+        self.visible = (0..self.items.len())
+            .filter(|&index| self.filter.matches(&self.items[index]))
+            .collect();
+        let visible: BTreeSet<usize> = self.visible.iter().copied().collect();
+        self.selected.retain(|index| visible.contains(index));
+        self.cursor = current
+            .and_then(|current| self.visible.iter().position(|&index| index == current))
+            .unwrap_or(0);
+        Ok(())
+    }
+
+    /// React to a key, returning any trash or upload work to carry out
+    fn handle_key(&mut self, key: KeyEvent) -> Option<Job> {
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit = true;
+            return None;
+        }
+        match self.mode.clone() {
+            Mode::Normal => self.handle_normal_key(key),
+            Mode::Filter { previous } => {
+                self.handle_filter_key(key, previous);
+                None
+            }
+            Mode::ConfirmTrash(targets) => {
+                self.mode = Mode::Normal;
+                if matches!(key.code, KeyCode::Char('y') | KeyCode::Char('Y')) {
+                    Some(Job::Trash(targets))
+                } else {
+                    self.message = "Nothing was moved to the trash".to_string();
+                    None
+                }
+            }
+        }
+    }
+
+    fn handle_normal_key(&mut self, key: KeyEvent) -> Option<Job> {
+        if key.code != KeyCode::Null {
+            self.message.clear();
+        }
+        match key.code {
+            KeyCode::Up => self.move_cursor(self.cursor.saturating_sub(1)),
+            KeyCode::Down => self.move_cursor(self.cursor + 1),
+            KeyCode::PageUp => self.move_cursor(self.cursor.saturating_sub(PAGE)),
+            KeyCode::PageDown => self.move_cursor(self.cursor + PAGE),
+            KeyCode::Home => self.move_cursor(0),
+            KeyCode::End => self.move_cursor(usize::MAX),
+            KeyCode::Char(' ') => {
+                if let Some(index) = self.current() {
+                    if !self.selected.remove(&index) && self.status[index].is_open() {
+                        self.selected.insert(index);
+                    }
+                    self.move_cursor(self.cursor + 1);
+                }
+            }
+            KeyCode::Char('a') => {
+                let open: BTreeSet<usize> = self
+                    .visible
+                    .iter()
+                    .copied()
+                    .filter(|&index| self.status[index].is_open())
+                    .collect();
+                if !open.is_empty() && self.selected == open {
+                    self.selected.clear();
+                } else {
+                    self.selected = open;
+                }
+            }
+            KeyCode::Char('t') => {
+                let targets = self.targets();
+                if targets.is_empty() {
+                    self.message = "Nothing to move to the trash".to_string();
+                } else {
+                    self.message = format!("Move {} assets to the trash? [y/N]", targets.len());
+                    self.mode = Mode::ConfirmTrash(targets);
+                }
+            }
+            KeyCode::Char('k') => {
+                let targets = self.targets();
+                for &index in &targets {
+                    self.status[index] = Status::Kept;
+                    self.selected.remove(&index);
+                }
+                self.message = format!("Keeping {} assets in the backup", targets.len());
+            }
+            KeyCode::Char('u') => {
+                let targets = self.targets();
+                if targets.is_empty() {
+                    self.message = "Nothing to upload".to_string();
+                } else {
+                    return Some(Job::Upload(targets));
+                }
+            }
+            KeyCode::Char('o') => {
+                if let Some(index) = self.current() {
+                    self.open(&self.assets[index].primary.clone());
+                }
+            }
+            KeyCode::Char('d') => {
+                let parent = self
+                    .current()
+                    .and_then(|index| self.assets[index].primary.parent().map(Path::to_path_buf));
+                if let Some(parent) = parent {
+                    self.open(&parent);
+                }
+            }
+            KeyCode::Char('/') => {
+                self.mode = Mode::Filter {
+                    previous: self.filter_text.clone(),
+                };
+            }
+            KeyCode::Esc => {
+                if !self.selected.is_empty() {
+                    self.selected.clear();
+                } else if !self.filter_text.is_empty() {
+                    self.filter_text.clear();
+                    let _ = self.refilter();
+                }
+            }
+            KeyCode::Char('q') => self.quit = true,
+            _ => {}
+        }
+        None
+    }
+
+    fn handle_filter_key(&mut self, key: KeyEvent, previous: String) {
+        match key.code {
+            KeyCode::Enter => {
+                self.mode = Mode::Normal;
+                return;
+            }
+            KeyCode::Esc => {
+                self.mode = Mode::Normal;
+                self.filter_text = previous;
+            }
+            KeyCode::Backspace => {
+                self.filter_text.pop();
+            }
+            KeyCode::Char(c) => self.filter_text.push(c),
+            _ => return,
+        }
+        // Filter as the user types, keeping the last working filter while
+        // the text doesn't parse
+        match self.refilter() {
+            Ok(()) => self.message.clear(),
+            Err(e) => self.message = e,
+        }
+    }
+
+    /// Open a file or directory with the default application
+    fn open(&mut self, path: &Path) {
+        let opened = Command::new("open")
+            .arg(path)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn();
+        self.message = match opened {
+            Ok(_) => format!("Opened {}", path.display()),
+            Err(e) => format!("Could not open {}: {}", path.display(), e),
+        };
+    }
+
+    fn describe(&self, job: &Job) -> String {
+        match job {
+            Job::Trash(targets) => format!("Moving {} assets to the trash...", targets.len()),
+            Job::Upload(targets) => format!("Uploading {} assets to Immich...", targets.len()),
+        }
+    }
+
+    fn run_job(&mut self, job: Job, actions: &mut dyn SyncActions) {
+        let (targets, verb) = match &job {
+            Job::Trash(targets) => (targets, "Moved to the trash"),
+            Job::Upload(targets) => (targets, "Uploaded"),
+        };
+        let mut failed = 0;
+        for &index in targets {
+            let asset = &self.assets[index];
+            let (result, done) = match job {
+                Job::Trash(_) => (actions.trash(asset), Status::Trashed),
+                Job::Upload(_) => (actions.upload(asset), Status::Uploaded),
+            };
+            self.status[index] = match result {
+                Ok(()) => done,
+                Err(e) => {
+                    failed += 1;
+                    Status::Failed(e.to_string())
+                }
+            };
+            self.selected.remove(&index);
+        }
+        self.message = format!("{} {} assets", verb, targets.len() - failed);
+        if failed > 0 {
+            self.message += &format!(", {} failed", failed);
+        }
+    }
+
+    fn count(&self, matches: impl Fn(&Status) -> bool) -> usize {
+        self.status.iter().filter(|status| matches(status)).count()
+    }
+
+    fn draw(&mut self, frame: &mut Frame) {
+        let [header, body, status_bar, footer] = Layout::vertical([
+            Constraint::Length(1),
+            Constraint::Min(3),
+            Constraint::Length(1),
+            Constraint::Length(1),
+        ])
+        .areas(frame.area());
+        let [list, panel] =
+            Layout::horizontal([Constraint::Percentage(62), Constraint::Percentage(38)])
+                .areas(body);
+
+        let mut title = format!(
+            "{} assets are in the backup but not in Immich",
+            self.assets.len()
+        );
+        if self.dry_run {
+            title += " [dry run: nothing will be changed]";
+        }
+        frame.render_widget(
+            Paragraph::new(title).style(Style::default().add_modifier(Modifier::BOLD)),
+            header,
+        );
+
+        let rows: Vec<Row> = self
+            .visible
+            .iter()
+            .map(|&index| {
+                let item = &self.items[index];
+                let status = &self.status[index];
+                let mark = if self.selected.contains(&index) {
+                    "[x]"
+                } else {
+                    "[ ]"
+                };
+                let similarity = self
+                    .near_matches
+                    .get(&self.assets[index].primary)
+                    .map(|near| format!("{:.0}%", near.similarity * 100.0))
+                    .unwrap_or_default();
+                Row::new(vec![
+                    Cell::from(mark),
+                    Cell::from(status.label()).style(status.style()),
+                    Cell::from(item.name.clone()),
+                    Cell::from(item.kind.map(|kind| kind.to_string()).unwrap_or_default()),
+                    Cell::from(
+                        item.taken()
+                            .map(|taken| taken.format("%Y-%m-%d").to_string())
+                            .unwrap_or_default(),
+                    ),
+                    Cell::from(short_size(item.size)),
+                    Cell::from(similarity),
+                ])
+            })
+            .collect();
+        let table = Table::new(
+            rows,
+            [
+                Constraint::Length(3),
+                Constraint::Length(8),
+                Constraint::Min(20),
+                Constraint::Length(7),
+                Constraint::Length(10),
+                Constraint::Length(9),
+                Constraint::Length(4),
+            ],
+        )
+        .header(
+            Row::new(vec!["", "Status", "File", "Type", "Taken", "Size", "Near"])
+                .style(Style::default().add_modifier(Modifier::BOLD)),
+        )
+        .block(Block::bordered().title(format!(" Assets ({} shown) ", self.visible.len())))
+        .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+        .highlight_symbol("> ")
+        .highlight_spacing(HighlightSpacing::Always);
+        self.table
+            .select((!self.visible.is_empty()).then_some(self.cursor));
+        frame.render_stateful_widget(table, list, &mut self.table);
+
+        frame.render_widget(
+            Paragraph::new(self.details())
+                .block(Block::bordered().title(" Details "))
+                .wrap(Wrap { trim: false }),
+            panel,
+        );
+
+        let counts = format!(
+            " {} pending | {} selected | {} trashed | {} uploaded | {} kept | {} failed",
+            self.count(|status| *status == Status::Pending),
+            self.selected.len(),
+            self.count(|status| *status == Status::Trashed),
+            self.count(|status| *status == Status::Uploaded),
+            self.count(|status| *status == Status::Kept),
+            self.count(|status| matches!(status, Status::Failed(_))),
+        );
+        let counts = match self.filter_text.is_empty() {
+            true => counts,
+            false => format!("{} | filter: {}", counts, self.filter_text),
+        };
+        frame.render_widget(
+            Paragraph::new(counts).style(Style::default().add_modifier(Modifier::REVERSED)),
+            status_bar,
+        );
+
+        let footer_text = match &self.mode {
+            Mode::Filter { .. } if self.message.is_empty() => format!(
+                "/{}_   type:photo|video after:YYYY-MM-DD before:YYYY-MM-DD or a pattern; Enter keeps it, Esc cancels",
+                self.filter_text
+            ),
+            Mode::Filter { .. } => format!("/{}_   {}", self.filter_text, self.message),
+            _ if !self.message.is_empty() => self.message.clone(),
+            _ => "Up/Down move  Space select  a all  t trash  k keep  u upload  o open  d folder  / filter  Esc clear  q quit"
+                .to_string(),
+        };
+        frame.render_widget(Paragraph::new(footer_text), footer);
+    }
+
+    /// The side panel for the highlighted asset
+    fn details(&self) -> Vec<Line<'static>> {
+        let Some(index) = self.current() else {
+            return vec![Line::from("No assets match the filter")];
+        };
+        let asset = &self.assets[index];
+        let item = &self.items[index];
+
+        let mut lines = vec![
+            Line::from(asset.primary.display().to_string()),
+            Line::from(""),
+        ];
+        for other in asset.files().into_iter().skip(1) {
+            lines.push(Line::from(format!("Also: {}", other.display())));
+        }
+        lines.push(Line::from(format!(
+            "Type: {}",
+            item.kind
+                .map(|kind| kind.to_string())
+                .unwrap_or_else(|| "unknown".to_string())
+        )));
+        lines.push(Line::from(format!("Size: {} bytes", item.size)));
+        lines.push(Line::from(match item.metadata.captured_at {
+            Some(time) => format!("Taken: {}", time),
+            None => "Taken: unknown".to_string(),
+        }));
+        if let Some(camera) = item.metadata.camera() {
+            lines.push(Line::from(format!("Camera: {}", camera)));
+        }
+        if let Some(gps) = item.metadata.gps {
+            lines.push(Line::from(format!("Location: {}", gps)));
+        }
+        if let Some(modified) = item.modified {
+            lines.push(Line::from(format!("Modified: {}", modified)));
+        }
+        if let Some(near) = self.near_matches.get(&asset.primary) {
+            lines.push(Line::from(""));
+            lines.push(Line::from(format!(
+                "Looks like {} in Immich ({:.0}% similar)",
+                near.immich_file.display(),
+                near.similarity * 100.0
+            )));
+        }
+        if let Status::Failed(e) = &self.status[index] {
+            lines.push(Line::from(""));
+            lines.push(
+                Line::from(format!("Failed: {}", e)).style(Status::Failed(String::new()).style()),
+            );
+        }
+        lines
+    }
+}
+
+/// Show the assets on `terminal` and let the user trash, keep or upload them
+/// with `keys` until they quit or the keys run out. Returns what happened to
+/// each asset, in the order given.
+pub fn review<B: Backend>(
+    terminal: &mut Terminal<B>,
+    root: &Path,
+    assets: &[Asset],
+    near_matches: &HashMap<PathBuf, NearMatch>,
+    keys: &mut dyn KeySource,
+    actions: &mut dyn SyncActions,
+    dry_run: bool,
+) -> Result<Vec<Status>, BackupError> {
+    let mut app = App::new(root, assets, near_matches, dry_run);
+    loop {
+        terminal.draw(|frame| app.draw(frame))?;
+        if app.quit {
+            break;
+        }
+        let Some(key) = keys.next_key()? else {
+            break;
+        };
+        if let Some(job) = app.handle_key(key) {
+            app.message = app.describe(&job);
+            terminal.draw(|frame| app.draw(frame))?;
+            app.run_job(job, actions);
+        }
+    }
+    Ok(app.status)
+}
+
+/// Run the review full-screen on the user's terminal
+pub fn review_on_terminal(
+    root: &Path,
+    assets: &[Asset],
+    near_matches: &HashMap<PathBuf, NearMatch>,
+    actions: &mut dyn SyncActions,
+    dry_run: bool,
+) -> Result<Vec<Status>, BackupError> {
+    let mut terminal = ratatui::try_init()?;
+    let result = review(
+        &mut terminal,
+        root,
+        assets,
+        near_matches,
+        &mut TerminalKeys,
+        actions,
+        dry_run,
+    );
+    ratatui::try_restore()?;
+    result
+}
+
+/// Play a key script on an off-screen terminal and return what happened to
+/// each asset along with the text of the final screen
+pub fn review_scripted(
+    root: &Path,
+    assets: &[Asset],
+    near_matches: &HashMap<PathBuf, NearMatch>,
+    mut keys: ScriptedKeys,
+    actions: &mut dyn SyncActions,
+    dry_run: bool,
+) -> Result<(Vec<Status>, String), BackupError> {
+    let mut terminal = Terminal::new(TestBackend::new(SCRIPTED_WIDTH, SCRIPTED_HEIGHT))?;
+    let status = review(
+        &mut terminal,
+        root,
+        assets,
+        near_matches,
+        &mut keys,
+        actions,
+        dry_run,
+    )?;
+    Ok((status, screen_text(terminal.backend().buffer())))
+}
+
+/// The characters of a drawn screen, one line per row
+pub fn screen_text(buffer: &Buffer) -> String {
+    let area = buffer.area;
+    let mut text = String::new();
+    for y in area.top()..area.bottom() {
+        let line: String = (area.left()..area.right())
+            .map(|x| buffer[(x, y)].symbol())
+            .collect();
+        text.push_str(line.trim_end());
+        text.push('\n');
+    }
+    text
+}
+
+/// A size in the units `sync plan` accepts, e.g. `2.1 M`
+fn short_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "K", "M", "G", "T"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Records what the screen asked for, failing for the listed files
+    #[derive(Default)]
+    struct FakeActions {
+        trashed: Vec<PathBuf>,
+        uploaded: Vec<PathBuf>,
+        failing: Vec<PathBuf>,
+    }
+
+    impl FakeActions {
+        fn fail(&self, asset: &Asset) -> Result<(), BackupError> {
+            if self.failing.contains(&asset.primary) {
+                return Err(BackupError::IoError(io::Error::other("disk on fire")));
+            }
+            Ok(())
+        }
+    }
+
+    impl SyncActions for FakeActions {
+        fn trash(&mut self, asset: &Asset) -> Result<(), BackupError> {
+            self.fail(asset)?;
+            self.trashed.push(asset.primary.clone());
+            Ok(())
+        }
+
+        fn upload(&mut self, asset: &Asset) -> Result<(), BackupError> {
+            self.fail(asset)?;
+            self.uploaded.push(asset.primary.clone());
+            Ok(())
+        }
+    }
+
+    fn root() -> PathBuf {
+        PathBuf::from("/backup")
+    }
+
+    fn assets() -> Vec<Asset> {
+        ["IMG_1.jpg", "IMG_2.mov", "Screenshots/S1.png", "VID_3.mov"]
+            .iter()
+            .map(|name| Asset::new(root().join(name)))
+            .collect()
+    }
+
+    fn play(script: &str, actions: &mut FakeActions) -> (Vec<Status>, String) {
+        let keys = ScriptedKeys::parse(script).unwrap();
+        review_scripted(&root(), &assets(), &HashMap::new(), keys, actions, false).unwrap()
+    }
+
+    #[test]
+    fn trash_waits_for_confirmation() {
+        let mut actions = FakeActions::default();
+        let (status, screen) = play("<space><space> t n", &mut actions);
+        assert!(actions.trashed.is_empty());
+        assert_eq!(status, vec![Status::Pending; 4]);
+        assert!(screen.contains("Nothing was moved to the trash"));
+
+        let (status, screen) = play("<space><space> t y q", &mut actions);
+        assert_eq!(
+            actions.trashed,
+            vec![root().join("IMG_1.jpg"), root().join("IMG_2.mov")]
+        );
+        assert_eq!(
+            status,
+            vec![
+                Status::Trashed,
+                Status::Trashed,
+                Status::Pending,
+                Status::Pending
+            ]
+        );
+        assert!(
+            screen.contains(" 2 pending | 0 selected | 2 trashed | 0 uploaded | 0 kept | 0 failed")
+        );
+    }
+
+    #[test]
+    fn keep_and_upload_the_highlighted_asset() {
+        let mut actions = FakeActions::default();
+        let (status, screen) = play("k <down> u <end> t y q", &mut actions);
+        assert_eq!(actions.uploaded, vec![root().join("IMG_2.mov")]);
+        assert_eq!(actions.trashed, vec![root().join("VID_3.mov")]);
+        assert_eq!(
+            status,
+            vec![
+                Status::Kept,
+                Status::Uploaded,
+                Status::Pending,
+                Status::Trashed
+            ]
+        );
+        assert!(
+            screen.contains(" 1 pending | 0 selected | 1 trashed | 1 uploaded | 1 kept | 0 failed")
+        );
+    }
+
+    #[test]
+    fn filter_limits_select_all() {
+        let mut actions = FakeActions::default();
+        let (status, screen) = play("/type:video<enter> a t y", &mut actions);
+        assert_eq!(
+            actions.trashed,
+            vec![root().join("IMG_2.mov"), root().join("VID_3.mov")]
+        );
+        assert_eq!(
+            status,
+            vec![
+                Status::Pending,
+                Status::Trashed,
+                Status::Pending,
+                Status::Trashed
+            ]
+        );
+        assert!(screen.contains("Assets (2 shown)"));
+        assert!(screen.contains("filter: type:video"));
+
+        // A glob without a / matches the file name; Esc brings everything back
+        let (status, screen) = play("/*.png<enter> a u <esc><esc>", &mut actions);
+        assert_eq!(actions.uploaded, vec![root().join("Screenshots/S1.png")]);
+        assert_eq!(status[2], Status::Uploaded);
+        assert!(screen.contains("Assets (4 shown)"));
+    }
+
+    #[test]
+    fn failed_assets_stay_open() {
+        let mut actions = FakeActions {
+            failing: vec![root().join("IMG_2.mov")],
+            ..FakeActions::default()
+        };
+        let (status, screen) = play("a t y", &mut actions);
+        assert_eq!(
+            status[1],
+            Status::Failed("I/O error: disk on fire".to_string())
+        );
+        assert_eq!(status.iter().filter(|s| **s == Status::Trashed).count(), 3);
+        assert!(screen.contains("Moved to the trash 3 assets, 1 failed"));
+
+        // Only the failed asset is left to select, and it can be kept
+        let (status, _) = play("a t y a k", &mut actions);
+        assert_eq!(status[1], Status::Kept);
+    }
+
+    #[test]
+    fn quit_ignores_the_keys_after_it() {
+        let mut actions = FakeActions::default();
+        let (status, _) = play("q t y", &mut actions);
+        assert_eq!(status, vec![Status::Pending; 4]);
+        let (status, _) = play("<c-c> k", &mut actions);
+        assert_eq!(status, vec![Status::Pending; 4]);
+        assert!(actions.trashed.is_empty());
+    }
+}